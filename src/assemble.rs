@@ -0,0 +1,249 @@
+// Building textures with several images out of textures with one each.
+
+use crate::compress::decompress;
+use crate::mipmap::{full_level_count, generate_mips, MipOptions};
+use crate::{Dimensions, RuxResult, Texture};
+
+clap::arg_enum! {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Assembly {
+    // one layer per input
+    Array,
+    // one face per input, six of them
+    Cubemap,
+    // six faces per layer, from six inputs each
+    CubeArray,
+    // one depth slice per input, from the base levels only; a volume's mips shrink in
+    // depth too, so they're generated afresh rather than stacked
+    Volume,
+}
+}
+
+pub const FACE_NAMES: [&str; 6] = ["+X", "-X", "+Y", "-Y", "+Z", "-Z"];
+
+// Parses the faces that each group of six inputs holds, in order, e.g. "+X,-X,+Y,-Y,+Z,-Z".
+pub fn parse_face_order(order: &str) -> RuxResult<[usize; 6]> {
+    let mut faces = [0; 6];
+    let names: Vec<&str> = order.split(',').map(str::trim).collect();
+    if names.len() != 6 {
+        bail!(format!("Expected six faces, found {}.", names.len()));
+    }
+    for (face, name) in faces.iter_mut().zip(&names) {
+        *face = match FACE_NAMES.iter().position(|n| n.eq_ignore_ascii_case(name)) {
+            Some(face) => face,
+            None => bail!(format!(
+                "Unknown face {}; expected one of {:?}.",
+                name, FACE_NAMES
+            )),
+        };
+    }
+    for (face, name) in FACE_NAMES.iter().enumerate() {
+        if !faces.contains(&face) {
+            bail!(format!("Face {} is missing from {}.", name, order));
+        }
+    }
+    Ok(faces)
+}
+
+// Combines single-image textures into an array, cubemap or cube array. The inputs must
+// share format, dimensions and mip count; for cubes, face_order gives the face each input
+// of a group of six holds.
+pub fn assemble(
+    inputs: &[Texture],
+    assembly: Assembly,
+    face_order: [usize; 6],
+) -> RuxResult<Texture> {
+    let first = match inputs.first() {
+        Some(first) => first,
+        None => bail!("Nothing to assemble."),
+    };
+    for (i, input) in inputs.iter().enumerate() {
+        if input.image_count() != 1 {
+            bail!(format!(
+                "Input {} already has {} images; only single images assemble.",
+                i,
+                input.image_count()
+            ));
+        }
+        if input.format != first.format {
+            bail!(format!(
+                "Input {} is {}, but input 0 is {}.",
+                i, input.format, first.format
+            ));
+        }
+        if input.pixel_dim != first.pixel_dim {
+            bail!(format!(
+                "Input {} is {:?}, but input 0 is {:?}.",
+                i, input.pixel_dim, first.pixel_dim
+            ));
+        }
+        if input.mip_blobs.len() != first.mip_blobs.len() {
+            bail!(format!(
+                "Input {} has {} mip levels, but input 0 has {}.",
+                i,
+                input.mip_blobs.len(),
+                first.mip_blobs.len()
+            ));
+        }
+    }
+
+    if assembly == Assembly::Volume {
+        if first.pixel_dim.depth() != 1 {
+            bail!(format!("Slices must be 2D, not {:?}.", first.pixel_dim));
+        }
+        let dim = first.pixel_dim;
+        let volume = Texture {
+            format: first.format,
+            pixel_dim: Dimensions::new(dim.0, dim.1, inputs.len() as u32),
+            array_size: 0,
+            face_count: first.face_count,
+            mip_blobs: vec![inputs
+                .iter()
+                .flat_map(|input| input.mip_blobs[0].iter().copied())
+                .collect()],
+        };
+        if first.mip_blobs.len() < 2 {
+            return Ok(volume);
+        }
+        // as many levels as the slices had, as far as the volume goes
+        let levels = first
+            .mip_blobs
+            .len()
+            .min(full_level_count(volume.pixel_dim));
+        let options = MipOptions {
+            levels: Some(levels),
+            ..MipOptions::default()
+        };
+        return generate_mips(&volume, &options);
+    }
+
+    let (array_size, face_count) = match assembly {
+        Assembly::Volume => unreachable!(),
+        Assembly::Array => (inputs.len() as u32, 1),
+        Assembly::Cubemap | Assembly::CubeArray => {
+            let dim = first.pixel_dim;
+            if dim.width() != dim.height() || dim.depth() != 1 {
+                bail!(format!("Cube faces must be square and 2D, not {:?}.", dim));
+            }
+            if assembly == Assembly::Cubemap && inputs.len() != 6 {
+                bail!(format!("A cubemap takes six faces, not {}.", inputs.len()));
+            }
+            let layers = inputs.len() as u32 / 6;
+            if layers == 0 || layers as usize * 6 != inputs.len() {
+                bail!(format!(
+                    "A cube array takes six faces per layer, not {}.",
+                    inputs.len()
+                ));
+            }
+            let array_size = if assembly == Assembly::Cubemap {
+                0
+            } else {
+                layers
+            };
+            (array_size, 6)
+        }
+    };
+
+    // which input holds each image, in container order
+    let mut order = vec![];
+    if face_count == 6 {
+        for layer in 0..inputs.len() / 6 {
+            for face in 0..6 {
+                let position = face_order.iter().position(|&f| f == face).unwrap();
+                order.push(layer * 6 + position);
+            }
+        }
+    } else {
+        order.extend(0..inputs.len());
+    }
+
+    let mip_blobs = (0..first.mip_blobs.len())
+        .map(|level| {
+            order
+                .iter()
+                .flat_map(|&i| inputs[i].mip_blobs[level].iter().copied())
+                .collect()
+        })
+        .collect();
+
+    Ok(Texture {
+        format: first.format,
+        pixel_dim: first.pixel_dim,
+        array_size,
+        face_count,
+        mip_blobs,
+    })
+}
+
+// Takes depth slice z of the base level, from every image of the texture, as a 2D texture.
+pub fn extract_slice(texture: &Texture, z: u32) -> RuxResult<Texture> {
+    let dim = texture.pixel_dim;
+    if z >= dim.depth() {
+        bail!(format!("No slice {} in a texture {} deep.", z, dim.depth()));
+    }
+    let base = match texture.mip_blobs.first() {
+        Some(base) => base,
+        None => bail!("Texture has no base level to take slices from."),
+    };
+    // compressed or not, every slice of a level takes the same number of bytes
+    let slice_count = (texture.image_count() * dim.depth()) as usize;
+    let slice_size = base.len() / slice_count;
+    let blob = (0..texture.image_count())
+        .flat_map(|image| {
+            let start = ((image * dim.depth() + z) as usize) * slice_size;
+            base[start..start + slice_size].iter().copied()
+        })
+        .collect();
+    Ok(Texture {
+        pixel_dim: Dimensions::new(dim.0, dim.1, 0),
+        mip_blobs: vec![blob],
+        ..*texture
+    })
+}
+
+// Takes one image out of a texture: a mip level of one face of one layer, and either all
+// of its depth or a single slice, as a texture of its own. Compressed data is decompressed.
+pub fn extract(
+    texture: &Texture,
+    level: usize,
+    layer: u32,
+    face: u32,
+    slice: Option<u32>,
+) -> RuxResult<Texture> {
+    let (layers, faces) = (texture.array_size.max(1), texture.face_count.max(1));
+    let blob = match texture.mip_blobs.get(level) {
+        Some(blob) => blob,
+        None => bail!(format!(
+            "No mip level {} among {}.",
+            level,
+            texture.mip_blobs.len()
+        )),
+    };
+    if layer >= layers {
+        bail!(format!("No layer {} among {}.", layer, layers));
+    }
+    if face >= faces {
+        bail!(format!("No face {} among {}.", face, faces));
+    }
+    let dim = texture.mip_dim(level);
+    let depth = dim.depth();
+    let (first, count, pixel_dim) = match slice {
+        Some(z) if z >= depth => bail!(format!("No slice {} in a level {} deep.", z, depth)),
+        Some(z) => (z, 1, Dimensions::new(dim.0, dim.1, 0)),
+        None => (0, depth, dim),
+    };
+    let slice_size = blob.len() / (texture.image_count() * depth) as usize;
+    let start = ((layer * faces + face) * depth + first) as usize * slice_size;
+    let image = Texture {
+        format: texture.format,
+        pixel_dim,
+        array_size: 0,
+        face_count: 1,
+        mip_blobs: vec![blob[start..start + count as usize * slice_size].to_vec()],
+    };
+    if image.format.is_compressed() {
+        decompress(&image)
+    } else {
+        Ok(image)
+    }
+}
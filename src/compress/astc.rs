@@ -0,0 +1,1150 @@
+use std::collections::HashMap;
+
+use crate::compress::bc4::unit_xy;
+use crate::compress::ise::{self, RANGES};
+use crate::compress::{
+    principal_axis, BlockDecoder, BlockEncoder, CompressOptions, Quality, Texel,
+};
+use crate::pixel::float::f16_to_f32;
+use crate::pixel::{CompContent, PixelFormat};
+
+// The LDR colour endpoint modes we encode, all of which store both endpoints directly.
+// Blocks are single-partition; the block size alone sets the bit rate.
+const CEM_LUMINANCE: u32 = 0;
+const CEM_LUMINANCE_ALPHA: u32 = 4;
+const CEM_RGB: u32 = 8;
+const CEM_RGBA: u32 = 12;
+
+// bits ahead of the colour endpoints in a single-partition block
+const HEADER_BITS: u32 = 17;
+
+// the decoder rejects blocks whose endpoints don't fit in at least six levels
+const MIN_COLOUR_RANGE: usize = 4;
+
+// how much of a block's extent, squared, we expect to lose to a weight grid sparser
+// than the texels it covers
+const DECIMATION_LOSS: f32 = 0.02;
+
+// Rec. 601 luma weights for colour, with alpha weighted like luma
+const PERCEPTUAL_WEIGHTS: [f32; 4] = [0.299, 0.587, 0.114, 1.0];
+const UNIFORM_WEIGHTS: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+// normal maps keep X in the colour channels and Y in alpha, as the .rrrg swizzle
+const NORMAL_WEIGHTS: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+#[derive(Copy, Clone, Debug)]
+struct BlockMode {
+    bits: u32,
+    grid_w: usize,
+    grid_h: usize,
+    dual_plane: bool,
+    // index into ise::RANGES
+    weight_range: usize,
+    weight_bits: u32,
+}
+
+lazy_static! {
+    static ref BLOCK_MODES: Vec<BlockMode> = (0..2048).filter_map(decode_block_mode).collect();
+    // per range from MIN_COLOUR_RANGE on, the nearest encoding of each value in [0, 255]
+    // and the value of each encoding
+    static ref COLOUR_TABLES: Vec<(Vec<u32>, Vec<u32>)> = RANGES[MIN_COLOUR_RANGE..]
+        .iter()
+        .map(|&range| {
+            (
+                ise::quantisation_table(range, 255, ise::unquantise_colour),
+                (0..range.levels())
+                    .map(|v| ise::unquantise_colour(v, range))
+                    .collect(),
+            )
+        })
+        .collect();
+    // the same for weights, in [0, 64]
+    static ref WEIGHT_TABLES: Vec<(Vec<u32>, Vec<u32>)> = RANGES[..12]
+        .iter()
+        .map(|&range| {
+            (
+                ise::quantisation_table(range, 64, ise::unquantise_weight),
+                (0..range.levels())
+                    .map(|v| ise::unquantise_weight(v, range))
+                    .collect(),
+            )
+        })
+        .collect();
+}
+
+// The weight grid and range that the 11 block mode bits describe, if they're valid for
+// a 2D block.
+fn decode_block_mode(bits: u32) -> Option<BlockMode> {
+    let field = |high: u32, low: u32| (bits >> low) & ((1 << (high - low + 1)) - 1);
+    let mut range = field(4, 4);
+    let (mut precise, mut dual_plane) = (field(9, 9), field(10, 10));
+    let a = field(6, 5);
+
+    let (grid_w, grid_h);
+    if field(1, 0) != 0 {
+        range |= field(1, 0) << 1;
+        let b = field(8, 7);
+        match field(3, 2) {
+            0 => {
+                grid_w = b + 4;
+                grid_h = a + 2;
+            }
+            1 => {
+                grid_w = b + 8;
+                grid_h = a + 2;
+            }
+            2 => {
+                grid_w = a + 2;
+                grid_h = b + 8;
+            }
+            _ => {
+                if field(8, 8) == 1 {
+                    grid_w = (b & 1) + 2;
+                    grid_h = a + 2;
+                } else {
+                    grid_w = a + 2;
+                    grid_h = (b & 1) + 6;
+                }
+            }
+        }
+    } else {
+        if field(3, 2) == 0 {
+            return None;
+        }
+        range |= field(3, 2) << 1;
+        match field(8, 7) {
+            0 => {
+                grid_w = 12;
+                grid_h = a + 2;
+            }
+            1 => {
+                grid_w = a + 2;
+                grid_h = 12;
+            }
+            2 => {
+                grid_w = a + 6;
+                grid_h = field(10, 9) + 6;
+                precise = 0;
+                dual_plane = 0;
+            }
+            _ => match a {
+                0 => {
+                    grid_w = 6;
+                    grid_h = 10;
+                }
+                1 => {
+                    grid_w = 10;
+                    grid_h = 6;
+                }
+                _ => return None,
+            },
+        }
+    }
+
+    let weight_range = (range - 2 + 6 * precise) as usize;
+    let count = (grid_w * grid_h * (dual_plane + 1)) as usize;
+    let weight_bits = ise::sequence_bits(count, RANGES[weight_range]);
+    if count > 64 || !(24..=96).contains(&weight_bits) {
+        return None;
+    }
+    Some(BlockMode {
+        bits,
+        grid_w: grid_w as usize,
+        grid_h: grid_h as usize,
+        dual_plane: dual_plane == 1,
+        weight_range,
+        weight_bits,
+    })
+}
+
+// How a weight grid spreads over the texels of a block: each texel takes a bilinear
+// blend, in sixteenths, of up to four grid points.
+struct Infill {
+    contributions: Vec<Vec<(usize, u32)>>,
+    // the sum of each grid point's contributions, over all texels
+    totals: Vec<f32>,
+}
+
+impl Infill {
+    fn new((block_w, block_h): (usize, usize), (grid_w, grid_h): (usize, usize)) -> Infill {
+        let scale_s = (1024 + block_w / 2) / (block_w - 1);
+        let scale_t = (1024 + block_h / 2) / (block_h - 1);
+        let mut contributions = Vec::with_capacity(block_w * block_h);
+        let mut totals = vec![0.0; grid_w * grid_h];
+        for t in 0..block_h {
+            for s in 0..block_w {
+                let gs = (scale_s * s * (grid_w - 1) + 32) >> 6;
+                let gt = (scale_t * t * (grid_h - 1) + 32) >> 6;
+                let (js, fs) = (gs >> 4, (gs & 15) as u32);
+                let (jt, ft) = (gt >> 4, (gt & 15) as u32);
+                let w11 = (fs * ft + 8) >> 4;
+                let corners = [
+                    (js, jt, 16 + w11 - fs - ft),
+                    (js + 1, jt, fs - w11),
+                    (js, jt + 1, ft - w11),
+                    (js + 1, jt + 1, w11),
+                ];
+                let texel: Vec<(usize, u32)> = corners
+                    .iter()
+                    .filter(|&&(_, _, weight)| weight > 0)
+                    .map(|&(x, y, weight)| (y * grid_w + x, weight))
+                    .collect();
+                for &(point, weight) in &texel {
+                    totals[point] += weight as f32;
+                }
+                contributions.push(texel);
+            }
+        }
+        Infill {
+            contributions,
+            totals,
+        }
+    }
+
+    fn texel_weight(&self, texel: usize, grid: &[u32]) -> u32 {
+        let sum: u32 = self.contributions[texel]
+            .iter()
+            .map(|&(point, weight)| grid[point] * weight)
+            .sum();
+        (sum + 8) >> 4
+    }
+
+    // grid values in [0, 1] whose blend approximates the ideal texel weights
+    fn fit(&self, ideal: &[f32]) -> Vec<f32> {
+        let mut grid = vec![0.0f32; self.totals.len()];
+        for (contributions, &target) in self.contributions.iter().zip(ideal) {
+            for &(point, weight) in contributions {
+                grid[point] += weight as f32 * target;
+            }
+        }
+        for (value, &total) in grid.iter_mut().zip(&self.totals) {
+            *value = if total > 0.0 { *value / total } else { 0.5 };
+        }
+        // nudge the grid towards the residual a couple of times
+        for _ in 0..2 {
+            let mut correction = vec![0.0f32; grid.len()];
+            for (contributions, &target) in self.contributions.iter().zip(ideal) {
+                let blended: f32 = contributions
+                    .iter()
+                    .map(|&(point, weight)| grid[point] * weight as f32 / 16.0)
+                    .sum();
+                for &(point, weight) in contributions {
+                    correction[point] += weight as f32 * (target - blended);
+                }
+            }
+            for ((value, delta), &total) in grid.iter_mut().zip(correction).zip(&self.totals) {
+                if total > 0.0 {
+                    *value = (*value + delta / total).clamp(0.0, 1.0);
+                }
+            }
+        }
+        grid
+    }
+}
+
+pub struct AstcEncoder {
+    quality: Quality,
+    srgb: bool,
+    normal_map: bool,
+    weights: [f32; 4],
+    // the block modes whose grids fit the footprint, with the index of their infill
+    modes: Vec<(BlockMode, usize)>,
+    infills: Vec<Infill>,
+}
+
+impl AstcEncoder {
+    pub fn new(target: &PixelFormat, options: &CompressOptions) -> AstcEncoder {
+        let (block_w, block_h) = target.block_extent();
+        let block = (block_w as usize, block_h as usize);
+
+        let mut grids = HashMap::new();
+        let mut infills = vec![];
+        let mut modes = vec![];
+        for mode in BLOCK_MODES.iter() {
+            if mode.grid_w > block.0 || mode.grid_h > block.1 {
+                continue;
+            }
+            let grid = (mode.grid_w, mode.grid_h);
+            let infill = *grids.entry(grid).or_insert_with(|| {
+                infills.push(Infill::new(block, grid));
+                infills.len() - 1
+            });
+            modes.push((*mode, infill));
+        }
+
+        AstcEncoder {
+            quality: options.quality,
+            srgb: target.comp_content == CompContent::SRGB,
+            normal_map: options.normal_map,
+            weights: if options.normal_map {
+                NORMAL_WEIGHTS
+            } else if options.perceptual {
+                PERCEPTUAL_WEIGHTS
+            } else {
+                UNIFORM_WEIGHTS
+            },
+            modes,
+            infills,
+        }
+    }
+
+    // The block modes worth trying for an endpoint mode, likeliest first, by a rough
+    // model of the error that weight, colour and grid precision each add.
+    fn ranked_modes(&self, cem: u32, dual_plane: bool, extent: f32) -> Vec<(usize, usize)> {
+        let texels = self.infills[0].contributions.len() as f32;
+        let mut ranked: Vec<(f32, usize, usize)> = self
+            .modes
+            .iter()
+            .enumerate()
+            .filter(|(_, (mode, _))| mode.dual_plane == dual_plane)
+            .filter_map(|(i, (mode, _))| {
+                let colour = colour_range(mode, cem)?;
+                let weight_step = 1.0 / (RANGES[mode.weight_range].levels() - 1) as f32;
+                let colour_step = 255.0 / (RANGES[colour].levels() - 1) as f32;
+                let coverage = (mode.grid_w * mode.grid_h) as f32 / texels;
+                let estimate = extent * extent * weight_step * weight_step / 12.0
+                    + colour_step * colour_step / 12.0
+                    + extent * extent * (1.0 - coverage) * DECIMATION_LOSS;
+                Some((estimate, i, colour))
+            })
+            .collect();
+        ranked.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let count = match self.quality {
+            Quality::Fast => 1,
+            Quality::Normal => 4,
+            Quality::Slow => 16,
+        };
+        ranked
+            .iter()
+            .take(count)
+            .map(|&(_, mode, colour)| (mode, colour))
+            .collect()
+    }
+
+    fn encode_mode(
+        &self,
+        texels: &[Texel],
+        cem: u32,
+        ccs: Option<usize>,
+        candidate: (usize, usize),
+    ) -> Encoding {
+        let active = active_channels(cem);
+
+        // the principal axis through the first plane's channels, and the extremes of the
+        // second plane's channel
+        let mut first_plane: Vec<Texel> = texels.to_vec();
+        if let Some(c) = ccs {
+            for texel in &mut first_plane {
+                texel[c] = 0.0;
+            }
+        }
+        let mut endpoints = principal_endpoints(&first_plane, active);
+        if let Some(c) = ccs {
+            endpoints[0][c] = texels.iter().map(|t| t[c]).fold(255.0, f32::min);
+            endpoints[1][c] = texels.iter().map(|t| t[c]).fold(0.0, f32::max);
+        }
+
+        let refinements = match self.quality {
+            Quality::Fast => 0,
+            Quality::Normal => 1,
+            Quality::Slow => 2,
+        };
+        let mut best: Option<Encoding> = None;
+        for iteration in 0..=refinements {
+            let encoding = self.fit_weights(texels, cem, ccs, candidate, &endpoints);
+            if iteration < refinements {
+                endpoints = least_squares(texels, active, ccs, &encoding.texel_weights);
+            }
+            let better = match best {
+                Some(ref best) => encoding.error < best.error,
+                None => true,
+            };
+            if better {
+                best = Some(encoding);
+            }
+        }
+        best.unwrap()
+    }
+
+    // quantises the endpoints, then finds and quantises the weights that best fit them
+    fn fit_weights(
+        &self,
+        texels: &[Texel],
+        cem: u32,
+        ccs: Option<usize>,
+        (mode, colour): (usize, usize),
+        endpoints: &[Texel; 2],
+    ) -> Encoding {
+        let (mode, infill) = &self.modes[mode];
+        let infill = &self.infills[*infill];
+        let (quantise, unquantise) = &COLOUR_TABLES[colour - MIN_COLOUR_RANGE];
+        let active = active_channels(cem);
+        let mut codes = [[0u32; 4]; 2];
+        let mut values = [[255u32; 4]; 2];
+        for e in 0..2 {
+            for c in 0..active {
+                let code = quantise[endpoints[e][c].round().clamp(0.0, 255.0) as usize];
+                codes[e][c] = code;
+                values[e][c] = unquantise[code as usize];
+            }
+            if cem == CEM_LUMINANCE || cem == CEM_LUMINANCE_ALPHA {
+                // one luminance for all three colour channels
+                codes[e][1] = codes[e][0];
+                codes[e][2] = codes[e][0];
+                values[e][1] = values[e][0];
+                values[e][2] = values[e][0];
+            }
+        }
+        // direct RGB(A) endpoints in the wrong order would be read as blue-contracted
+        if (cem == CEM_RGB || cem == CEM_RGBA)
+            && values[1][..3].iter().sum::<u32>() < values[0][..3].iter().sum::<u32>()
+        {
+            codes.swap(0, 1);
+            values.swap(0, 1);
+        }
+
+        let planes = if ccs.is_some() { 2 } else { 1 };
+        let plane_of = |c: usize| if Some(c) == ccs { 1 } else { 0 };
+        let (weight_quantise, weight_unquantise) = &WEIGHT_TABLES[mode.weight_range];
+        let mut grids = vec![];
+        let mut texel_weights = vec![vec![0u32; texels.len()]; planes];
+        for (plane, weights) in texel_weights.iter_mut().enumerate() {
+            let channels: Vec<usize> = (0..active).filter(|&c| plane_of(c) == plane).collect();
+            let (mut span, mut length) = ([0.0f32; 4], 0.0f32);
+            for &c in &channels {
+                span[c] = values[1][c] as f32 - values[0][c] as f32;
+                length += self.weights[c] * span[c] * span[c];
+            }
+            let ideal: Vec<f32> = texels
+                .iter()
+                .map(|texel| {
+                    if length < 1e-6 {
+                        return 0.0;
+                    }
+                    let dot: f32 = channels
+                        .iter()
+                        .map(|&c| self.weights[c] * (texel[c] - values[0][c] as f32) * span[c])
+                        .sum();
+                    (dot / length).clamp(0.0, 1.0)
+                })
+                .collect();
+            let grid: Vec<u32> = infill
+                .fit(&ideal)
+                .iter()
+                .map(|value| weight_quantise[(value * 64.0).round() as usize])
+                .collect();
+            let grid_values: Vec<u32> = grid
+                .iter()
+                .map(|&code| weight_unquantise[code as usize])
+                .collect();
+            for (texel, weight) in weights.iter_mut().enumerate() {
+                *weight = infill.texel_weight(texel, &grid_values);
+            }
+            grids.push(grid);
+        }
+
+        let mut error = 0.0;
+        for (i, texel) in texels.iter().enumerate() {
+            for c in 0..4 {
+                let weight = texel_weights[plane_of(c)][i];
+                let decoded = self.decode(values[0][c], values[1][c], weight);
+                error += self.weights[c] * (decoded - texel[c]) * (decoded - texel[c]);
+            }
+        }
+
+        // weights for the two planes interleave
+        let mut weights = vec![];
+        for point in 0..grids[0].len() {
+            for grid in &grids {
+                weights.push(grid[point]);
+            }
+        }
+        Encoding {
+            error,
+            mode: *mode,
+            cem,
+            ccs,
+            colour,
+            codes,
+            weights,
+            texel_weights,
+        }
+    }
+
+    // an endpoint channel interpolated as the decoder does, scaled to [0, 255]
+    fn decode(&self, value0: u32, value1: u32, weight: u32) -> f32 {
+        let expand = |value: u32| {
+            if self.srgb {
+                value << 8 | 0x80
+            } else {
+                value * 257
+            }
+        };
+        let interpolated = (expand(value0) * (64 - weight) + expand(value1) * weight + 32) >> 6;
+        if self.srgb {
+            (interpolated >> 8) as f32
+        } else {
+            interpolated as f32 / 257.0
+        }
+    }
+}
+
+impl BlockEncoder for AstcEncoder {
+    fn encode_block(&self, block: &[Texel], out: &mut Vec<u8>) {
+        let mut texels: Vec<Texel> = block
+            .iter()
+            .map(|texel| {
+                if self.normal_map {
+                    let (x, y) = unit_xy(texel);
+                    [x * 255.0, x * 255.0, x * 255.0, y * 255.0]
+                } else {
+                    [
+                        texel[0] * 255.0,
+                        texel[1] * 255.0,
+                        texel[2] * 255.0,
+                        texel[3] * 255.0,
+                    ]
+                }
+            })
+            .collect();
+
+        let opaque = texels.iter().all(|t| t[3] > 254.5);
+        let grey = texels
+            .iter()
+            .all(|t| (t[0] - t[1]).abs() < 0.5 && (t[1] - t[2]).abs() < 0.5);
+        let cem = match (self.normal_map || grey, opaque && !self.normal_map) {
+            (true, true) => CEM_LUMINANCE,
+            (true, false) => CEM_LUMINANCE_ALPHA,
+            (false, true) => CEM_RGB,
+            (false, false) => CEM_RGBA,
+        };
+        if cem == CEM_LUMINANCE || cem == CEM_LUMINANCE_ALPHA {
+            for texel in &mut texels {
+                let luminance = (texel[0] + texel[1] + texel[2]) / 3.0;
+                texel[0] = luminance;
+                texel[1] = luminance;
+                texel[2] = luminance;
+            }
+        }
+
+        // which channel, if any, gets a second plane of weights; normal maps always try
+        // X and Y apart, since they're largely independent
+        let mut planes = vec![None];
+        let has_alpha = cem == CEM_LUMINANCE_ALPHA || cem == CEM_RGBA;
+        if has_alpha && (self.normal_map || self.quality != Quality::Fast) {
+            planes.push(Some(3));
+        }
+        if self.quality == Quality::Slow && (cem == CEM_RGB || cem == CEM_RGBA) {
+            planes.extend(&[Some(0), Some(1), Some(2)]);
+        }
+
+        let (mean, axis) = principal_axis(&texels, 0, 4);
+        let projections = texels
+            .iter()
+            .map(|texel| (0..4).map(|c| (texel[c] - mean[c]) * axis[c]).sum::<f32>());
+        let (min, max) =
+            projections.fold((0.0f32, 0.0f32), |(min, max), t| (min.min(t), max.max(t)));
+
+        let mut best: Option<Encoding> = None;
+        for &ccs in &planes {
+            for candidate in self.ranked_modes(cem, ccs.is_some(), max - min) {
+                let encoding = self.encode_mode(&texels, cem, ccs, candidate);
+                let better = match best {
+                    Some(ref best) => encoding.error < best.error,
+                    None => true,
+                };
+                if better {
+                    best = Some(encoding);
+                }
+            }
+        }
+        best.unwrap().write(out);
+    }
+}
+
+// what decoders produce for blocks that are malformed or need the HDR profile
+const ERROR_COLOUR: Texel = [1.0, 0.0, 1.0, 1.0];
+
+// the most colour endpoint integers a block can hold
+const MAX_COLOUR_INTEGERS: usize = 18;
+
+pub struct AstcDecoder {
+    srgb: bool,
+    block_w: usize,
+    block_h: usize,
+    // every weight grid that fits the footprint
+    infills: HashMap<(usize, usize), Infill>,
+}
+
+impl AstcDecoder {
+    pub fn new(format: &PixelFormat) -> AstcDecoder {
+        let (block_w, block_h) = format.block_extent();
+        let block = (block_w as usize, block_h as usize);
+        let mut infills = HashMap::new();
+        for mode in BLOCK_MODES.iter() {
+            if mode.grid_w <= block.0 && mode.grid_h <= block.1 {
+                let grid = (mode.grid_w, mode.grid_h);
+                infills
+                    .entry(grid)
+                    .or_insert_with(|| Infill::new(block, grid));
+            }
+        }
+        AstcDecoder {
+            srgb: format.comp_content == CompContent::SRGB,
+            block_w: block.0,
+            block_h: block.1,
+            infills,
+        }
+    }
+
+    // None for blocks that decode to the error colour
+    fn decode_bits(&self, bits: u128, out: &mut [Texel]) -> Option<()> {
+        let field = |low: u32, count: u32| (bits >> low) as u32 & ((1 << count) - 1);
+        if field(0, 9) == 0x1fc {
+            return self.decode_void_extent(bits, out);
+        }
+        let mode = decode_block_mode(field(0, 11))?;
+        let infill = self.infills.get(&(mode.grid_w, mode.grid_h))?;
+        let partitions = field(11, 2) as usize + 1;
+        if partitions == 4 && mode.dual_plane {
+            return None;
+        }
+
+        // Partitioned blocks either share one endpoint mode or pick each from two
+        // neighbouring classes, with the bits that don't fit the header stored just below
+        // the weights.
+        let plane_bits = if mode.dual_plane { 2 } else { 0 };
+        let below_weights = 128 - mode.weight_bits - plane_bits;
+        let (cems, colour_start, colour_end) = if partitions == 1 {
+            (vec![field(13, 4)], HEADER_BITS, below_weights)
+        } else if field(23, 2) == 0 {
+            (vec![field(25, 4); partitions], 29, below_weights)
+        } else {
+            let extra = 3 * partitions as u32 - 4;
+            let data = field(25, 4) | field(below_weights - extra, extra) << 4;
+            let class = field(23, 2) - 1;
+            let cems = (0..partitions)
+                .map(|p| (class + (data >> p & 1)) << 2 | data >> (partitions + 2 * p) & 3)
+                .collect();
+            (cems, 29, below_weights - extra)
+        };
+
+        let counts: Vec<usize> = cems.iter().map(|cem| 2 * (cem / 4 + 1) as usize).collect();
+        let total: usize = counts.iter().sum();
+        if total > MAX_COLOUR_INTEGERS || colour_end < colour_start {
+            return None;
+        }
+        let colour = (MIN_COLOUR_RANGE..RANGES.len())
+            .rev()
+            .find(|&range| ise::sequence_bits(total, RANGES[range]) <= colour_end - colour_start)?;
+        let values: Vec<i32> = ise::decode(bits, RANGES[colour], colour_start, total)
+            .iter()
+            .map(|&value| ise::unquantise_colour(value, RANGES[colour]) as i32)
+            .collect();
+        let mut endpoints = vec![];
+        let mut offset = 0;
+        for (&cem, &count) in cems.iter().zip(&counts) {
+            endpoints.push(decode_endpoints(cem, &values[offset..offset + count])?);
+            offset += count;
+        }
+
+        // weights fill the block from the top, bit-reversed, and interleave by plane
+        let planes = if mode.dual_plane { 2 } else { 1 };
+        let weight_range = RANGES[mode.weight_range];
+        let weights = ise::decode(
+            bits.reverse_bits(),
+            weight_range,
+            0,
+            mode.grid_w * mode.grid_h * planes,
+        );
+        let grids: Vec<Vec<u32>> = (0..planes)
+            .map(|plane| {
+                weights
+                    .iter()
+                    .skip(plane)
+                    .step_by(planes)
+                    .map(|&weight| ise::unquantise_weight(weight, weight_range))
+                    .collect()
+            })
+            .collect();
+        let ccs = if mode.dual_plane {
+            Some(field(below_weights, 2) as usize)
+        } else {
+            None
+        };
+
+        let seed = field(13, 10);
+        let small = self.block_w * self.block_h < 31;
+        for (i, texel) in out.iter_mut().enumerate() {
+            let (x, y) = ((i % self.block_w) as u32, (i / self.block_w) as u32);
+            let partition = if partitions == 1 {
+                0
+            } else {
+                select_partition(seed, (x, y, 0), partitions, small)
+            };
+            let [start, end] = endpoints[partition];
+            let plane_weights = [
+                infill.texel_weight(i, &grids[0]),
+                infill.texel_weight(i, &grids[planes - 1]),
+            ];
+            for c in 0..4 {
+                let weight = plane_weights[(Some(c) == ccs) as usize];
+                texel[c] = self.interpolate(start[c] as u32, end[c] as u32, weight);
+            }
+        }
+        Some(())
+    }
+
+    // a block of one colour, as 16-bit UNORM values or, for HDR, halves
+    fn decode_void_extent(&self, bits: u128, out: &mut [Texel]) -> Option<()> {
+        let hdr = bits >> 9 & 1 == 1;
+        let mut colour = [0.0; 4];
+        for (c, value) in colour.iter_mut().enumerate() {
+            let channel = (bits >> (64 + 16 * c)) as u16;
+            *value = if hdr {
+                f16_to_f32(channel)
+            } else if self.srgb {
+                f32::from(channel >> 8) / 255.0
+            } else {
+                f32::from(channel) / 65535.0
+            };
+        }
+        for texel in out.iter_mut() {
+            *texel = colour;
+        }
+        Some(())
+    }
+
+    // an endpoint channel interpolated as in AstcEncoder::decode, in [0, 1]
+    fn interpolate(&self, value0: u32, value1: u32, weight: u32) -> f32 {
+        let expand = |value: u32| {
+            if self.srgb {
+                value << 8 | 0x80
+            } else {
+                value * 257
+            }
+        };
+        let interpolated = (expand(value0) * (64 - weight) + expand(value1) * weight + 32) >> 6;
+        if self.srgb {
+            (interpolated >> 8) as f32 / 255.0
+        } else {
+            interpolated as f32 / 65535.0
+        }
+    }
+}
+
+impl BlockDecoder for AstcDecoder {
+    fn block_bytes(&self) -> usize {
+        16
+    }
+
+    fn decode_block(&self, block: &[u8], out: &mut [Texel]) {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&block[..16]);
+        if self.decode_bits(u128::from_le_bytes(bytes), out).is_none() {
+            for texel in out.iter_mut() {
+                *texel = ERROR_COLOUR;
+            }
+        }
+    }
+}
+
+// The two endpoints of an LDR colour endpoint mode, from its unquantised integers; HDR
+// modes give None.
+fn decode_endpoints(cem: u32, v: &[i32]) -> Option<[[i32; 4]; 2]> {
+    let endpoints = match cem {
+        0 => [[v[0], v[0], v[0], 255], [v[1], v[1], v[1], 255]],
+        1 => {
+            let l0 = (v[0] >> 2) | (v[1] & 0xc0);
+            let l1 = l0 + (v[1] & 0x3f);
+            [[l0, l0, l0, 255], [l1, l1, l1, 255]]
+        }
+        4 => [[v[0], v[0], v[0], v[2]], [v[1], v[1], v[1], v[3]]],
+        5 => {
+            let (dl, l) = bit_transfer_signed(v[1], v[0]);
+            let (da, a) = bit_transfer_signed(v[3], v[2]);
+            [[l, l, l, a], [l + dl, l + dl, l + dl, a + da]]
+        }
+        6 | 10 => {
+            let (a0, a1) = if cem == 10 { (v[4], v[5]) } else { (255, 255) };
+            [
+                [
+                    (v[0] * v[3]) >> 8,
+                    (v[1] * v[3]) >> 8,
+                    (v[2] * v[3]) >> 8,
+                    a0,
+                ],
+                [v[0], v[1], v[2], a1],
+            ]
+        }
+        8 | 12 => {
+            let (a0, a1) = if cem == 12 { (v[6], v[7]) } else { (255, 255) };
+            if v[1] + v[3] + v[5] >= v[0] + v[2] + v[4] {
+                [[v[0], v[2], v[4], a0], [v[1], v[3], v[5], a1]]
+            } else {
+                [
+                    blue_contract([v[1], v[3], v[5], a1]),
+                    blue_contract([v[0], v[2], v[4], a0]),
+                ]
+            }
+        }
+        9 | 13 => {
+            let (dr, r) = bit_transfer_signed(v[1], v[0]);
+            let (dg, g) = bit_transfer_signed(v[3], v[2]);
+            let (db, b) = bit_transfer_signed(v[5], v[4]);
+            let (da, a) = if cem == 13 {
+                bit_transfer_signed(v[7], v[6])
+            } else {
+                (0, 255)
+            };
+            if dr + dg + db >= 0 {
+                [[r, g, b, a], [r + dr, g + dg, b + db, a + da]]
+            } else {
+                [
+                    blue_contract([r + dr, g + dg, b + db, a + da]),
+                    blue_contract([r, g, b, a]),
+                ]
+            }
+        }
+        _ => return None,
+    };
+    Some(endpoints.map(|endpoint| endpoint.map(|value| value.clamp(0, 255))))
+}
+
+// moves the top bit of a into b, leaving a as a signed 6-bit offset
+fn bit_transfer_signed(a: i32, b: i32) -> (i32, i32) {
+    let b = (b >> 1) | (a & 0x80);
+    let a = (a >> 1) & 0x3f;
+    (if a & 0x20 != 0 { a - 0x40 } else { a }, b)
+}
+
+fn blue_contract([r, g, b, a]: [i32; 4]) -> [i32; 4] {
+    [(r + b) >> 1, (g + b) >> 1, b, a]
+}
+
+// The partition of a texel, from the spec's hash of the seed and its coordinates.
+// Small blocks double the coordinates to spread the partitions out.
+fn select_partition(
+    seed: u32,
+    (x, y, z): (u32, u32, u32),
+    partitions: usize,
+    small: bool,
+) -> usize {
+    let (x, y, z) = if small {
+        (x << 1, y << 1, z << 1)
+    } else {
+        (x, y, z)
+    };
+    let seed = seed + 1024 * (partitions as u32 - 1);
+    let rnum = hash52(seed);
+    let nibble = |shift: u32| {
+        let value = (rnum >> shift) & 0xf;
+        value * value
+    };
+    let mut seeds = [
+        nibble(0),
+        nibble(4),
+        nibble(8),
+        nibble(12),
+        nibble(16),
+        nibble(20),
+        nibble(24),
+        nibble(28),
+        nibble(18),
+        nibble(22),
+        nibble(26),
+        {
+            let value = rnum.rotate_left(2) & 0xf;
+            value * value
+        },
+    ];
+
+    let (sh1, sh2) = if seed & 1 == 1 {
+        (
+            if seed & 2 != 0 { 4 } else { 5 },
+            if partitions == 3 { 6 } else { 5 },
+        )
+    } else {
+        (
+            if partitions == 3 { 6 } else { 5 },
+            if seed & 2 != 0 { 4 } else { 5 },
+        )
+    };
+    let sh3 = if seed & 0x10 != 0 { sh1 } else { sh2 };
+    for (i, value) in seeds.iter_mut().enumerate() {
+        *value >>= match i {
+            0..=7 if i % 2 == 0 => sh1,
+            0..=7 => sh2,
+            _ => sh3,
+        };
+    }
+
+    let combine = |sx: u32, sy: u32, sz: u32, shift: u32| {
+        (sx.wrapping_mul(x))
+            .wrapping_add(sy.wrapping_mul(y))
+            .wrapping_add(sz.wrapping_mul(z))
+            .wrapping_add(rnum >> shift)
+            & 0x3f
+    };
+    let a = combine(seeds[0], seeds[1], seeds[10], 14);
+    let b = combine(seeds[2], seeds[3], seeds[11], 10);
+    let c = if partitions < 3 {
+        0
+    } else {
+        combine(seeds[4], seeds[5], seeds[8], 6)
+    };
+    let d = if partitions < 4 {
+        0
+    } else {
+        combine(seeds[6], seeds[7], seeds[9], 2)
+    };
+
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+fn hash52(input: u32) -> u32 {
+    let mut value = input;
+    value ^= value >> 15;
+    value = value.wrapping_mul(0xeede_0891);
+    value ^= value >> 5;
+    value = value.wrapping_add(value << 16);
+    value ^= value >> 7;
+    value ^= value >> 3;
+    value ^= value << 6;
+    value ^= value >> 17;
+    value
+}
+
+struct Encoding {
+    error: f32,
+    mode: BlockMode,
+    cem: u32,
+    ccs: Option<usize>,
+    colour: usize,
+    codes: [[u32; 4]; 2],
+    // quantised grid weights, interleaved by plane
+    weights: Vec<u32>,
+    // per plane, each texel's infilled weight in [0, 64]
+    texel_weights: Vec<Vec<u32>>,
+}
+
+impl Encoding {
+    fn write(&self, out: &mut Vec<u8>) {
+        // one partition, so bits 11 and 12 stay clear
+        let mut bits = u128::from(self.mode.bits) | u128::from(self.cem) << 13;
+
+        let mut integers = vec![];
+        for c in 0..(self.cem / 4 + 1) as usize {
+            // luminance-alpha keeps its alpha second
+            let c = if self.cem == CEM_LUMINANCE_ALPHA && c == 1 {
+                3
+            } else {
+                c
+            };
+            integers.push(self.codes[0][c]);
+            integers.push(self.codes[1][c]);
+        }
+        ise::encode(&integers, RANGES[self.colour], &mut bits, HEADER_BITS);
+
+        // weights fill the block from the top, bit-reversed
+        let mut weights = 0u128;
+        ise::encode(
+            &self.weights,
+            RANGES[self.mode.weight_range],
+            &mut weights,
+            0,
+        );
+        bits |= weights.reverse_bits();
+        if let Some(ccs) = self.ccs {
+            bits |= (ccs as u128) << (128 - self.mode.weight_bits - 2);
+        }
+        out.extend_from_slice(&bits.to_le_bytes());
+    }
+}
+
+fn active_channels(cem: u32) -> usize {
+    match cem {
+        CEM_LUMINANCE | CEM_RGB => 3,
+        _ => 4,
+    }
+}
+
+// the finest colour range whose endpoints fit beside the mode's weights
+fn colour_range(mode: &BlockMode, cem: u32) -> Option<usize> {
+    let integers = 2 * (cem / 4 + 1) as usize;
+    let plane_bits = if mode.dual_plane { 2 } else { 0 };
+    let available = 128 - HEADER_BITS - mode.weight_bits - plane_bits;
+    (MIN_COLOUR_RANGE..RANGES.len())
+        .rev()
+        .find(|&range| ise::sequence_bits(integers, RANGES[range]) <= available)
+}
+
+fn principal_endpoints(texels: &[Texel], active: usize) -> [Texel; 2] {
+    let (mean, axis) = principal_axis(texels, 0, active);
+    let (mut min, mut max) = (0.0f32, 0.0f32);
+    for texel in texels {
+        let t: f32 = (0..active).map(|c| (texel[c] - mean[c]) * axis[c]).sum();
+        min = min.min(t);
+        max = max.max(t);
+    }
+    let mut endpoints = [[255.0f32; 4]; 2];
+    for c in 0..active {
+        endpoints[0][c] = (mean[c] + axis[c] * min).clamp(0.0, 255.0);
+        endpoints[1][c] = (mean[c] + axis[c] * max).clamp(0.0, 255.0);
+    }
+    endpoints
+}
+
+// endpoints that best reproduce the texels given each plane's infilled weights
+fn least_squares(
+    texels: &[Texel],
+    active: usize,
+    ccs: Option<usize>,
+    texel_weights: &[Vec<u32>],
+) -> [Texel; 2] {
+    let mut endpoints = [[255.0f32; 4]; 2];
+    for c in 0..active {
+        let weights = &texel_weights[if Some(c) == ccs { 1 } else { 0 }];
+        let (mut aa, mut bb, mut ab, mut ax, mut bx) = (0.0f32, 0.0f32, 0.0f32, 0.0f32, 0.0f32);
+        for (texel, &weight) in texels.iter().zip(weights) {
+            let t = weight as f32 / 64.0;
+            let s = 1.0 - t;
+            aa += s * s;
+            bb += t * t;
+            ab += s * t;
+            ax += s * texel[c];
+            bx += t * texel[c];
+        }
+        let det = aa * bb - ab * ab;
+        if det.abs() < 1e-6 {
+            // every texel on one weight; a flat channel
+            let mean = texels.iter().map(|t| t[c]).sum::<f32>() / texels.len() as f32;
+            endpoints[0][c] = mean;
+            endpoints[1][c] = mean;
+        } else {
+            endpoints[0][c] = ((ax * bb - bx * ab) / det).clamp(0.0, 255.0);
+            endpoints[1][c] = ((bx * aa - ax * ab) / det).clamp(0.0, 255.0);
+        }
+    }
+    endpoints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ERROR_COLOUR;
+    use crate::compress::bc4::unit_xy;
+    use crate::compress::{
+        decode_block_for_test, image_for_test, rmse_for_test, round_trip_for_test, CompressOptions,
+        Quality, Texel,
+    };
+    use crate::Dimensions;
+
+    // an LDR void-extent block covering no particular extent, with 16-bit channels
+    fn void_extent(colour: [u16; 4]) -> Vec<u8> {
+        let mut block = vec![0xfc, 0xfd, 0xff, 0xff];
+        block.extend_from_slice(&[0xff; 4]);
+        for channel in &colour {
+            block.extend_from_slice(&channel.to_le_bytes());
+        }
+        block
+    }
+
+    #[test]
+    fn void_extent_ldr() {
+        let block = void_extent([0xffff, 0x8000, 0x0000, 0x4040]);
+        for name in &["ASTC_4x4_UNORM", "ASTC_8x8_UNORM"] {
+            let texels = decode_block_for_test(name, &block);
+            assert_eq!(texels.len(), if name.contains("8x8") { 64 } else { 16 });
+            for texel in texels {
+                assert_eq!(
+                    texel,
+                    [1.0, 32768.0 / 65535.0, 0.0, 0x4040 as f32 / 65535.0]
+                );
+            }
+        }
+        // sRGB keeps only the top eight bits
+        for texel in decode_block_for_test("ASTC_6x5_SRGB", &block) {
+            assert_eq!(texel, [1.0, 128.0 / 255.0, 0.0, 64.0 / 255.0]);
+        }
+    }
+
+    #[test]
+    fn reserved_block_mode() {
+        for texel in decode_block_for_test("ASTC_4x4_UNORM", &[0; 16]) {
+            assert_eq!(texel, ERROR_COLOUR);
+        }
+    }
+
+    fn options(quality: Quality, normal_map: bool) -> CompressOptions {
+        CompressOptions {
+            quality,
+            normal_map,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quality_levels() {
+        // larger footprints spend fewer bits on each texel, while higher qualities try more
+        // block modes, refinements and dual planes
+        let image = image_for_test();
+        let dim = Dimensions::new(16, 16, 0);
+        let footprints = [
+            ("ASTC_4x4_UNORM", [0.04, 0.04, 0.02]),
+            ("ASTC_6x6_UNORM", [0.065, 0.065, 0.045]),
+            ("ASTC_8x8_UNORM", [0.095, 0.095, 0.065]),
+        ];
+        let mut previous = [0.0; 3];
+        for (name, bounds) in footprints.iter() {
+            let mut errors = [0.0; 3];
+            let qualities = [Quality::Fast, Quality::Normal, Quality::Slow];
+            for (error, &quality) in errors.iter_mut().zip(&qualities) {
+                let (_, decoded) = round_trip_for_test(name, &options(quality, false), &image, dim);
+                *error = rmse_for_test(&decoded, &image, 4);
+            }
+            for i in 0..3 {
+                assert!(errors[i] < bounds[i], "{}: {:?}", name, errors);
+                assert!(errors[i] > previous[i], "{}: {:?}", name, errors);
+            }
+            assert!(
+                errors[1] <= errors[0] && errors[2] <= errors[1],
+                "{}: {:?}",
+                name,
+                errors
+            );
+            previous = errors;
+        }
+    }
+
+    #[test]
+    fn normal_map() {
+        // X goes to the colour channels and Y to alpha, renormalised from the colours
+        let dim = Dimensions::new(8, 8, 0);
+        let texels: Vec<Texel> = (0..64)
+            .map(|i| {
+                let (x, y) = ((i % 8) as f32 / 7.0, (i / 8) as f32 / 7.0);
+                [0.3 + 0.4 * x, 0.4 + 0.3 * y, 0.9, 1.0]
+            })
+            .collect();
+        let (_, decoded) = round_trip_for_test(
+            "ASTC_4x4_UNORM",
+            &options(Quality::Normal, true),
+            &texels,
+            dim,
+        );
+        for (texel, source) in decoded.iter().zip(&texels) {
+            let (x, y) = unit_xy(source);
+            assert!((texel[0] - x).abs() < 0.02, "{:?} for {:?}", texel, (x, y));
+            assert_eq!(texel[0], texel[1]);
+            assert_eq!(texel[0], texel[2]);
+            assert!((texel[3] - y).abs() < 0.02, "{:?} for {:?}", texel, (x, y));
+        }
+    }
+}
@@ -0,0 +1,634 @@
+use std::mem;
+
+use crate::compress::{bc4, BlockDecoder, BlockEncoder, CompressOptions, Quality, Texel};
+use crate::pixel::{CompLayout, PixelFormat};
+
+// BC1 colour endpoints and BC2/BC3 colour blocks, in 0-255 space
+type Vec3 = [f32; 3];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum AlphaMode {
+    Opaque,
+    PunchThrough,
+    Explicit,
+    Interpolated,
+}
+
+pub struct Bc1Encoder {
+    quality: Quality,
+    alpha_mode: AlphaMode,
+    alpha_threshold: f32,
+}
+
+impl Bc1Encoder {
+    pub fn new(target: &PixelFormat, options: &CompressOptions) -> Bc1Encoder {
+        let alpha_mode = match target.tag {
+            "BC2" => AlphaMode::Explicit,
+            "BC3" => AlphaMode::Interpolated,
+            _ if target.comp_layout == CompLayout::R8G8B8A8 => AlphaMode::PunchThrough,
+            _ => AlphaMode::Opaque,
+        };
+        Bc1Encoder {
+            quality: options.quality,
+            alpha_mode,
+            alpha_threshold: f32::from(options.alpha_threshold) / 255.0,
+        }
+    }
+}
+
+impl BlockEncoder for Bc1Encoder {
+    fn encode_block(&self, block: &[Texel], out: &mut Vec<u8>) {
+        match self.alpha_mode {
+            AlphaMode::Explicit => encode_explicit_alpha(block, out),
+            AlphaMode::Interpolated => {
+                let alphas: Vec<f32> = block.iter().map(|texel| texel[3]).collect();
+                bc4::encode_unorm(&alphas, self.quality, out);
+            }
+            _ => (),
+        }
+
+        let fit = ColourFit {
+            colours: block
+                .iter()
+                .map(|t| [to_255(t[0]), to_255(t[1]), to_255(t[2])])
+                .collect(),
+            transparent: block
+                .iter()
+                .map(|t| self.alpha_mode == AlphaMode::PunchThrough && t[3] < self.alpha_threshold)
+                .collect(),
+            // the D3D10 spec decodes BC2/BC3 colour blocks in four-colour mode regardless
+            allow_three: self.alpha_mode == AlphaMode::Opaque
+                || self.alpha_mode == AlphaMode::PunchThrough,
+            black_is_opaque: self.alpha_mode == AlphaMode::Opaque,
+        };
+        fit.encode(self.quality).write(out);
+    }
+}
+
+pub struct Bc1Decoder {
+    alpha_mode: AlphaMode,
+}
+
+impl Bc1Decoder {
+    pub fn new(format: &PixelFormat) -> Bc1Decoder {
+        Bc1Decoder {
+            alpha_mode: Bc1Encoder::new(format, &CompressOptions::default()).alpha_mode,
+        }
+    }
+}
+
+impl BlockDecoder for Bc1Decoder {
+    fn block_bytes(&self) -> usize {
+        match self.alpha_mode {
+            AlphaMode::Opaque | AlphaMode::PunchThrough => 8,
+            _ => 16,
+        }
+    }
+
+    fn decode_block(&self, block: &[u8], out: &mut [Texel]) {
+        let colour = &block[self.block_bytes() - 8..];
+        let colour0 = u16::from_le_bytes([colour[0], colour[1]]);
+        let colour1 = u16::from_le_bytes([colour[2], colour[3]]);
+        let indices = u32::from_le_bytes([colour[4], colour[5], colour[6], colour[7]]);
+        let three_colour = colour0 <= colour1
+            && (self.alpha_mode == AlphaMode::Opaque || self.alpha_mode == AlphaMode::PunchThrough);
+        let palette = palette(colour0, colour1, three_colour);
+
+        for (i, texel) in out.iter_mut().enumerate() {
+            let index = (indices >> (2 * i) & 0x3) as usize;
+            let entry = palette[index];
+            let transparent =
+                three_colour && index == 3 && self.alpha_mode == AlphaMode::PunchThrough;
+            *texel = [
+                entry[0] / 255.0,
+                entry[1] / 255.0,
+                entry[2] / 255.0,
+                if transparent { 0.0 } else { 1.0 },
+            ];
+        }
+        match self.alpha_mode {
+            AlphaMode::Explicit => {
+                for (i, texel) in out.iter_mut().enumerate() {
+                    texel[3] = f32::from(block[i / 2] >> (4 * (i % 2)) & 0xF) / 15.0;
+                }
+            }
+            AlphaMode::Interpolated => {
+                let mut alphas = [0.0; 16];
+                bc4::decode_unorm(&block[..8], &mut alphas);
+                for (texel, alpha) in out.iter_mut().zip(alphas.iter()) {
+                    texel[3] = *alpha;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn encode_explicit_alpha(block: &[Texel], out: &mut Vec<u8>) {
+    let quantised: Vec<u8> = block
+        .iter()
+        .map(|t| (t[3].clamp(0.0, 1.0) * 15.0).round() as u8)
+        .collect();
+    for pair in quantised.chunks(2) {
+        out.push(pair[0] | pair[1] << 4);
+    }
+}
+
+struct ColourFit {
+    colours: Vec<Vec3>,
+    transparent: Vec<bool>,
+    allow_three: bool,
+    black_is_opaque: bool,
+}
+
+struct Encoded {
+    error: f32,
+    colour0: u16,
+    colour1: u16,
+    indices: u32,
+}
+
+impl Encoded {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.colour0.to_le_bytes());
+        out.extend_from_slice(&self.colour1.to_le_bytes());
+        out.extend_from_slice(&self.indices.to_le_bytes());
+    }
+}
+
+impl ColourFit {
+    fn encode(&self, quality: Quality) -> Encoded {
+        let points: Vec<Vec3> = self
+            .colours
+            .iter()
+            .zip(&self.transparent)
+            .filter(|&(_, &transparent)| !transparent)
+            .map(|(colour, _)| *colour)
+            .collect();
+        if points.is_empty() {
+            return Encoded {
+                error: 0.0,
+                colour0: 0,
+                colour1: 0,
+                indices: 0xFFFF_FFFF,
+            };
+        }
+        // any transparent texel forces three-colour mode
+        let need_three = points.len() < self.colours.len();
+
+        let mut best = match quality {
+            Quality::Fast => {
+                let (start, end) = bounding_box(&points);
+                self.evaluate(start, end, need_three)
+            }
+            Quality::Normal => {
+                let (start, end) = principal_endpoints(&points);
+                self.refine(self.evaluate(start, end, need_three), 2)
+            }
+            Quality::Slow => {
+                let order = principal_order(&points);
+                let mut best = None;
+                if !need_three {
+                    let (start, end) = cluster_fit(&order, &[1.0, 2.0 / 3.0, 1.0 / 3.0, 0.0]);
+                    best = Some(self.refine(self.evaluate(start, end, false), 1));
+                }
+                if self.allow_three {
+                    let (start, end) = cluster_fit(&order, &[1.0, 0.5, 0.0]);
+                    let three = self.refine(self.evaluate(start, end, true), 1);
+                    let better = match best {
+                        Some(ref best) => three.error < best.error,
+                        None => true,
+                    };
+                    if better {
+                        best = Some(three);
+                    }
+                }
+                best.unwrap()
+            }
+        };
+        if quality != Quality::Fast && self.allow_three && !need_three {
+            // a three-colour block sometimes beats four colours, e.g. with a midpoint cluster
+            let (start, end) = principal_endpoints(&points);
+            let three = self.evaluate(start, end, true);
+            if three.error < best.error {
+                best = three;
+            }
+        }
+        best
+    }
+
+    // quantise endpoints to 565 and pick the closest palette entry for each texel
+    fn evaluate(&self, start: Vec3, end: Vec3, three: bool) -> Encoded {
+        let (mut colour0, mut colour1) = (pack_565(start), pack_565(end));
+        // four-colour mode is signalled by colour0 > colour1, three-colour by the reverse
+        if (three && colour0 > colour1) || (!three && colour0 < colour1) {
+            mem::swap(&mut colour0, &mut colour1);
+        }
+        let three_colour = self.allow_three && colour0 <= colour1;
+        let palette = palette(colour0, colour1, three_colour);
+        let usable = if three_colour && !self.black_is_opaque {
+            3
+        } else {
+            4
+        };
+
+        let mut error = 0.0;
+        let mut indices = 0u32;
+        for (i, (colour, &transparent)) in self.colours.iter().zip(&self.transparent).enumerate() {
+            let index = if transparent {
+                3
+            } else {
+                let mut best_index = 0;
+                let mut best_error = distance(colour, &palette[0]);
+                for (index, entry) in palette.iter().enumerate().take(usable).skip(1) {
+                    let entry_error = distance(colour, entry);
+                    if entry_error < best_error {
+                        best_index = index;
+                        best_error = entry_error;
+                    }
+                }
+                error += best_error;
+                best_index
+            };
+            indices |= (index as u32) << (2 * i);
+        }
+        Encoded {
+            error,
+            colour0,
+            colour1,
+            indices,
+        }
+    }
+
+    // least-squares endpoints for the current index assignment, iterated
+    fn refine(&self, encoded: Encoded, iterations: usize) -> Encoded {
+        let mut best = encoded;
+        for _ in 0..iterations {
+            let three = self.allow_three && best.colour0 <= best.colour1;
+            let weights: &[f32] = if three {
+                &[1.0, 0.0, 0.5]
+            } else {
+                &[1.0, 0.0, 2.0 / 3.0, 1.0 / 3.0]
+            };
+            let mut samples = vec![];
+            for (i, colour) in self.colours.iter().enumerate() {
+                let index = ((best.indices >> (2 * i)) & 0x3) as usize;
+                if index < weights.len() && !self.transparent[i] {
+                    samples.push((weights[index], *colour));
+                }
+            }
+            let (start, end) = match least_squares(&samples) {
+                Some(endpoints) => endpoints,
+                None => break,
+            };
+            let candidate = self.evaluate(start, end, three);
+            if candidate.error < best.error {
+                best = candidate;
+            } else {
+                break;
+            }
+        }
+        best
+    }
+}
+
+fn to_255(value: f32) -> f32 {
+    value.clamp(0.0, 1.0) * 255.0
+}
+
+fn distance(a: &Vec3, b: &Vec3) -> f32 {
+    let (dr, dg, db) = (a[0] - b[0], a[1] - b[1], a[2] - b[2]);
+    dr * dr + dg * dg + db * db
+}
+
+fn lerp(a: &Vec3, b: &Vec3, t: f32) -> Vec3 {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
+}
+
+fn pack_565(colour: Vec3) -> u16 {
+    let quantise = |value: f32, max: f32| (value.clamp(0.0, 255.0) * max / 255.0).round() as u16;
+    quantise(colour[0], 31.0) << 11 | quantise(colour[1], 63.0) << 5 | quantise(colour[2], 31.0)
+}
+
+fn unpack_565(colour: u16) -> Vec3 {
+    let (r, g, b) = (colour >> 11 & 0x1F, colour >> 5 & 0x3F, colour & 0x1F);
+    [
+        f32::from(r << 3 | r >> 2),
+        f32::from(g << 2 | g >> 4),
+        f32::from(b << 3 | b >> 2),
+    ]
+}
+
+fn palette(colour0: u16, colour1: u16, three_colour: bool) -> [Vec3; 4] {
+    let (a, b) = (unpack_565(colour0), unpack_565(colour1));
+    if three_colour {
+        [a, b, lerp(&a, &b, 0.5), [0.0, 0.0, 0.0]]
+    } else {
+        [a, b, lerp(&a, &b, 1.0 / 3.0), lerp(&a, &b, 2.0 / 3.0)]
+    }
+}
+
+// per-channel extremes, with red and blue flipped when they run against green
+fn bounding_box(points: &[Vec3]) -> (Vec3, Vec3) {
+    let mut min = [255.0f32; 3];
+    let mut max = [0.0f32; 3];
+    for point in points {
+        for c in 0..3 {
+            min[c] = min[c].min(point[c]);
+            max[c] = max[c].max(point[c]);
+        }
+    }
+    let mean = mean(points);
+    let (mut rg, mut bg) = (0.0, 0.0);
+    for point in points {
+        rg += (point[0] - mean[0]) * (point[1] - mean[1]);
+        bg += (point[2] - mean[2]) * (point[1] - mean[1]);
+    }
+    if rg < 0.0 {
+        mem::swap(&mut min[0], &mut max[0]);
+    }
+    if bg < 0.0 {
+        mem::swap(&mut min[2], &mut max[2]);
+    }
+    // inset slightly, since the extremes are rarely the best endpoints
+    for c in 0..3 {
+        let inset = (max[c] - min[c]) / 16.0;
+        max[c] -= inset;
+        min[c] += inset;
+    }
+    (max, min)
+}
+
+fn mean(points: &[Vec3]) -> Vec3 {
+    let mut sum = [0.0f32; 3];
+    for point in points {
+        for c in 0..3 {
+            sum[c] += point[c];
+        }
+    }
+    let n = points.len() as f32;
+    [sum[0] / n, sum[1] / n, sum[2] / n]
+}
+
+fn principal_axis(points: &[Vec3]) -> (Vec3, Vec3) {
+    let mean = mean(points);
+    let mut cov = [[0.0f32; 3]; 3];
+    for point in points {
+        let d = [point[0] - mean[0], point[1] - mean[1], point[2] - mean[2]];
+        for i in 0..3 {
+            for j in 0..3 {
+                cov[i][j] += d[i] * d[j];
+            }
+        }
+    }
+    // power iteration, seeded with the row of largest variance
+    let mut axis = cov[0];
+    for row in &cov[1..] {
+        if row[0] * row[0] + row[1] * row[1] + row[2] * row[2]
+            > axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]
+        {
+            axis = *row;
+        }
+    }
+    for _ in 0..8 {
+        let next = [
+            cov[0][0] * axis[0] + cov[0][1] * axis[1] + cov[0][2] * axis[2],
+            cov[1][0] * axis[0] + cov[1][1] * axis[1] + cov[1][2] * axis[2],
+            cov[2][0] * axis[0] + cov[2][1] * axis[1] + cov[2][2] * axis[2],
+        ];
+        let length = (next[0] * next[0] + next[1] * next[1] + next[2] * next[2]).sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        axis = [next[0] / length, next[1] / length, next[2] / length];
+    }
+    let length = (axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]).sqrt();
+    if length < 1e-6 {
+        return (mean, [0.0, 0.0, 0.0]);
+    }
+    (mean, [axis[0] / length, axis[1] / length, axis[2] / length])
+}
+
+fn project(point: &Vec3, mean: &Vec3, axis: &Vec3) -> f32 {
+    (point[0] - mean[0]) * axis[0] + (point[1] - mean[1]) * axis[1] + (point[2] - mean[2]) * axis[2]
+}
+
+fn principal_endpoints(points: &[Vec3]) -> (Vec3, Vec3) {
+    let (mean, axis) = principal_axis(points);
+    let (mut min, mut max) = (0.0f32, 0.0f32);
+    for point in points {
+        let t = project(point, &mean, &axis);
+        min = min.min(t);
+        max = max.max(t);
+    }
+    let along = |t: f32| {
+        [
+            mean[0] + axis[0] * t,
+            mean[1] + axis[1] * t,
+            mean[2] + axis[2] * t,
+        ]
+    };
+    (along(max), along(min))
+}
+
+fn principal_order(points: &[Vec3]) -> Vec<Vec3> {
+    let (mean, axis) = principal_axis(points);
+    let mut order = points.to_vec();
+    // total_cmp so that NaN texels sort somewhere rather than panic
+    order.sort_by(|a, b| project(b, &mean, &axis).total_cmp(&project(a, &mean, &axis)));
+    order
+}
+
+// Solves for endpoints a, b minimising sum |w * a + (1 - w) * b - x|^2.
+fn least_squares(samples: &[(f32, Vec3)]) -> Option<(Vec3, Vec3)> {
+    let (mut aa, mut bb, mut ab) = (0.0f32, 0.0f32, 0.0f32);
+    let mut ax = [0.0f32; 3];
+    let mut bx = [0.0f32; 3];
+    for &(w, x) in samples {
+        let v = 1.0 - w;
+        aa += w * w;
+        bb += v * v;
+        ab += w * v;
+        for c in 0..3 {
+            ax[c] += w * x[c];
+            bx[c] += v * x[c];
+        }
+    }
+    solve_endpoints(aa, bb, ab, &ax, &bx)
+}
+
+fn solve_endpoints(aa: f32, bb: f32, ab: f32, ax: &Vec3, bx: &Vec3) -> Option<(Vec3, Vec3)> {
+    let det = aa * bb - ab * ab;
+    if det.abs() < 1e-6 {
+        return None;
+    }
+    let mut start = [0.0f32; 3];
+    let mut end = [0.0f32; 3];
+    for c in 0..3 {
+        start[c] = ((ax[c] * bb - bx[c] * ab) / det).clamp(0.0, 255.0);
+        end[c] = ((bx[c] * aa - ax[c] * ab) / det).clamp(0.0, 255.0);
+    }
+    Some((start, end))
+}
+
+// Cluster fit: try every ordered split of the sorted points into as many clusters as
+// there are palette weights, and keep the least-squares endpoints with the lowest error.
+fn cluster_fit(order: &[Vec3], weights: &[f32]) -> (Vec3, Vec3) {
+    let n = order.len();
+    let mut prefix = vec![[0.0f32; 3]; n + 1];
+    for (i, point) in order.iter().enumerate() {
+        for c in 0..3 {
+            prefix[i + 1][c] = prefix[i][c] + point[c];
+        }
+    }
+    let range_sum = |from: usize, to: usize| {
+        [
+            prefix[to][0] - prefix[from][0],
+            prefix[to][1] - prefix[from][1],
+            prefix[to][2] - prefix[from][2],
+        ]
+    };
+
+    let first = order[0];
+    let last = order[n - 1];
+    let mut best = (first, last);
+    let mut best_error = f32::MAX;
+
+    let mut bounds = vec![0; weights.len() + 1];
+    bounds[weights.len()] = n;
+    loop {
+        let (mut aa, mut bb, mut ab) = (0.0f32, 0.0f32, 0.0f32);
+        let mut ax = [0.0f32; 3];
+        let mut bx = [0.0f32; 3];
+        for (cluster, &w) in weights.iter().enumerate() {
+            let (from, to) = (bounds[cluster], bounds[cluster + 1]);
+            if from == to {
+                continue;
+            }
+            let count = (to - from) as f32;
+            let sum = range_sum(from, to);
+            let v = 1.0 - w;
+            aa += count * w * w;
+            bb += count * v * v;
+            ab += count * w * v;
+            for c in 0..3 {
+                ax[c] += w * sum[c];
+                bx[c] += v * sum[c];
+            }
+        }
+        if let Some((a, b)) = solve_endpoints(aa, bb, ab, &ax, &bx) {
+            // the squared error, less the constant sum of |x|^2
+            let mut error = 0.0;
+            for c in 0..3 {
+                error += aa * a[c] * a[c] + 2.0 * ab * a[c] * b[c] + bb * b[c] * b[c]
+                    - 2.0 * (a[c] * ax[c] + b[c] * bx[c]);
+            }
+            if error < best_error {
+                best_error = error;
+                best = (a, b);
+            }
+        }
+        // advance the interior bounds like an odometer, keeping them non-decreasing
+        let mut k = weights.len() - 1;
+        loop {
+            if k == 0 {
+                return best;
+            }
+            if bounds[k] < n {
+                bounds[k] += 1;
+                for j in k + 1..weights.len() {
+                    bounds[j] = bounds[k];
+                }
+                break;
+            }
+            k -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compress::{
+        compress_texels, decode_block_for_test, decompress_texels, CompressOptions, Quality, Texel,
+    };
+    use crate::pixel::PixelFormat;
+    use crate::Dimensions;
+
+    fn assert_near(actual: Texel, expected: Texel) {
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!(
+                (a - e).abs() < 1.0 / 255.0,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn four_colours() {
+        // red and blue endpoints, with the first four texels taking indices 0 to 3
+        let block = [0x00, 0xf8, 0x1f, 0x00, 0xe4, 0x00, 0x00, 0x00];
+        let texels = decode_block_for_test("BC1_RGBA_UNORM", &block);
+        assert_near(texels[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_near(texels[1], [0.0, 0.0, 1.0, 1.0]);
+        assert_near(texels[2], [2.0 / 3.0, 0.0, 1.0 / 3.0, 1.0]);
+        assert_near(texels[3], [1.0 / 3.0, 0.0, 2.0 / 3.0, 1.0]);
+        assert_near(texels[15], [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn three_colours_and_transparent() {
+        // the same endpoints swapped, so that index 2 is the midpoint and 3 is black
+        let block = [0x1f, 0x00, 0x00, 0xf8, 0xe4, 0x00, 0x00, 0x00];
+        let texels = decode_block_for_test("BC1_RGBA_UNORM", &block);
+        assert_near(texels[0], [0.0, 0.0, 1.0, 1.0]);
+        assert_near(texels[2], [0.5, 0.0, 0.5, 1.0]);
+        assert_near(texels[3], [0.0, 0.0, 0.0, 0.0]);
+        // which only punches through when the format has alpha
+        let texels = decode_block_for_test("BC1_RGB_UNORM", &block);
+        assert_near(texels[3], [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn explicit_alpha() {
+        let mut block = [0u8; 16];
+        block[0] = 0xf0;
+        block[8..].copy_from_slice(&[0x00, 0xf8, 0x00, 0xf8, 0, 0, 0, 0]);
+        let texels = decode_block_for_test("BC2_UNORM", &block);
+        assert_near(texels[0], [1.0, 0.0, 0.0, 0.0]);
+        assert_near(texels[1], [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn solid_colour_is_exact() {
+        let format = PixelFormat::for_name("BC1_RGBA_UNORM").unwrap();
+        let texels = vec![[1.0, 0.0, 1.0, 1.0]; 16];
+        let options = CompressOptions::default();
+        let dim = Dimensions::new(4, 4, 0);
+        let block = compress_texels(format, &options, &texels, dim, 1).unwrap();
+        assert_eq!(decompress_texels(format, &block, dim, 1).unwrap(), texels);
+    }
+
+    #[test]
+    fn non_finite_texels() {
+        // NaN reads as zero and infinities as the ends of the range, for every quality
+        let format = PixelFormat::for_name("BC1_RGBA_UNORM").unwrap();
+        let mut texels = vec![[f32::NAN, f32::INFINITY, f32::NEG_INFINITY, 1.0]; 8];
+        texels.extend(vec![[0.0, 1.0, 0.0, 1.0]; 8]);
+        let dim = Dimensions::new(4, 4, 0);
+        for &quality in &[Quality::Fast, Quality::Normal, Quality::Slow] {
+            let options = CompressOptions {
+                quality,
+                ..Default::default()
+            };
+            let block = compress_texels(format, &options, &texels, dim, 1).unwrap();
+            let decoded = decompress_texels(format, &block, dim, 1).unwrap();
+            assert_eq!(decoded, vec![[0.0, 1.0, 0.0, 1.0]; 16], "{:?}", quality);
+        }
+    }
+}
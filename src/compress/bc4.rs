@@ -0,0 +1,349 @@
+use crate::compress::{BlockDecoder, BlockEncoder, CompressOptions, Quality, Texel};
+use crate::pixel::{CompContent, CompLayout, PixelFormat};
+
+// BC4 (one channel) and BC5 (two channels), each channel an independent block
+pub struct Bc4Encoder {
+    quality: Quality,
+    signed: bool,
+    channels: usize,
+    normal_map: bool,
+}
+
+impl Bc4Encoder {
+    pub fn new(target: &PixelFormat, options: &CompressOptions) -> Bc4Encoder {
+        Bc4Encoder {
+            quality: options.quality,
+            signed: target.comp_content == CompContent::SNORM,
+            channels: if target.comp_layout == CompLayout::R8G8 {
+                2
+            } else {
+                1
+            },
+            normal_map: options.normal_map,
+        }
+    }
+}
+
+impl BlockEncoder for Bc4Encoder {
+    fn encode_block(&self, block: &[Texel], out: &mut Vec<u8>) {
+        // channel values in the target's range: [0, 1] for UNORM, [-1, 1] for SNORM
+        let mut channels = vec![Vec::with_capacity(block.len()); self.channels];
+        for texel in block {
+            let (x, y) = if self.normal_map && self.channels == 2 {
+                // normals arrive as [0, 1] colours, which SNORM stores as the vector itself
+                let (x, y) = unit_xy(texel);
+                if self.signed {
+                    (x * 2.0 - 1.0, y * 2.0 - 1.0)
+                } else {
+                    (x, y)
+                }
+            } else {
+                (texel[0], texel[1])
+            };
+            for (c, &value) in [x, y].iter().enumerate().take(self.channels) {
+                channels[c].push(value);
+            }
+        }
+        for values in channels {
+            encode_channel(&values, self.signed, self.quality, out);
+        }
+    }
+}
+
+pub struct Bc4Decoder {
+    signed: bool,
+    channels: usize,
+}
+
+impl Bc4Decoder {
+    pub fn new(format: &PixelFormat) -> Bc4Decoder {
+        let encoder = Bc4Encoder::new(format, &CompressOptions::default());
+        Bc4Decoder {
+            signed: encoder.signed,
+            channels: encoder.channels,
+        }
+    }
+}
+
+impl BlockDecoder for Bc4Decoder {
+    fn block_bytes(&self) -> usize {
+        8 * self.channels
+    }
+
+    // values in the format's range, with missing colour channels zero
+    fn decode_block(&self, block: &[u8], out: &mut [Texel]) {
+        let mut values = [0.0; 16];
+        for texel in out.iter_mut() {
+            *texel = [0.0, 0.0, 0.0, 1.0];
+        }
+        for (c, channel) in block.chunks_exact(8).enumerate().take(self.channels) {
+            decode_channel(channel, self.signed, &mut values);
+            for (texel, value) in out.iter_mut().zip(values.iter()) {
+                texel[c] = *value;
+            }
+        }
+    }
+}
+
+// Normalises the full XYZ vector, so that only X and Y need to be stored and Z can be
+// reconstructed as sqrt(1 - x^2 - y^2). Returns X and Y in [0, 1].
+pub fn unit_xy(texel: &Texel) -> (f32, f32) {
+    let (x, y, z) = (
+        texel[0] * 2.0 - 1.0,
+        texel[1] * 2.0 - 1.0,
+        texel[2] * 2.0 - 1.0,
+    );
+    let length = (x * x + y * y + z * z).sqrt();
+    if length < 1e-6 {
+        return (0.5, 0.5);
+    }
+    ((x / length + 1.0) / 2.0, (y / length + 1.0) / 2.0)
+}
+
+// Encodes 16 values in [0, 1] as a BC4 UNORM block, as also used for BC3 alpha.
+pub fn encode_unorm(values: &[f32], quality: Quality, out: &mut Vec<u8>) {
+    encode_channel(values, false, quality, out);
+}
+
+// Decodes a BC4 UNORM block into 16 values in [0, 1], as also used for BC3 alpha.
+pub fn decode_unorm(block: &[u8], out: &mut [f32]) {
+    decode_channel(block, false, out);
+}
+
+fn decode_channel(block: &[u8], signed: bool, out: &mut [f32]) {
+    let (range, a0, a1) = if signed {
+        // -128 is a second encoding of -127
+        let endpoint = |byte: u8| i32::from(byte as i8).max(-127);
+        (SIGNED, endpoint(block[0]), endpoint(block[1]))
+    } else {
+        (UNSIGNED, i32::from(block[0]), i32::from(block[1]))
+    };
+    let palette = palette(range, a0, a1);
+    let mut bytes = [0u8; 8];
+    bytes[..6].copy_from_slice(&block[2..8]);
+    let bits = u64::from_le_bytes(bytes);
+    for (i, value) in out.iter_mut().enumerate().take(16) {
+        *value = palette[(bits >> (3 * i) & 0x7) as usize] / range.max as f32;
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Range {
+    min: i32,
+    max: i32,
+}
+
+const UNSIGNED: Range = Range { min: 0, max: 255 };
+const SIGNED: Range = Range {
+    min: -127,
+    max: 127,
+};
+
+struct Encoded {
+    error: f32,
+    a0: i32,
+    a1: i32,
+    indices: Vec<u8>,
+}
+
+fn encode_channel(values: &[f32], signed: bool, quality: Quality, out: &mut Vec<u8>) {
+    let range = if signed { SIGNED } else { UNSIGNED };
+    let scale = range.max as f32;
+    let values: Vec<f32> = values
+        .iter()
+        .map(|v| (v * scale).clamp(range.min as f32, scale))
+        .collect();
+
+    let min = values.iter().cloned().fold(scale, f32::min).round() as i32;
+    let max = values
+        .iter()
+        .cloned()
+        .fold(range.min as f32, f32::max)
+        .round() as i32;
+    let radius = match quality {
+        Quality::Fast => 0,
+        Quality::Normal => 2,
+        Quality::Slow => 8,
+    };
+
+    // eight interpolated values between the extremes, signalled by a0 > a1
+    let mut best = search(&values, range, (max, min), radius, true);
+
+    if quality != Quality::Fast {
+        // six interpolated values plus exact min and max, which can leave the interpolated
+        // span to the values that aren't already at an extreme
+        let inner: Vec<f32> = values
+            .iter()
+            .cloned()
+            .filter(|&v| v > range.min as f32 + 0.5 && v < scale - 0.5)
+            .collect();
+        if !inner.is_empty() {
+            let lo = inner.iter().cloned().fold(scale, f32::min).round() as i32;
+            let hi = inner
+                .iter()
+                .cloned()
+                .fold(range.min as f32, f32::max)
+                .round() as i32;
+            let candidate = search(&values, range, (lo, hi), radius, false);
+            if candidate.error < best.error {
+                best = candidate;
+            }
+        }
+    }
+
+    out.push(best.a0 as u8);
+    out.push(best.a1 as u8);
+    let mut bits = 0u64;
+    for (i, &index) in best.indices.iter().enumerate() {
+        bits |= u64::from(index) << (3 * i);
+    }
+    out.extend_from_slice(&bits.to_le_bytes()[..6]);
+}
+
+// Tries endpoint pairs around the given ones, keeping only those that select the
+// requested mode; endpoints just inside or outside the extremes often fit better.
+fn search(values: &[f32], range: Range, (a0, a1): (i32, i32), radius: i32, eight: bool) -> Encoded {
+    let clamp = |v: i32| v.clamp(range.min, range.max);
+    let mut best = evaluate(values, range, clamp(a0), clamp(a1));
+    for d0 in -radius..=radius {
+        for d1 in -radius..=radius {
+            let (c0, c1) = (a0 + d0, a1 + d1);
+            if c0 < range.min || c0 > range.max || c1 < range.min || c1 > range.max {
+                continue;
+            }
+            if (c0 > c1) != eight {
+                continue;
+            }
+            let candidate = evaluate(values, range, c0, c1);
+            if candidate.error < best.error {
+                best = candidate;
+            }
+        }
+    }
+    best
+}
+
+fn palette(range: Range, a0: i32, a1: i32) -> [f32; 8] {
+    let (a, b) = (a0 as f32, a1 as f32);
+    let mut palette = [a, b, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (a * (7 - i) as f32 + b * i as f32) / 7.0;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (a * (5 - i) as f32 + b * i as f32) / 5.0;
+        }
+        palette[6] = range.min as f32;
+        palette[7] = range.max as f32;
+    }
+    palette
+}
+
+fn evaluate(values: &[f32], range: Range, a0: i32, a1: i32) -> Encoded {
+    let palette = palette(range, a0, a1);
+    let mut error = 0.0;
+    let mut indices = Vec::with_capacity(values.len());
+    for value in values {
+        let mut best_index = 0;
+        let mut best_error = f32::MAX;
+        for (index, entry) in palette.iter().enumerate() {
+            let entry_error = (value - entry) * (value - entry);
+            if entry_error < best_error {
+                best_index = index;
+                best_error = entry_error;
+            }
+        }
+        error += best_error;
+        indices.push(best_index as u8);
+    }
+    Encoded {
+        error,
+        a0,
+        a1,
+        indices,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compress::{
+        compress_texels, decode_block_for_test, decompress_texels, CompressOptions, Texel,
+    };
+    use crate::pixel::format_for_test;
+    use crate::Dimensions;
+
+    // indices 0 to 7 for the first eight texels, and 0 for the rest
+    const RAMP: [u8; 6] = [0x88, 0xc6, 0xfa, 0x00, 0x00, 0x00];
+
+    fn assert_reds(texels: &[Texel], expected: &[f32]) {
+        for (i, (texel, e)) in texels.iter().zip(expected).enumerate() {
+            assert!(
+                (texel[0] - e).abs() < 1e-6,
+                "texel {}: {:?} != {}",
+                i,
+                texel,
+                e
+            );
+            assert_eq!(texel[1..], [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn eight_values() {
+        let mut block = vec![255, 0];
+        block.extend_from_slice(&RAMP);
+        let sevenths = [7.0, 0.0, 6.0, 5.0, 4.0, 3.0, 2.0, 1.0, 7.0];
+        let expected: Vec<f32> = sevenths.iter().map(|v| v / 7.0).collect();
+        assert_reds(&decode_block_for_test("BC4_UNORM", &block), &expected);
+    }
+
+    #[test]
+    fn six_values_and_extremes() {
+        let mut block = vec![0, 255];
+        block.extend_from_slice(&RAMP);
+        let expected = [0.0, 1.0, 0.2, 0.4, 0.6, 0.8, 0.0, 1.0, 0.0];
+        assert_reds(&decode_block_for_test("BC4_UNORM", &block), &expected);
+        // signed, where -128 reads as -127
+        let mut block = vec![0x80, 0x7f];
+        block.extend_from_slice(&RAMP);
+        let expected = [-1.0, 1.0, -0.6, -0.2, 0.2, 0.6, -1.0, 1.0, -1.0];
+        assert_reds(&decode_block_for_test("BC4_SNORM", &block), &expected);
+    }
+
+    #[test]
+    fn two_channels() {
+        let block = [255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        for texel in decode_block_for_test("BC5_UNORM", &block) {
+            assert_eq!(texel, [1.0, 0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn signed_values_stay_put() {
+        // as with uncompressed formats, values keep their meaning rather than being
+        // stretched from [0, 1] into [-1, 1]
+        let dim = Dimensions::new(4, 4, 0);
+        let format = format_for_test("BC5_SNORM");
+        let texels: Vec<Texel> = (0..16)
+            .map(|i| [i as f32 / 15.0, -(i as f32) / 15.0, 0.0, 1.0])
+            .collect();
+        let block = compress_texels(format, &Default::default(), &texels, dim, 1).unwrap();
+        let decoded = decompress_texels(format, &block, dim, 1).unwrap();
+        // eight levels for sixteen values leave each pair sharing one
+        for (texel, expected) in decoded.iter().zip(&texels) {
+            assert!((texel[0] - expected[0]).abs() < 0.06, "{:?}", texel);
+            assert!((texel[1] - expected[1]).abs() < 0.06, "{:?}", texel);
+        }
+
+        // normal maps are the exception: their [0, 1] colours hold vectors
+        let options = CompressOptions {
+            normal_map: true,
+            ..Default::default()
+        };
+        let block = compress_texels(format, &options, &[[1.0, 0.5, 0.5, 1.0]; 16], dim, 1);
+        let decoded = decompress_texels(format, &block.unwrap(), dim, 1).unwrap();
+        assert!((decoded[0][0] - 1.0).abs() < 0.01, "{:?}", decoded[0]);
+        assert!(decoded[0][1].abs() < 0.01, "{:?}", decoded[0]);
+    }
+}
@@ -0,0 +1,811 @@
+use crate::compress::bptc::{self, BitReader, BitWriter};
+use crate::compress::{
+    principal_axis, BlockDecoder, BlockEncoder, CompressOptions, Quality, Texel,
+};
+use crate::pixel::float::{f16_to_f32, f32_to_f16};
+use crate::pixel::{CompContent, PixelFormat};
+
+// An endpoint channel as (endpoint, channel). Endpoints 0 and 1 belong to the first
+// subset, 2 and 3 to the second.
+type Field = (usize, usize);
+
+const R0: Field = (0, 0);
+const G0: Field = (0, 1);
+const B0: Field = (0, 2);
+const R1: Field = (1, 0);
+const G1: Field = (1, 1);
+const B1: Field = (1, 2);
+const R2: Field = (2, 0);
+const G2: Field = (2, 1);
+const B2: Field = (2, 2);
+const R3: Field = (3, 0);
+const G3: Field = (3, 1);
+const B3: Field = (3, 2);
+
+struct Mode {
+    value: u32,
+    mode_bits: u32,
+    subsets: usize,
+    endpoint_bits: u32,
+    // bits of each channel's delta from endpoint 0; None where endpoints are stored as is
+    delta_bits: Option<[u32; 3]>,
+    // The header in stream order, as a field with the first and last of its bits to
+    // write. Fields that the spec stores reversed have first > last.
+    layout: &'static [(Field, u32, u32)],
+}
+
+#[rustfmt::skip]
+const MODES: [Mode; 14] = [
+    Mode {
+        value: 0x00, mode_bits: 2, subsets: 2, endpoint_bits: 10, delta_bits: Some([5, 5, 5]),
+        layout: &[
+            (G2, 4, 4), (B2, 4, 4), (B3, 4, 4), (R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 4),
+            (G3, 4, 4), (G2, 0, 3), (G1, 0, 4), (B3, 0, 0), (G3, 0, 3), (B1, 0, 4), (B3, 1, 1),
+            (B2, 0, 3), (R2, 0, 4), (B3, 2, 2), (R3, 0, 4), (B3, 3, 3),
+        ],
+    },
+    Mode {
+        value: 0x01, mode_bits: 2, subsets: 2, endpoint_bits: 7, delta_bits: Some([6, 6, 6]),
+        layout: &[
+            (G2, 5, 5), (G3, 4, 4), (G3, 5, 5), (R0, 0, 6), (B3, 0, 0), (B3, 1, 1), (B2, 4, 4),
+            (G0, 0, 6), (B2, 5, 5), (B3, 2, 2), (G2, 4, 4), (B0, 0, 6), (B3, 3, 3), (B3, 5, 5),
+            (B3, 4, 4), (R1, 0, 5), (G2, 0, 3), (G1, 0, 5), (G3, 0, 3), (B1, 0, 5), (B2, 0, 3),
+            (R2, 0, 5), (R3, 0, 5),
+        ],
+    },
+    Mode {
+        value: 0x02, mode_bits: 5, subsets: 2, endpoint_bits: 11, delta_bits: Some([5, 4, 4]),
+        layout: &[
+            (R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 4), (R0, 10, 10), (G2, 0, 3), (G1, 0, 3),
+            (G0, 10, 10), (B3, 0, 0), (G3, 0, 3), (B1, 0, 3), (B0, 10, 10), (B3, 1, 1),
+            (B2, 0, 3), (R2, 0, 4), (B3, 2, 2), (R3, 0, 4), (B3, 3, 3),
+        ],
+    },
+    Mode {
+        value: 0x06, mode_bits: 5, subsets: 2, endpoint_bits: 11, delta_bits: Some([4, 5, 4]),
+        layout: &[
+            (R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 3), (R0, 10, 10), (G3, 4, 4), (G2, 0, 3),
+            (G1, 0, 4), (G0, 10, 10), (G3, 0, 3), (B1, 0, 3), (B0, 10, 10), (B3, 1, 1),
+            (B2, 0, 3), (R2, 0, 3), (B3, 0, 0), (B3, 2, 2), (R3, 0, 3), (G2, 4, 4), (B3, 3, 3),
+        ],
+    },
+    Mode {
+        value: 0x0a, mode_bits: 5, subsets: 2, endpoint_bits: 11, delta_bits: Some([4, 4, 5]),
+        layout: &[
+            (R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 3), (R0, 10, 10), (B2, 4, 4), (G2, 0, 3),
+            (G1, 0, 3), (G0, 10, 10), (B3, 0, 0), (G3, 0, 3), (B1, 0, 4), (B0, 10, 10),
+            (B2, 0, 3), (R2, 0, 3), (B3, 1, 1), (B3, 2, 2), (R3, 0, 3), (B3, 4, 4), (B3, 3, 3),
+        ],
+    },
+    Mode {
+        value: 0x0e, mode_bits: 5, subsets: 2, endpoint_bits: 9, delta_bits: Some([5, 5, 5]),
+        layout: &[
+            (R0, 0, 8), (B2, 4, 4), (G0, 0, 8), (G2, 4, 4), (B0, 0, 8), (B3, 4, 4), (R1, 0, 4),
+            (G3, 4, 4), (G2, 0, 3), (G1, 0, 4), (B3, 0, 0), (G3, 0, 3), (B1, 0, 4), (B3, 1, 1),
+            (B2, 0, 3), (R2, 0, 4), (B3, 2, 2), (R3, 0, 4), (B3, 3, 3),
+        ],
+    },
+    Mode {
+        value: 0x12, mode_bits: 5, subsets: 2, endpoint_bits: 8, delta_bits: Some([6, 5, 5]),
+        layout: &[
+            (R0, 0, 7), (G3, 4, 4), (B2, 4, 4), (G0, 0, 7), (B3, 2, 2), (G2, 4, 4), (B0, 0, 7),
+            (B3, 3, 3), (B3, 4, 4), (R1, 0, 5), (G2, 0, 3), (G1, 0, 4), (B3, 0, 0), (G3, 0, 3),
+            (B1, 0, 4), (B3, 1, 1), (B2, 0, 3), (R2, 0, 5), (R3, 0, 5),
+        ],
+    },
+    Mode {
+        value: 0x16, mode_bits: 5, subsets: 2, endpoint_bits: 8, delta_bits: Some([5, 6, 5]),
+        layout: &[
+            (R0, 0, 7), (B3, 0, 0), (B2, 4, 4), (G0, 0, 7), (G2, 5, 5), (G2, 4, 4), (B0, 0, 7),
+            (G3, 5, 5), (B3, 4, 4), (R1, 0, 4), (G3, 4, 4), (G2, 0, 3), (G1, 0, 5), (G3, 0, 3),
+            (B1, 0, 4), (B3, 1, 1), (B2, 0, 3), (R2, 0, 4), (B3, 2, 2), (R3, 0, 4), (B3, 3, 3),
+        ],
+    },
+    Mode {
+        value: 0x1a, mode_bits: 5, subsets: 2, endpoint_bits: 8, delta_bits: Some([5, 5, 6]),
+        layout: &[
+            (R0, 0, 7), (B3, 1, 1), (B2, 4, 4), (G0, 0, 7), (B2, 5, 5), (G2, 4, 4), (B0, 0, 7),
+            (B3, 5, 5), (B3, 4, 4), (R1, 0, 4), (G3, 4, 4), (G2, 0, 3), (G1, 0, 4), (B3, 0, 0),
+            (G3, 0, 3), (B1, 0, 5), (B2, 0, 3), (R2, 0, 4), (B3, 2, 2), (R3, 0, 4), (B3, 3, 3),
+        ],
+    },
+    Mode {
+        value: 0x1e, mode_bits: 5, subsets: 2, endpoint_bits: 6, delta_bits: None,
+        layout: &[
+            (R0, 0, 5), (G3, 4, 4), (B3, 0, 0), (B3, 1, 1), (B2, 4, 4), (G0, 0, 5), (G2, 5, 5),
+            (B2, 5, 5), (B3, 2, 2), (G2, 4, 4), (B0, 0, 5), (G3, 5, 5), (B3, 3, 3), (B3, 5, 5),
+            (B3, 4, 4), (R1, 0, 5), (G2, 0, 3), (G1, 0, 5), (G3, 0, 3), (B1, 0, 5), (B2, 0, 3),
+            (R2, 0, 5), (R3, 0, 5),
+        ],
+    },
+    Mode {
+        value: 0x03, mode_bits: 5, subsets: 1, endpoint_bits: 10, delta_bits: None,
+        layout: &[(R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 9), (G1, 0, 9), (B1, 0, 9)],
+    },
+    Mode {
+        value: 0x07, mode_bits: 5, subsets: 1, endpoint_bits: 11, delta_bits: Some([9, 9, 9]),
+        layout: &[
+            (R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 8), (R0, 10, 10), (G1, 0, 8),
+            (G0, 10, 10), (B1, 0, 8), (B0, 10, 10),
+        ],
+    },
+    Mode {
+        value: 0x0b, mode_bits: 5, subsets: 1, endpoint_bits: 12, delta_bits: Some([8, 8, 8]),
+        layout: &[
+            (R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 7), (R0, 11, 10), (G1, 0, 7),
+            (G0, 11, 10), (B1, 0, 7), (B0, 11, 10),
+        ],
+    },
+    Mode {
+        value: 0x0f, mode_bits: 5, subsets: 1, endpoint_bits: 16, delta_bits: Some([4, 4, 4]),
+        layout: &[
+            (R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 3), (R0, 15, 10), (G1, 0, 3),
+            (G0, 15, 10), (B1, 0, 3), (B0, 15, 10),
+        ],
+    },
+];
+
+// BC6H has 32 two-subset partitions, the first half of BC7's
+const PARTITIONS: usize = 32;
+
+// largest finite half
+const HALF_MAX: f32 = 65504.0;
+
+const UNIFORM_WEIGHTS: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+pub struct Bc6hEncoder {
+    quality: Quality,
+    signed: bool,
+}
+
+impl Bc6hEncoder {
+    pub fn new(target: &PixelFormat, options: &CompressOptions) -> Bc6hEncoder {
+        Bc6hEncoder {
+            quality: options.quality,
+            signed: target.comp_content == CompContent::SFLOAT,
+        }
+    }
+}
+
+impl BlockEncoder for Bc6hEncoder {
+    fn encode_block(&self, block: &[Texel], out: &mut Vec<u8>) {
+        // Everything below works on half bit patterns read as integers, with the sign
+        // applied for SF16. Those are close to logarithmic in the value, so errors
+        // measured on them weigh dark and bright texels alike.
+        let texels: Vec<Texel> = block
+            .iter()
+            .map(|t| [self.half(t[0]), self.half(t[1]), self.half(t[2]), 0.0])
+            .collect();
+
+        let (partitions, refinements) = match self.quality {
+            Quality::Fast => (0, 0),
+            Quality::Normal => (4, 1),
+            Quality::Slow => (12, 2),
+        };
+
+        let mut best = self.encode_mode(10, 0, &texels, refinements);
+        for mode in 11..14 {
+            keep_best(&mut best, self.encode_mode(mode, 0, &texels, refinements));
+        }
+        if partitions > 0 {
+            for partition in rank_partitions(&texels, partitions) {
+                for mode in 0..10 {
+                    let candidate = self.encode_mode(mode, partition, &texels, refinements);
+                    keep_best(&mut best, candidate);
+                }
+            }
+        }
+        best.write(out);
+    }
+}
+
+pub struct Bc6hDecoder {
+    signed: bool,
+}
+
+impl Bc6hDecoder {
+    pub fn new(format: &PixelFormat) -> Bc6hDecoder {
+        Bc6hDecoder {
+            signed: format.comp_content == CompContent::SFLOAT,
+        }
+    }
+
+    fn extend(&self, value: i32, bits: u32) -> i32 {
+        if self.signed {
+            sign_extend(value, bits)
+        } else {
+            value
+        }
+    }
+}
+
+impl BlockDecoder for Bc6hDecoder {
+    fn block_bytes(&self) -> usize {
+        16
+    }
+
+    // the inverse of Block::write; the four reserved modes decode as zero
+    fn decode_block(&self, block: &[u8], out: &mut [Texel]) {
+        let mode_bits = if block[0] & 0x3 < 2 { 2 } else { 5 };
+        let value = u32::from(block[0]) & ((1 << mode_bits) - 1);
+        let mode = match MODES
+            .iter()
+            .find(|mode| mode.mode_bits == mode_bits && mode.value == value)
+        {
+            Some(mode) => mode,
+            None => {
+                for texel in out.iter_mut() {
+                    *texel = [0.0, 0.0, 0.0, 1.0];
+                }
+                return;
+            }
+        };
+
+        let mut reader = BitReader::new(block);
+        reader.read(mode_bits);
+        let mut endpoints = [[0i32; 3]; 4];
+        for &((endpoint, channel), first, last) in mode.layout {
+            let mut read_bit =
+                |bit: u32| endpoints[endpoint][channel] |= (reader.read(1) << bit) as i32;
+            if first <= last {
+                (first..=last).for_each(&mut read_bit);
+            } else {
+                (last..=first).rev().for_each(&mut read_bit);
+            }
+        }
+        let partition = if mode.subsets == 2 {
+            reader.read(5) as usize
+        } else {
+            0
+        };
+
+        let bits = mode.endpoint_bits;
+        let base = endpoints[0].map(|value| self.extend(value, bits));
+        endpoints[0] = base;
+        for endpoint in &mut endpoints[1..2 * mode.subsets] {
+            for c in 0..3 {
+                endpoint[c] = match mode.delta_bits {
+                    Some(delta_bits) => {
+                        let delta = sign_extend(endpoint[c], delta_bits[c]);
+                        self.extend((base[c] + delta) & ((1 << bits) - 1), bits)
+                    }
+                    None => self.extend(endpoint[c], bits),
+                };
+            }
+        }
+
+        let index_bits = index_bits(mode);
+        let palettes: Vec<Vec<Texel>> = (0..mode.subsets)
+            .map(|subset| {
+                palette(
+                    &endpoints[2 * subset],
+                    &endpoints[2 * subset + 1],
+                    mode,
+                    self.signed,
+                )
+            })
+            .collect();
+        let subset_of = bptc::partition(mode.subsets, partition);
+        for (i, texel) in out.iter_mut().enumerate() {
+            let anchor = bptc::is_anchor(mode.subsets, partition, i);
+            let index = reader.read(index_bits - anchor as u32) as usize;
+            let entry = palettes[subset_of[i] as usize][index];
+            for c in 0..3 {
+                // back from signed half patterns to the halves they stand for
+                let pattern = entry[c] as i32;
+                let half = if pattern < 0 {
+                    0x8000 | -pattern
+                } else {
+                    pattern
+                };
+                texel[c] = f16_to_f32(half as u16);
+            }
+            texel[3] = 1.0;
+        }
+    }
+}
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (value << shift) >> shift
+}
+
+fn keep_best(best: &mut Block, candidate: Block) {
+    if candidate.error < best.error {
+        *best = candidate;
+    }
+}
+
+struct Block {
+    error: f32,
+    mode: usize,
+    partition: usize,
+    // quantised endpoints, before any delta encoding
+    endpoints: [[i32; 3]; 4],
+    indices: [u32; 16],
+}
+
+impl Block {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mode = &MODES[self.mode];
+        let mut stored = self.endpoints;
+        if mode.delta_bits.is_some() {
+            for endpoint in &mut stored[1..2 * mode.subsets] {
+                for (value, base) in endpoint.iter_mut().zip(&self.endpoints[0]) {
+                    *value -= base;
+                }
+            }
+        }
+
+        let mut writer = BitWriter::new();
+        writer.write(mode.value, mode.mode_bits);
+        for &((endpoint, channel), first, last) in mode.layout {
+            let value = stored[endpoint][channel];
+            // negative values are written as two's complement
+            let mut write_bit = |bit: u32| writer.write((value >> bit) as u32 & 1, 1);
+            if first <= last {
+                (first..=last).for_each(&mut write_bit);
+            } else {
+                (last..=first).rev().for_each(&mut write_bit);
+            }
+        }
+        if mode.subsets == 2 {
+            writer.write(self.partition as u32, 5);
+        }
+
+        let index_bits = index_bits(mode);
+        for (texel, &index) in self.indices.iter().enumerate() {
+            let anchor = bptc::is_anchor(mode.subsets, self.partition, texel);
+            writer.write(index, index_bits - anchor as u32);
+        }
+        writer.finish(out);
+    }
+}
+
+fn index_bits(mode: &Mode) -> u32 {
+    if mode.subsets == 2 {
+        3
+    } else {
+        4
+    }
+}
+
+// Orders partitions by how well each subset is approximated by a line.
+fn rank_partitions(texels: &[Texel], keep: usize) -> Vec<usize> {
+    let mut scored: Vec<(f32, usize)> = (0..PARTITIONS)
+        .map(|partition| {
+            let subset_of = bptc::partition(2, partition);
+            let residual = (0..2)
+                .map(|subset| {
+                    let members: Vec<Texel> = (0..16)
+                        .filter(|&i| subset_of[i] as usize == subset)
+                        .map(|i| texels[i])
+                        .collect();
+                    bptc::line_residual(&members, 0, 3, &UNIFORM_WEIGHTS)
+                })
+                .sum();
+            (residual, partition)
+        })
+        .collect();
+    scored.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    scored
+        .iter()
+        .take(keep)
+        .map(|&(_, partition)| partition)
+        .collect()
+}
+
+impl Bc6hEncoder {
+    // NaNs become zero, and values are clamped to the finite range of the format
+    fn half(&self, value: f32) -> f32 {
+        if value.is_nan() {
+            return 0.0;
+        }
+        let min = if self.signed { -HALF_MAX } else { 0.0 };
+        let bits = f32_to_f16(value.clamp(min, HALF_MAX));
+        let magnitude = f32::from(bits & 0x7fff);
+        if bits & 0x8000 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    fn encode_mode(
+        &self,
+        mode_index: usize,
+        partition: usize,
+        texels: &[Texel],
+        refinements: usize,
+    ) -> Block {
+        let mode = &MODES[mode_index];
+        let subset_of = bptc::partition(mode.subsets, partition);
+        let members: Vec<Vec<usize>> = (0..mode.subsets)
+            .map(|subset| {
+                (0..16)
+                    .filter(|&i| subset_of[i] as usize == subset)
+                    .collect()
+            })
+            .collect();
+
+        let mut ends = [[0.0f32; 3]; 4];
+        for (subset, members) in members.iter().enumerate() {
+            let subset_texels: Vec<Texel> = members.iter().map(|&i| texels[i]).collect();
+            let (start, end) = principal_endpoints(&subset_texels);
+            ends[2 * subset] = start;
+            ends[2 * subset + 1] = end;
+        }
+        let mut best = self.quantise_and_index(mode_index, partition, texels, &ends);
+
+        for _ in 0..refinements {
+            let weights = bptc::weights(index_bits(mode));
+            let mut refined = ends;
+            for (subset, members) in members.iter().enumerate() {
+                let fitted = least_squares(members.iter().map(|&i| {
+                    let t = weights[best.indices[i] as usize] as f32 / 64.0;
+                    (t, &texels[i])
+                }));
+                if let Some((start, end)) = fitted {
+                    refined[2 * subset] = start;
+                    refined[2 * subset + 1] = end;
+                }
+            }
+            let candidate = self.quantise_and_index(mode_index, partition, texels, &refined);
+            if candidate.error < best.error {
+                best = candidate;
+                ends = refined;
+            } else {
+                break;
+            }
+        }
+        best
+    }
+
+    fn quantise_and_index(
+        &self,
+        mode_index: usize,
+        partition: usize,
+        texels: &[Texel],
+        ends: &[[f32; 3]; 4],
+    ) -> Block {
+        let mode = &MODES[mode_index];
+        let bits = mode.endpoint_bits;
+        let index_bits = index_bits(mode);
+        let half = 1 << (index_bits - 1);
+        let anchors = bptc::anchors(mode.subsets, partition);
+
+        let mut endpoints = [[0i32; 3]; 4];
+        for (quantised, end) in endpoints.iter_mut().zip(ends).take(2 * mode.subsets) {
+            for c in 0..3 {
+                quantised[c] = self.quantise(end[c], bits);
+            }
+        }
+
+        // the anchor texel's index must have a clear top bit, which swapping endpoints fixes
+        for (subset, &anchor) in anchors.iter().enumerate().take(mode.subsets) {
+            let palette = palette(
+                &endpoints[2 * subset],
+                &endpoints[2 * subset + 1],
+                mode,
+                self.signed,
+            );
+            if nearest(&palette, &texels[anchor]).0 >= half {
+                endpoints.swap(2 * subset, 2 * subset + 1);
+            }
+        }
+
+        // pull endpoints towards the first until their deltas fit
+        if let Some(delta_bits) = mode.delta_bits {
+            let base = endpoints[0];
+            for endpoint in &mut endpoints[1..2 * mode.subsets] {
+                for c in 0..3 {
+                    let limit = 1 << (delta_bits[c] - 1);
+                    endpoint[c] = base[c] + (endpoint[c] - base[c]).clamp(-limit, limit - 1);
+                }
+            }
+        }
+
+        // anchors were oriented before any clamping, so keep them in the lower half
+        let subset_of = bptc::partition(mode.subsets, partition);
+        let palettes: Vec<Vec<Texel>> = (0..mode.subsets)
+            .map(|subset| {
+                palette(
+                    &endpoints[2 * subset],
+                    &endpoints[2 * subset + 1],
+                    mode,
+                    self.signed,
+                )
+            })
+            .collect();
+        let mut error = 0.0;
+        let mut indices = [0u32; 16];
+        for (texel, index) in indices.iter_mut().enumerate() {
+            let palette = &palettes[subset_of[texel] as usize];
+            let (nearest_index, nearest_error) = if bptc::is_anchor(mode.subsets, partition, texel)
+            {
+                nearest(&palette[..half], &texels[texel])
+            } else {
+                nearest(palette, &texels[texel])
+            };
+            *index = nearest_index as u32;
+            error += nearest_error;
+        }
+
+        Block {
+            error,
+            mode: mode_index,
+            partition,
+            endpoints,
+            indices,
+        }
+    }
+
+    // Maps a half pattern to the endpoint precision of a mode, inverting unquantise and
+    // the final scaling by 31/64 (or 31/32 for SF16) that decoders apply.
+    fn quantise(&self, value: f32, bits: u32) -> i32 {
+        let (value, max) = if self.signed {
+            (value * 32.0 / 31.0, (1 << (bits - 1)) - 1)
+        } else {
+            (value.max(0.0) * 64.0 / 31.0, (1 << bits) - 1)
+        };
+        if bits >= 16 || (!self.signed && bits >= 15) {
+            let limit = if self.signed { 0x7fff } else { 0xffff };
+            return (value.round() as i32).clamp(-limit, limit);
+        }
+        // levels aren't evenly spread at the ends, so take whichever of the two around the
+        // value decodes nearer
+        let target = value.abs().round() as i32;
+        let below = (target >> (16 - bits)).min(max);
+        let magnitude = (below..=(below + 1).min(max))
+            .min_by_key(|&level| (unquantise(level, bits, self.signed) - target).abs())
+            .unwrap_or(below);
+        if value < 0.0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+}
+
+fn unquantise(value: i32, bits: u32, signed: bool) -> i32 {
+    if signed {
+        if bits >= 16 {
+            return value;
+        }
+        let magnitude = value.abs();
+        let unquantised = if magnitude == 0 {
+            0
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7fff
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if value < 0 {
+            -unquantised
+        } else {
+            unquantised
+        }
+    } else if bits >= 15 {
+        value
+    } else if value == 0 {
+        0
+    } else if value == (1 << bits) - 1 {
+        0xffff
+    } else {
+        ((value << 16) + 0x8000) >> bits
+    }
+}
+
+// the decoded values of a subset, as signed half patterns
+fn palette(start: &[i32; 3], end: &[i32; 3], mode: &Mode, signed: bool) -> Vec<Texel> {
+    let bits = mode.endpoint_bits;
+    let a: Vec<i32> = start.iter().map(|&v| unquantise(v, bits, signed)).collect();
+    let b: Vec<i32> = end.iter().map(|&v| unquantise(v, bits, signed)).collect();
+    bptc::weights(index_bits(mode))
+        .iter()
+        .map(|&w| {
+            let w = w as i32;
+            let mut entry = [0.0f32; 4];
+            for c in 0..3 {
+                let interpolated = ((64 - w) * a[c] + w * b[c] + 32) >> 6;
+                entry[c] = finish(interpolated, signed) as f32;
+            }
+            entry
+        })
+        .collect()
+}
+
+fn finish(value: i32, signed: bool) -> i32 {
+    if !signed {
+        (value * 31) >> 6
+    } else if value < 0 {
+        -((-value * 31) >> 5)
+    } else {
+        (value * 31) >> 5
+    }
+}
+
+fn nearest(palette: &[Texel], texel: &Texel) -> (usize, f32) {
+    let mut best = (0, f32::MAX);
+    for (index, entry) in palette.iter().enumerate() {
+        let error: f32 = (0..3)
+            .map(|c| (texel[c] - entry[c]) * (texel[c] - entry[c]))
+            .sum();
+        if error < best.1 {
+            best = (index, error);
+        }
+    }
+    best
+}
+
+fn principal_endpoints(texels: &[Texel]) -> ([f32; 3], [f32; 3]) {
+    let (mean, axis) = principal_axis(texels, 0, 3);
+    let project = |texel: &Texel| (0..3).map(|c| (texel[c] - mean[c]) * axis[c]).sum::<f32>();
+    let (mut min, mut max) = (0.0f32, 0.0f32);
+    for texel in texels {
+        let t = project(texel);
+        min = min.min(t);
+        max = max.max(t);
+    }
+    let mut start = [0.0f32; 3];
+    let mut end = [0.0f32; 3];
+    for c in 0..3 {
+        start[c] = mean[c] + axis[c] * min;
+        end[c] = mean[c] + axis[c] * max;
+    }
+    (start, end)
+}
+
+// Solves for endpoints a, b minimising sum |(1 - t) * a + t * b - x|^2 over the
+// (t, x) pairs.
+fn least_squares<'a, I>(samples: I) -> Option<([f32; 3], [f32; 3])>
+where
+    I: Iterator<Item = (f32, &'a Texel)>,
+{
+    let (mut aa, mut bb, mut ab) = (0.0f32, 0.0f32, 0.0f32);
+    let mut ax = [0.0f32; 3];
+    let mut bx = [0.0f32; 3];
+    for (t, texel) in samples {
+        let s = 1.0 - t;
+        aa += s * s;
+        bb += t * t;
+        ab += s * t;
+        for c in 0..3 {
+            ax[c] += s * texel[c];
+            bx[c] += t * texel[c];
+        }
+    }
+    let det = aa * bb - ab * ab;
+    if det.abs() < 1e-6 {
+        return None;
+    }
+    let mut start = [0.0f32; 3];
+    let mut end = [0.0f32; 3];
+    for c in 0..3 {
+        start[c] = (ax[c] * bb - bx[c] * ab) / det;
+        end[c] = (bx[c] * aa - ax[c] * ab) / det;
+    }
+    Some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compress::bptc::BitWriter;
+    use crate::compress::{
+        decode_block_for_test, rmse_for_test, round_trip_for_test, CompressOptions, Quality, Texel,
+    };
+    use crate::Dimensions;
+
+    // largest finite half, which the top endpoint value decodes to
+    const MAX: f32 = 65504.0;
+
+    fn block(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        for &(value, count) in fields {
+            writer.write(value, count);
+        }
+        let mut block = vec![];
+        writer.finish(&mut block);
+        block
+    }
+
+    // mode 11: one region with 10-bit endpoints stored as they are, and index 0 for the
+    // first texel and 15 for the rest
+    fn one_region(start: [u32; 3], end: [u32; 3]) -> Vec<u8> {
+        let mut fields = vec![(0x03, 5)];
+        fields.extend(start.iter().chain(&end).map(|&value| (value, 10)));
+        fields.push((0, 3));
+        fields.extend([(15, 4); 15].iter());
+        block(&fields)
+    }
+
+    #[test]
+    fn one_region_unsigned() {
+        // 495 decodes to 31 * 495 + 15 = 0x3c00, which is 1.0
+        let texels = decode_block_for_test("BC6H_UFLOAT", &one_region([495, 0, 1023], [0, 495, 0]));
+        assert_eq!(texels[0], [1.0, 0.0, MAX, 1.0]);
+        for texel in &texels[1..] {
+            assert_eq!(*texel, [0.0, 1.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn one_region_signed() {
+        // 10-bit two's complement, so 0x201 is -511, the most negative value
+        let texels =
+            decode_block_for_test("BC6H_SFLOAT", &one_region([0x201, 0, 511], [0, 0x201, 0]));
+        assert_eq!(texels[0], [-MAX, 0.0, MAX, 1.0]);
+        for texel in &texels[1..] {
+            assert_eq!(*texel, [0.0, -MAX, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn two_regions() {
+        // mode 10 with 6-bit endpoints, red at its top in the first subset and green in
+        // the second. The header scatters the high bits of the second subset's fields.
+        let block = block(&[
+            (0x1e, 5),
+            (63, 6), // R0
+            (0, 4),
+            (0, 6), // G0
+            (1, 1), // G2 bit 5
+            (0, 2),
+            (1, 1), // G2 bit 4
+            (0, 6), // B0
+            (0, 4),
+            (0, 6),  // R1
+            (15, 4), // G2 bits 0 to 3
+            (0, 6),
+            (0, 4),
+            (0, 6),
+            (0, 4),
+            (0, 6),
+            (0, 6),
+            (0, 5), // partition 0, whose second subset is the right half of each row
+            (0, 46),
+        ]);
+        let texels = decode_block_for_test("BC6H_UFLOAT", &block);
+        for (i, texel) in texels.iter().enumerate() {
+            let expected = if i % 4 < 2 {
+                [MAX, 0.0, 0.0, 1.0]
+            } else {
+                [0.0, MAX, 0.0, 1.0]
+            };
+            assert_eq!(*texel, expected, "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn reserved_modes() {
+        let texels = decode_block_for_test("BC6H_UFLOAT", &block(&[(0x13, 5), (0, 123)]));
+        assert_eq!(texels, vec![[0.0, 0.0, 0.0, 1.0]; 16]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let dim = Dimensions::new(8, 8, 0);
+        for (name, sign) in [("BC6H_UFLOAT", 1.0), ("BC6H_SFLOAT", -1.0)].iter() {
+            let texels: Vec<Texel> = (0..64)
+                .map(|i| {
+                    let (x, y) = ((i % 8) as f32 / 7.0, (i / 8) as f32 / 7.0);
+                    [sign * (0.5 + 2.0 * x), 1.0 + y, 0.25 + (x + y) / 2.0, 1.0]
+                })
+                .collect();
+            let errors: Vec<f32> = [Quality::Fast, Quality::Normal, Quality::Slow]
+                .iter()
+                .map(|&quality| {
+                    let options = CompressOptions {
+                        quality,
+                        ..Default::default()
+                    };
+                    let (_, decoded) = round_trip_for_test(name, &options, &texels, dim);
+                    rmse_for_test(&decoded, &texels, 3)
+                })
+                .collect();
+            assert!(errors[0] < 0.15, "{} fast: {:?}", name, errors);
+            assert!(errors[1] < 0.08, "{} normal: {:?}", name, errors);
+            assert!(errors[2] <= errors[1], "{} slow: {:?}", name, errors);
+        }
+    }
+}
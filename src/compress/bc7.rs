@@ -0,0 +1,916 @@
+use crate::compress::bptc::{self, BitReader, BitWriter};
+use crate::compress::{
+    principal_axis, BlockDecoder, BlockEncoder, CompressOptions, Quality, Texel,
+};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum PBits {
+    None,
+    PerEndpoint,
+    Shared,
+}
+
+struct Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    colour_bits: u32,
+    alpha_bits: u32,
+    pbits: PBits,
+    index_bits: u32,
+    index_bits2: u32,
+}
+
+const MODES: [Mode; 8] = [
+    Mode {
+        subsets: 3,
+        partition_bits: 4,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 4,
+        alpha_bits: 0,
+        pbits: PBits::PerEndpoint,
+        index_bits: 3,
+        index_bits2: 0,
+    },
+    Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 6,
+        alpha_bits: 0,
+        pbits: PBits::Shared,
+        index_bits: 3,
+        index_bits2: 0,
+    },
+    Mode {
+        subsets: 3,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 5,
+        alpha_bits: 0,
+        pbits: PBits::None,
+        index_bits: 2,
+        index_bits2: 0,
+    },
+    Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 7,
+        alpha_bits: 0,
+        pbits: PBits::PerEndpoint,
+        index_bits: 2,
+        index_bits2: 0,
+    },
+    Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_selection_bits: 1,
+        colour_bits: 5,
+        alpha_bits: 6,
+        pbits: PBits::None,
+        index_bits: 2,
+        index_bits2: 3,
+    },
+    Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_selection_bits: 0,
+        colour_bits: 7,
+        alpha_bits: 8,
+        pbits: PBits::None,
+        index_bits: 2,
+        index_bits2: 2,
+    },
+    Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 7,
+        alpha_bits: 7,
+        pbits: PBits::PerEndpoint,
+        index_bits: 4,
+        index_bits2: 0,
+    },
+    Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 5,
+        alpha_bits: 5,
+        pbits: PBits::PerEndpoint,
+        index_bits: 2,
+        index_bits2: 0,
+    },
+];
+
+// Rec. 601 luma weights for colour, with alpha weighted like luma
+const PERCEPTUAL_WEIGHTS: [f32; 4] = [0.299, 0.587, 0.114, 1.0];
+const UNIFORM_WEIGHTS: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+pub struct Bc7Encoder {
+    quality: Quality,
+    weights: [f32; 4],
+}
+
+impl Bc7Encoder {
+    pub fn new(options: &CompressOptions) -> Bc7Encoder {
+        Bc7Encoder {
+            quality: options.quality,
+            weights: if options.perceptual {
+                PERCEPTUAL_WEIGHTS
+            } else {
+                UNIFORM_WEIGHTS
+            },
+        }
+    }
+}
+
+// how hard to look: partitions tried per partitioned mode, least-squares refinements,
+// and whether to try every p-bit combination rather than the likeliest
+#[derive(Copy, Clone)]
+struct Effort {
+    partitions: usize,
+    refinements: usize,
+    exhaustive_pbits: bool,
+}
+
+impl BlockEncoder for Bc7Encoder {
+    fn encode_block(&self, block: &[Texel], out: &mut Vec<u8>) {
+        let texels: Vec<Texel> = block
+            .iter()
+            .map(|t| {
+                [
+                    t[0].clamp(0.0, 1.0) * 255.0,
+                    t[1].clamp(0.0, 1.0) * 255.0,
+                    t[2].clamp(0.0, 1.0) * 255.0,
+                    t[3].clamp(0.0, 1.0) * 255.0,
+                ]
+            })
+            .collect();
+        let opaque = texels.iter().all(|t| t[3] > 254.5);
+
+        let effort = match self.quality {
+            Quality::Fast => Effort {
+                partitions: 0,
+                refinements: 0,
+                exhaustive_pbits: false,
+            },
+            Quality::Normal => Effort {
+                partitions: 4,
+                refinements: 1,
+                exhaustive_pbits: false,
+            },
+            Quality::Slow => Effort {
+                partitions: 16,
+                refinements: 2,
+                exhaustive_pbits: true,
+            },
+        };
+
+        // mode 6 handles anything reasonably, and is all we try when in a hurry
+        let mut best = self.encode_joint(6, 0, &texels, effort);
+        if self.quality != Quality::Fast {
+            let (rotations, selections) = if self.quality == Quality::Slow {
+                (0..4, 0..2)
+            } else {
+                (0..1, 0..1)
+            };
+            for rotation in rotations {
+                keep_best(
+                    &mut best,
+                    self.encode_split(5, rotation, 0, &texels, effort),
+                );
+                for index_selection in selections.clone() {
+                    let candidate =
+                        self.encode_split(4, rotation, index_selection, &texels, effort);
+                    keep_best(&mut best, candidate);
+                }
+            }
+
+            // modes 0 to 3 have no alpha and decode it as fully opaque
+            let partitioned: &[usize] = if opaque { &[1, 3, 0, 2, 7] } else { &[7] };
+            for &mode in partitioned {
+                let available = 1 << MODES[mode].partition_bits;
+                let ranked = self.rank_partitions(&texels, mode, available, effort.partitions);
+                for partition in ranked {
+                    keep_best(
+                        &mut best,
+                        self.encode_joint(mode, partition, &texels, effort),
+                    );
+                }
+            }
+        }
+        best.write(out);
+    }
+}
+
+pub struct Bc7Decoder;
+
+impl BlockDecoder for Bc7Decoder {
+    fn block_bytes(&self) -> usize {
+        16
+    }
+
+    // fields in the order Block::write stores them; reserved mode 8 decodes as zero
+    fn decode_block(&self, block: &[u8], out: &mut [Texel]) {
+        let mode_index = block[0].trailing_zeros() as usize;
+        if mode_index >= MODES.len() {
+            for texel in out.iter_mut() {
+                *texel = [0.0; 4];
+            }
+            return;
+        }
+        let mode = &MODES[mode_index];
+        let mut reader = BitReader::new(block);
+        reader.read(mode_index as u32 + 1);
+        let partition = reader.read(mode.partition_bits) as usize;
+        let rotation = reader.read(mode.rotation_bits);
+        let index_selection = reader.read(mode.index_selection_bits);
+
+        let mut endpoints = [[[0u32; 4]; 2]; 3];
+        for c in 0..3 {
+            for subset in &mut endpoints[..mode.subsets] {
+                subset[0][c] = reader.read(mode.colour_bits);
+                subset[1][c] = reader.read(mode.colour_bits);
+            }
+        }
+        for subset in &mut endpoints[..mode.subsets] {
+            subset[0][3] = reader.read(mode.alpha_bits);
+            subset[1][3] = reader.read(mode.alpha_bits);
+        }
+        let mut pbits = [[None; 2]; 3];
+        for subset_pbits in &mut pbits[..mode.subsets] {
+            match mode.pbits {
+                PBits::PerEndpoint => {
+                    subset_pbits[0] = Some(reader.read(1));
+                    subset_pbits[1] = Some(reader.read(1));
+                }
+                PBits::Shared => {
+                    let pbit = Some(reader.read(1));
+                    *subset_pbits = [pbit, pbit];
+                }
+                PBits::None => (),
+            }
+        }
+        for (subset, subset_pbits) in endpoints.iter_mut().zip(pbits.iter()) {
+            for (endpoint, &pbit) in subset.iter_mut().zip(subset_pbits.iter()) {
+                for value in &mut endpoint[..3] {
+                    *value = dequantise(*value, mode.colour_bits, pbit);
+                }
+                endpoint[3] = if mode.alpha_bits == 0 {
+                    255
+                } else {
+                    dequantise(endpoint[3], mode.alpha_bits, pbit)
+                };
+            }
+        }
+
+        let mut indices = [0u32; 16];
+        let mut indices2 = [0u32; 16];
+        for (texel, index) in indices.iter_mut().enumerate() {
+            let anchor = bptc::is_anchor(mode.subsets, partition, texel);
+            *index = reader.read(mode.index_bits - anchor as u32);
+        }
+        if mode.index_bits2 > 0 {
+            for (texel, index) in indices2.iter_mut().enumerate() {
+                *index = reader.read(mode.index_bits2 - (texel == 0) as u32);
+            }
+        }
+
+        let (colour_index_bits, alpha_index_bits) = if index_selection == 1 {
+            (mode.index_bits2, mode.index_bits)
+        } else {
+            (mode.index_bits, mode.index_bits2)
+        };
+        let subsets = bptc::partition(mode.subsets, partition);
+        for (i, texel) in out.iter_mut().enumerate() {
+            let subset = &endpoints[subsets[i] as usize];
+            let (colour_index, alpha_index) = match (mode.index_bits2, index_selection) {
+                (0, _) => (indices[i], indices[i]),
+                (_, 1) => (indices2[i], indices[i]),
+                _ => (indices[i], indices2[i]),
+            };
+            let colour_weight = bptc::weights(colour_index_bits)[colour_index as usize];
+            let alpha_weight = if mode.index_bits2 == 0 {
+                colour_weight
+            } else {
+                bptc::weights(alpha_index_bits)[alpha_index as usize]
+            };
+            for c in 0..4 {
+                let w = if c == 3 { alpha_weight } else { colour_weight };
+                let value = ((64 - w) * subset[0][c] + w * subset[1][c] + 32) >> 6;
+                texel[c] = value as f32 / 255.0;
+            }
+            if rotation > 0 {
+                texel.swap(rotation as usize - 1, 3);
+            }
+        }
+    }
+}
+
+fn keep_best(best: &mut Block, candidate: Block) {
+    if candidate.error < best.error {
+        *best = candidate;
+    }
+}
+
+// the channels an endpoint fit covers, and how its endpoints and indices are stored
+#[derive(Copy, Clone)]
+struct FitParams {
+    first: usize,
+    last: usize,
+    bits: u32,
+    pbits: PBits,
+    index_bits: u32,
+}
+
+// quantised endpoints (before any p-bit is appended) and per-texel indices
+struct Fit {
+    endpoints: [[u32; 4]; 2],
+    pbits: [u32; 2],
+    indices: Vec<u32>,
+    error: f32,
+}
+
+impl Fit {
+    // the anchor texel's index must have a clear top bit, which swapping endpoints fixes
+    fn fix_anchor(&mut self, anchor: usize, index_bits: u32) {
+        let max = (1 << index_bits) - 1;
+        if self.indices[anchor] > max / 2 {
+            self.endpoints.swap(0, 1);
+            self.pbits.swap(0, 1);
+            for index in &mut self.indices {
+                *index = max - *index;
+            }
+        }
+    }
+}
+
+struct Block {
+    error: f32,
+    mode: usize,
+    partition: usize,
+    rotation: u32,
+    index_selection: u32,
+    endpoints: [[[u32; 4]; 2]; 3],
+    pbits: [[u32; 2]; 3],
+    indices: [u32; 16],
+    indices2: [u32; 16],
+}
+
+impl Block {
+    fn new(mode: usize, partition: usize) -> Block {
+        Block {
+            error: 0.0,
+            mode,
+            partition,
+            rotation: 0,
+            index_selection: 0,
+            endpoints: [[[0; 4]; 2]; 3],
+            pbits: [[0; 2]; 3],
+            indices: [0; 16],
+            indices2: [0; 16],
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let mode = &MODES[self.mode];
+        let mut writer = BitWriter::new();
+        writer.write(1 << self.mode, self.mode as u32 + 1);
+        writer.write(self.partition as u32, mode.partition_bits);
+        writer.write(self.rotation, mode.rotation_bits);
+        writer.write(self.index_selection, mode.index_selection_bits);
+
+        let endpoints = &self.endpoints[..mode.subsets];
+        for c in 0..3 {
+            for subset in endpoints {
+                writer.write(subset[0][c], mode.colour_bits);
+                writer.write(subset[1][c], mode.colour_bits);
+            }
+        }
+        for subset in endpoints {
+            writer.write(subset[0][3], mode.alpha_bits);
+            writer.write(subset[1][3], mode.alpha_bits);
+        }
+        for pbits in &self.pbits[..mode.subsets] {
+            match mode.pbits {
+                PBits::PerEndpoint => {
+                    writer.write(pbits[0], 1);
+                    writer.write(pbits[1], 1);
+                }
+                PBits::Shared => writer.write(pbits[0], 1),
+                PBits::None => (),
+            }
+        }
+
+        for (texel, &index) in self.indices.iter().enumerate() {
+            let anchor = bptc::is_anchor(mode.subsets, self.partition, texel);
+            writer.write(index, mode.index_bits - anchor as u32);
+        }
+        if mode.index_bits2 > 0 {
+            for (texel, &index) in self.indices2.iter().enumerate() {
+                writer.write(index, mode.index_bits2 - (texel == 0) as u32);
+            }
+        }
+        writer.finish(out);
+    }
+}
+
+impl Bc7Encoder {
+    // modes where colour and alpha share endpoints and indices, optionally partitioned
+    fn encode_joint(
+        &self,
+        mode_index: usize,
+        partition: usize,
+        texels: &[Texel],
+        effort: Effort,
+    ) -> Block {
+        let mode = &MODES[mode_index];
+        let params = FitParams {
+            first: 0,
+            last: if mode.alpha_bits > 0 { 4 } else { 3 },
+            bits: mode.colour_bits,
+            pbits: mode.pbits,
+            index_bits: mode.index_bits,
+        };
+        let subset_of = bptc::partition(mode.subsets, partition);
+        let anchors = bptc::anchors(mode.subsets, partition);
+
+        let mut block = Block::new(mode_index, partition);
+        for (subset, &anchor) in anchors.iter().enumerate().take(mode.subsets) {
+            let members: Vec<usize> = (0..16)
+                .filter(|&i| subset_of[i] as usize == subset)
+                .collect();
+            let subset_texels: Vec<Texel> = members.iter().map(|&i| texels[i]).collect();
+            let mut fit = fit_subset(&subset_texels, &params, &self.weights, effort);
+            let anchor = members.iter().position(|&i| i == anchor).unwrap();
+            fit.fix_anchor(anchor, params.index_bits);
+
+            for (&texel, &index) in members.iter().zip(&fit.indices) {
+                block.indices[texel] = index;
+            }
+            block.endpoints[subset] = fit.endpoints;
+            block.pbits[subset] = fit.pbits;
+            block.error += fit.error;
+        }
+        if params.last == 3 {
+            for texel in texels {
+                block.error += self.weights[3] * (255.0 - texel[3]) * (255.0 - texel[3]);
+            }
+        }
+        block
+    }
+
+    // modes 4 and 5: separate colour and alpha fits, with an optional channel rotation
+    // that swaps alpha with one of the colour channels
+    fn encode_split(
+        &self,
+        mode_index: usize,
+        rotation: u32,
+        index_selection: u32,
+        texels: &[Texel],
+        effort: Effort,
+    ) -> Block {
+        let mode = &MODES[mode_index];
+        let mut weights = self.weights;
+        let mut rotated = texels.to_vec();
+        if rotation > 0 {
+            let channel = rotation as usize - 1;
+            weights.swap(channel, 3);
+            for texel in &mut rotated {
+                texel.swap(channel, 3);
+            }
+        }
+        let (colour_index_bits, alpha_index_bits) = if index_selection == 1 {
+            (mode.index_bits2, mode.index_bits)
+        } else {
+            (mode.index_bits, mode.index_bits2)
+        };
+
+        let colour_params = FitParams {
+            first: 0,
+            last: 3,
+            bits: mode.colour_bits,
+            pbits: PBits::None,
+            index_bits: colour_index_bits,
+        };
+        let mut colour = fit_subset(&rotated, &colour_params, &weights, effort);
+        colour.fix_anchor(0, colour_index_bits);
+
+        let alpha_params = FitParams {
+            first: 3,
+            last: 4,
+            bits: mode.alpha_bits,
+            pbits: PBits::None,
+            index_bits: alpha_index_bits,
+        };
+        let mut alpha = fit_subset(&rotated, &alpha_params, &weights, effort);
+        alpha.fix_anchor(0, alpha_index_bits);
+
+        let mut block = Block::new(mode_index, 0);
+        block.rotation = rotation;
+        block.index_selection = index_selection;
+        block.error = colour.error + alpha.error;
+        for e in 0..2 {
+            block.endpoints[0][e] = colour.endpoints[e];
+            block.endpoints[0][e][3] = alpha.endpoints[e][3];
+        }
+        for texel in 0..16 {
+            if index_selection == 1 {
+                block.indices[texel] = alpha.indices[texel];
+                block.indices2[texel] = colour.indices[texel];
+            } else {
+                block.indices[texel] = colour.indices[texel];
+                block.indices2[texel] = alpha.indices[texel];
+            }
+        }
+        block
+    }
+
+    // Orders partitions by how well each subset is approximated by a line, which is
+    // cheap to compute and a decent predictor of the final error.
+    fn rank_partitions(
+        &self,
+        texels: &[Texel],
+        mode_index: usize,
+        available: usize,
+        keep: usize,
+    ) -> Vec<usize> {
+        let mode = &MODES[mode_index];
+        let last = if mode.alpha_bits > 0 { 4 } else { 3 };
+        let mut scored: Vec<(f32, usize)> = (0..available)
+            .map(|partition| {
+                let subset_of = bptc::partition(mode.subsets, partition);
+                let residual = (0..mode.subsets)
+                    .map(|subset| {
+                        let members: Vec<Texel> = (0..16)
+                            .filter(|&i| subset_of[i] as usize == subset)
+                            .map(|i| texels[i])
+                            .collect();
+                        bptc::line_residual(&members, 0, last, &self.weights)
+                    })
+                    .sum();
+                (residual, partition)
+            })
+            .collect();
+        scored.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        scored
+            .iter()
+            .take(keep)
+            .map(|&(_, partition)| partition)
+            .collect()
+    }
+}
+
+fn fit_subset(texels: &[Texel], params: &FitParams, weights: &[f32; 4], effort: Effort) -> Fit {
+    let (start, end) = principal_endpoints(texels, params.first, params.last);
+    let mut best = quantise_and_index(texels, params, weights, start, end, effort.exhaustive_pbits);
+    for _ in 0..effort.refinements {
+        let (start, end) = match least_squares(texels, params, &best) {
+            Some(endpoints) => endpoints,
+            None => break,
+        };
+        let candidate =
+            quantise_and_index(texels, params, weights, start, end, effort.exhaustive_pbits);
+        if candidate.error < best.error {
+            best = candidate;
+        } else {
+            break;
+        }
+    }
+    best
+}
+
+fn expand(value: u32, bits: u32) -> u32 {
+    if bits >= 8 {
+        value
+    } else {
+        (value << (8 - bits)) | (value >> (2 * bits - 8))
+    }
+}
+
+fn quantise(value: f32, bits: u32, pbit: Option<u32>) -> u32 {
+    let max = (1 << bits) - 1;
+    let quantised = match pbit {
+        None => value / 255.0 * max as f32,
+        Some(p) => (value / 255.0 * ((1 << (bits + 1)) - 1) as f32 - p as f32) / 2.0,
+    };
+    (quantised.round().max(0.0) as u32).min(max)
+}
+
+fn dequantise(value: u32, bits: u32, pbit: Option<u32>) -> u32 {
+    match pbit {
+        None => expand(value, bits),
+        Some(p) => expand(value << 1 | p, bits + 1),
+    }
+}
+
+fn pbit(params: &FitParams, pbits: &[u32; 2], endpoint: usize) -> Option<u32> {
+    match params.pbits {
+        PBits::None => None,
+        _ => Some(pbits[endpoint]),
+    }
+}
+
+// the p-bit that best preserves a single endpoint
+fn best_pbit(endpoint: &Texel, params: &FitParams, weights: &[f32; 4]) -> (u32, f32) {
+    let error = |p: u32| {
+        (params.first..params.last)
+            .map(|c| {
+                let restored = dequantise(
+                    quantise(endpoint[c], params.bits, Some(p)),
+                    params.bits,
+                    Some(p),
+                );
+                let d = restored as f32 - endpoint[c];
+                weights[c] * d * d
+            })
+            .sum::<f32>()
+    };
+    let (zero, one) = (error(0), error(1));
+    if one < zero {
+        (1, one)
+    } else {
+        (0, zero)
+    }
+}
+
+fn quantise_and_index(
+    texels: &[Texel],
+    params: &FitParams,
+    weights: &[f32; 4],
+    start: Texel,
+    end: Texel,
+    exhaustive_pbits: bool,
+) -> Fit {
+    let candidates: Vec<[u32; 2]> = match params.pbits {
+        PBits::None => vec![[0, 0]],
+        PBits::Shared if exhaustive_pbits => vec![[0, 0], [1, 1]],
+        PBits::PerEndpoint if exhaustive_pbits => vec![[0, 0], [0, 1], [1, 0], [1, 1]],
+        PBits::Shared => {
+            let (p0, p1) = (
+                best_pbit(&start, params, weights),
+                best_pbit(&end, params, weights),
+            );
+            // when they disagree, favour the endpoint that suffers more from the wrong one
+            let p = if p0.1 > p1.1 { p0.0 } else { p1.0 };
+            vec![[p, p]]
+        }
+        PBits::PerEndpoint => vec![[
+            best_pbit(&start, params, weights).0,
+            best_pbit(&end, params, weights).0,
+        ]],
+    };
+
+    let mut best: Option<Fit> = None;
+    for pbits in candidates {
+        let mut endpoints = [[0u32; 4]; 2];
+        for c in params.first..params.last {
+            endpoints[0][c] = quantise(start[c], params.bits, pbit(params, &pbits, 0));
+            endpoints[1][c] = quantise(end[c], params.bits, pbit(params, &pbits, 1));
+        }
+        let fit = evaluate(texels, params, weights, endpoints, pbits);
+        let better = match best {
+            Some(ref best) => fit.error < best.error,
+            None => true,
+        };
+        if better {
+            best = Some(fit);
+        }
+    }
+    best.unwrap()
+}
+
+fn evaluate(
+    texels: &[Texel],
+    params: &FitParams,
+    weights: &[f32; 4],
+    endpoints: [[u32; 4]; 2],
+    pbits: [u32; 2],
+) -> Fit {
+    let mut expanded = [[0u32; 4]; 2];
+    for e in 0..2 {
+        for c in params.first..params.last {
+            expanded[e][c] = dequantise(endpoints[e][c], params.bits, pbit(params, &pbits, e));
+        }
+    }
+    let palette: Vec<Texel> = bptc::weights(params.index_bits)
+        .iter()
+        .map(|&w| {
+            let mut entry = [0.0f32; 4];
+            for c in params.first..params.last {
+                entry[c] = (((64 - w) * expanded[0][c] + w * expanded[1][c] + 32) >> 6) as f32;
+            }
+            entry
+        })
+        .collect();
+
+    let mut error = 0.0;
+    let mut indices = Vec::with_capacity(texels.len());
+    for texel in texels {
+        let mut best_index = 0;
+        let mut best_error = f32::MAX;
+        for (index, entry) in palette.iter().enumerate() {
+            let mut entry_error = 0.0;
+            for c in params.first..params.last {
+                let d = texel[c] - entry[c];
+                entry_error += weights[c] * d * d;
+            }
+            if entry_error < best_error {
+                best_index = index;
+                best_error = entry_error;
+            }
+        }
+        error += best_error;
+        indices.push(best_index as u32);
+    }
+    Fit {
+        endpoints,
+        pbits,
+        indices,
+        error,
+    }
+}
+
+// Solves for endpoints a, b minimising sum |(1 - t) * a + t * b - x|^2, given each
+// texel's interpolation weight t.
+fn least_squares(texels: &[Texel], params: &FitParams, fit: &Fit) -> Option<(Texel, Texel)> {
+    let weights = bptc::weights(params.index_bits);
+    let (mut aa, mut bb, mut ab) = (0.0f32, 0.0f32, 0.0f32);
+    let mut ax = [0.0f32; 4];
+    let mut bx = [0.0f32; 4];
+    for (texel, &index) in texels.iter().zip(&fit.indices) {
+        let t = weights[index as usize] as f32 / 64.0;
+        let s = 1.0 - t;
+        aa += s * s;
+        bb += t * t;
+        ab += s * t;
+        for c in params.first..params.last {
+            ax[c] += s * texel[c];
+            bx[c] += t * texel[c];
+        }
+    }
+    let det = aa * bb - ab * ab;
+    if det.abs() < 1e-6 {
+        return None;
+    }
+    let mut start = [0.0f32; 4];
+    let mut end = [0.0f32; 4];
+    for c in params.first..params.last {
+        start[c] = ((ax[c] * bb - bx[c] * ab) / det).clamp(0.0, 255.0);
+        end[c] = ((bx[c] * aa - ax[c] * ab) / det).clamp(0.0, 255.0);
+    }
+    Some((start, end))
+}
+
+fn principal_endpoints(texels: &[Texel], first: usize, last: usize) -> (Texel, Texel) {
+    let (mean, axis) = principal_axis(texels, first, last);
+    let project = |texel: &Texel| {
+        (first..last)
+            .map(|c| (texel[c] - mean[c]) * axis[c])
+            .sum::<f32>()
+    };
+    let (mut min, mut max) = (0.0f32, 0.0f32);
+    for texel in texels {
+        let t = project(texel);
+        min = min.min(t);
+        max = max.max(t);
+    }
+    let mut start = [0.0f32; 4];
+    let mut end = [0.0f32; 4];
+    for c in first..last {
+        start[c] = (mean[c] + axis[c] * min).clamp(0.0, 255.0);
+        end[c] = (mean[c] + axis[c] * max).clamp(0.0, 255.0);
+    }
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compress::bptc::BitWriter;
+    use crate::compress::{
+        decode_block_for_test, image_for_test, rmse_for_test, round_trip_for_test, CompressOptions,
+        Quality, Texel,
+    };
+    use crate::Dimensions;
+
+    const WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+    #[test]
+    fn mode_6() {
+        // 7-bit endpoints with a p-bit each, and a 4-bit index per texel, i for texel i
+        let (e0, e1) = ([0, 127, 64, 127], [127, 0, 64, 127]);
+        let mut writer = BitWriter::new();
+        writer.write(1 << 6, 7);
+        for c in 0..4 {
+            writer.write(e0[c], 7);
+            writer.write(e1[c], 7);
+        }
+        writer.write(0, 1);
+        writer.write(1, 1);
+        // the anchor texel's index drops its top bit, which must be zero
+        writer.write(0, 3);
+        for i in 1..16 {
+            writer.write(i, 4);
+        }
+        let mut block = vec![];
+        writer.finish(&mut block);
+
+        let texels = decode_block_for_test("BC7_UNORM", &block);
+        for (i, texel) in texels.iter().enumerate() {
+            for c in 0..4 {
+                let (a, b) = (e0[c] << 1, e1[c] << 1 | 1);
+                let w = WEIGHTS[i];
+                let expected = ((64 - w) * a + w * b + 32) >> 6;
+                assert_eq!(
+                    texel[c],
+                    expected as f32 / 255.0,
+                    "texel {} channel {}",
+                    i,
+                    c
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reserved_mode() {
+        assert_eq!(
+            decode_block_for_test("BC7_UNORM", &[0; 16]),
+            vec![[0.0; 4]; 16]
+        );
+    }
+
+    fn options(quality: Quality) -> CompressOptions {
+        CompressOptions {
+            quality,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quality_levels() {
+        // each level searches more modes and partitions, and does no worse for it
+        let image = image_for_test();
+        let dim = Dimensions::new(16, 16, 0);
+        let qualities = [Quality::Fast, Quality::Normal, Quality::Slow];
+        let errors: Vec<f32> = qualities
+            .iter()
+            .map(|&quality| {
+                let (_, decoded) = round_trip_for_test("BC7_UNORM", &options(quality), &image, dim);
+                rmse_for_test(&decoded, &image, 4)
+            })
+            .collect();
+        assert!(errors[0] < 0.045, "{:?}", errors);
+        assert!(errors[1] < 0.025 && errors[1] <= errors[0], "{:?}", errors);
+        assert!(errors[2] < 0.015 && errors[2] <= errors[1], "{:?}", errors);
+    }
+
+    #[test]
+    fn partition_search() {
+        // two and three differently coloured regions, each with a gradient of its own that
+        // mode 6's single line can't follow
+        let dim = Dimensions::new(4, 4, 0);
+        let two: Vec<Texel> = (0..16)
+            .map(|i| {
+                let t = (i / 4) as f32 / 3.0;
+                if i % 4 < 2 {
+                    [0.9, 0.1 + 0.3 * t, 0.1, 1.0]
+                } else {
+                    [0.1, 0.2, 0.3 + 0.6 * t, 1.0]
+                }
+            })
+            .collect();
+        let three: Vec<Texel> = (0..16)
+            .map(|i| {
+                let (x, y) = (i % 4, i / 4);
+                let t = (x + y) as f32 / 6.0;
+                match (x < 2, y < 2) {
+                    (true, true) => [0.9, 0.1 + 0.2 * t, 0.1, 1.0],
+                    (false, true) => [0.1, 0.9, 0.2 * t, 1.0],
+                    _ => [0.1 + 0.2 * t, 0.1, 0.9, 1.0],
+                }
+            })
+            .collect();
+        // the mode is the number of zeros before the first set bit
+        for (texels, modes) in [(two, &[1, 3, 7][..]), (three, &[0, 2][..])].iter() {
+            let (_, fast) = round_trip_for_test("BC7_UNORM", &options(Quality::Fast), texels, dim);
+            let (block, decoded) =
+                round_trip_for_test("BC7_UNORM", &options(Quality::Normal), texels, dim);
+            let mode = block[0].trailing_zeros();
+            assert!(modes.contains(&mode), "mode {}", mode);
+            assert!(rmse_for_test(&decoded, texels, 4) < 0.01, "mode {}", mode);
+            assert!(rmse_for_test(&fast, texels, 4) > 0.05);
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use crate::compress::{BlockDecoder, BlockEncoder, CompressOptions, Quality, Texel};
+use crate::pixel::{CompContent, CompLayout, PixelFormat};
+
+// modifier tables, indexed by table and then by the 3-bit texel index
+const MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+// R11 and RG11, each channel an independent block
+pub struct EacEncoder {
+    quality: Quality,
+    precision: Precision,
+    channels: usize,
+}
+
+impl EacEncoder {
+    pub fn new(target: &PixelFormat, options: &CompressOptions) -> EacEncoder {
+        EacEncoder {
+            quality: options.quality,
+            precision: if target.comp_content == CompContent::SNORM {
+                Precision::Signed11
+            } else {
+                Precision::Unsigned11
+            },
+            channels: if target.comp_layout == CompLayout::R11G11 {
+                2
+            } else {
+                1
+            },
+        }
+    }
+}
+
+impl BlockEncoder for EacEncoder {
+    fn encode_block(&self, block: &[Texel], out: &mut Vec<u8>) {
+        for c in 0..self.channels {
+            // channel values in the target's range: [0, 1] for UNORM, [-1, 1] for SNORM
+            let values: Vec<f32> = block.iter().map(|texel| texel[c]).collect();
+            encode_channel(&values, self.precision, self.quality, out);
+        }
+    }
+}
+
+pub struct EacDecoder {
+    precision: Precision,
+    channels: usize,
+}
+
+impl EacDecoder {
+    pub fn new(format: &PixelFormat) -> EacDecoder {
+        let encoder = EacEncoder::new(format, &CompressOptions::default());
+        EacDecoder {
+            precision: encoder.precision,
+            channels: encoder.channels,
+        }
+    }
+}
+
+impl BlockDecoder for EacDecoder {
+    fn block_bytes(&self) -> usize {
+        8 * self.channels
+    }
+
+    // values in the format's range, with missing colour channels zero
+    fn decode_block(&self, block: &[u8], out: &mut [Texel]) {
+        let mut values = [0.0; 16];
+        for texel in out.iter_mut() {
+            *texel = [0.0, 0.0, 0.0, 1.0];
+        }
+        for (c, channel) in block.chunks_exact(8).enumerate().take(self.channels) {
+            decode_channel(channel, self.precision, &mut values);
+            for (texel, value) in out.iter_mut().zip(values.iter()) {
+                texel[c] = *value;
+            }
+        }
+    }
+}
+
+// Encodes 16 values in [0, 1], in row-major order, as the EAC alpha half of an ETC2
+// RGBA block.
+pub fn encode_alpha(values: &[f32], quality: Quality, out: &mut Vec<u8>) {
+    encode_channel(values, Precision::Alpha8, quality, out);
+}
+
+// Decodes the EAC alpha half of an ETC2 RGBA block into 16 values in [0, 1].
+pub fn decode_alpha(block: &[u8], out: &mut [f32]) {
+    decode_channel(block, Precision::Alpha8, out);
+}
+
+fn decode_channel(block: &[u8], precision: Precision, out: &mut [f32]) {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&block[..8]);
+    let bits = u64::from_be_bytes(bytes);
+    let base = match precision {
+        // -128 is a second encoding of -127
+        Precision::Signed11 => i32::from((bits >> 56) as u8 as i8).max(-127),
+        _ => (bits >> 56) as i32,
+    };
+    let multiplier = (bits >> 52 & 0xf) as i32;
+    let table = (bits >> 48 & 0xf) as usize;
+    let palette = precision.palette(base, multiplier, table);
+    let scale = precision.range().1 as f32;
+    for (i, value) in out.iter_mut().enumerate().take(16) {
+        let position = (i % 4) * 4 + i / 4;
+        *value = palette[(bits >> (45 - 3 * position) & 0x7) as usize] / scale;
+    }
+}
+
+// how a block's base, multiplier and modifiers combine into values
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Precision {
+    // base + modifier * multiplier, in [0, 255]
+    Alpha8,
+    // base * 8 + 4 + modifier * multiplier * 8, in [0, 2047]
+    Unsigned11,
+    // signed base * 8 + modifier * multiplier * 8, in [-1023, 1023]
+    Signed11,
+}
+
+impl Precision {
+    // the range of decoded values, to which values in [0, 1] or [-1, 1] are scaled
+    fn range(self) -> (i32, i32) {
+        match self {
+            Precision::Alpha8 => (0, 255),
+            Precision::Unsigned11 => (0, 2047),
+            Precision::Signed11 => (-1023, 1023),
+        }
+    }
+
+    fn bases(self) -> (i32, i32) {
+        match self {
+            Precision::Signed11 => (-127, 127),
+            _ => (0, 255),
+        }
+    }
+
+    fn step(self) -> i32 {
+        match self {
+            Precision::Alpha8 => 1,
+            _ => 8,
+        }
+    }
+
+    fn bias(self) -> i32 {
+        match self {
+            Precision::Unsigned11 => 4,
+            _ => 0,
+        }
+    }
+
+    // what each modifier is multiplied by; a multiplier of zero means a fine step of one
+    // for the 11-bit formats, but flattens alpha to its base
+    fn scale(self, multiplier: i32) -> i32 {
+        match (self, multiplier) {
+            (Precision::Alpha8, _) => multiplier,
+            (_, 0) => 1,
+            _ => multiplier * self.step(),
+        }
+    }
+
+    fn palette(self, base: i32, multiplier: i32, table: usize) -> [f32; 8] {
+        let (min, max) = self.range();
+        let scale = self.scale(multiplier);
+        let mut palette = [0.0; 8];
+        for (entry, &modifier) in palette.iter_mut().zip(&MODIFIERS[table]) {
+            *entry = (base * self.step() + self.bias() + modifier * scale).clamp(min, max) as f32;
+        }
+        palette
+    }
+}
+
+struct Encoded {
+    error: f32,
+    base: i32,
+    multiplier: i32,
+    table: usize,
+    indices: [u8; 16],
+}
+
+fn encode_channel(values: &[f32], precision: Precision, quality: Quality, out: &mut Vec<u8>) {
+    let (lo, hi) = precision.range();
+    let scale = hi as f32;
+    let values: Vec<f32> = values
+        .iter()
+        .map(|v| (v * scale).clamp(lo as f32, scale))
+        .collect();
+    let min = values.iter().cloned().fold(scale, f32::min);
+    let max = values.iter().cloned().fold(lo as f32, f32::max);
+    let (base_min, base_max) = precision.bases();
+    let step = precision.step() as f32;
+
+    // the slowest setting tries every multiplier with every table
+    let (multiplier_radius, base_radius) = match quality {
+        Quality::Fast => (0, 0),
+        Quality::Normal => (1, 2),
+        Quality::Slow => (15, 4),
+    };
+
+    let mut best: Option<Encoded> = None;
+    for (table, modifiers) in MODIFIERS.iter().enumerate() {
+        // the multiplier and base that stretch the table's extremes over the values
+        let span = (modifiers[7] - modifiers[3]) as f32;
+        let multiplier = (((max - min) / span / step).round() as i32).clamp(0, 15);
+        let offset = (modifiers[7] + modifiers[3]) as f32 / 2.0;
+
+        let lowest = (multiplier - multiplier_radius).max(0);
+        let highest = (multiplier + multiplier_radius).min(15);
+        for m in lowest..=highest {
+            let scaled = precision.scale(m) as f32;
+            let centre = (((max + min) / 2.0 - offset * scaled - precision.bias() as f32) / step)
+                .round() as i32;
+            for base in centre - base_radius..=centre + base_radius {
+                let base = base.clamp(base_min, base_max);
+                let candidate =
+                    evaluate(&values, precision.palette(base, m, table), base, m, table);
+                let better = match best {
+                    Some(ref best) => candidate.error < best.error,
+                    None => true,
+                };
+                if better {
+                    best = Some(candidate);
+                }
+            }
+        }
+    }
+
+    let best = best.unwrap();
+    let mut bits = u64::from(best.base as u8) << 56
+        | (best.multiplier as u64) << 52
+        | (best.table as u64) << 48;
+    for (i, &index) in best.indices.iter().enumerate() {
+        // texels are stored column by column
+        let position = (i % 4) * 4 + i / 4;
+        bits |= u64::from(index) << (45 - 3 * position);
+    }
+    out.extend_from_slice(&bits.to_be_bytes());
+}
+
+fn evaluate(
+    values: &[f32],
+    palette: [f32; 8],
+    base: i32,
+    multiplier: i32,
+    table: usize,
+) -> Encoded {
+    let mut error = 0.0;
+    let mut indices = [0u8; 16];
+    for (value, index) in values.iter().zip(indices.iter_mut()) {
+        let mut best_error = f32::MAX;
+        for (i, entry) in palette.iter().enumerate() {
+            let entry_error = (value - entry) * (value - entry);
+            if entry_error < best_error {
+                best_error = entry_error;
+                *index = i as u8;
+            }
+        }
+        error += best_error;
+    }
+    Encoded {
+        error,
+        base,
+        multiplier,
+        table,
+        indices,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compress::decode_block_for_test;
+
+    // base, multiplier and table 0, with index 4 everywhere except texel (0, 0), which
+    // takes the first index, and texel (3, 3), which takes the last
+    fn block(base: u8, multiplier: u64, first: u64, last: u64) -> [u8; 8] {
+        let mut bits = u64::from(base) << 56 | multiplier << 52;
+        for position in 0..16 {
+            let index = match position {
+                0 => first,
+                15 => last,
+                _ => 4,
+            };
+            bits |= index << (45 - 3 * position);
+        }
+        bits.to_be_bytes()
+    }
+
+    #[test]
+    fn r11_unsigned() {
+        // base * 8 + 4, plus the modifiers -3, 2 and 14 times the multiplier * 8
+        let texels = decode_block_for_test("EAC_R11_UNORM", &block(128, 2, 0, 7));
+        assert_eq!(texels[0], [980.0 / 2047.0, 0.0, 0.0, 1.0]);
+        assert_eq!(texels[1], [1060.0 / 2047.0, 0.0, 0.0, 1.0]);
+        assert_eq!(texels[15], [1252.0 / 2047.0, 0.0, 0.0, 1.0]);
+        // a zero multiplier counts as 1/8, and values clamp to 11 bits
+        let texels = decode_block_for_test("EAC_R11_UNORM", &block(255, 0, 0, 7));
+        assert_eq!(texels[0][0], 2041.0 / 2047.0);
+        assert_eq!(texels[1][0], 2046.0 / 2047.0);
+        assert_eq!(texels[15][0], 1.0);
+    }
+
+    #[test]
+    fn r11_signed() {
+        // base * 8 with no offset, where -128 reads as -127 and values clamp to -1023
+        let texels = decode_block_for_test("EAC_R11_SNORM", &block(0x80, 0, 3, 7));
+        assert_eq!(texels[0][0], -1.0);
+        assert_eq!(texels[1][0], -1014.0 / 1023.0);
+        assert_eq!(texels[15][0], -1002.0 / 1023.0);
+    }
+
+    #[test]
+    fn rg11() {
+        let mut blocks = block(0, 0, 4, 4).to_vec();
+        blocks.extend_from_slice(&block(255, 0, 4, 4));
+        for texel in decode_block_for_test("EAC_R11G11_UNORM", &blocks) {
+            assert_eq!(texel, [6.0 / 2047.0, 2046.0 / 2047.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn alpha() {
+        // base plus the modifiers -3, 2 and 14 times the multiplier, with black colour
+        let mut blocks = block(100, 3, 0, 7).to_vec();
+        blocks.extend_from_slice(&[0; 8]);
+        let texels = decode_block_for_test("ETC2_R8G8B8A8_UNORM", &blocks);
+        assert_eq!(texels[0][3], 91.0 / 255.0);
+        assert_eq!(texels[1][3], 106.0 / 255.0);
+        assert_eq!(texels[15][3], 142.0 / 255.0);
+        // unlike the 11-bit formats, a zero multiplier leaves only the base
+        let mut blocks = block(100, 0, 0, 7).to_vec();
+        blocks.extend_from_slice(&[0; 8]);
+        for texel in decode_block_for_test("ETC2_R8G8B8A8_UNORM", &blocks) {
+            assert_eq!(texel[3], 100.0 / 255.0);
+        }
+    }
+}
@@ -0,0 +1,1070 @@
+use crate::compress::{
+    eac, principal_axis, BlockDecoder, BlockEncoder, CompressOptions, Quality, Texel,
+};
+use crate::pixel::{CompLayout, PixelFormat};
+
+// intensity modifiers, indexed by table and then by the 2-bit texel index
+const MODIFIERS: [[i32; 4]; 8] = [
+    [2, 8, -2, -8],
+    [5, 17, -5, -17],
+    [9, 29, -9, -29],
+    [13, 42, -13, -42],
+    [18, 60, -18, -60],
+    [24, 80, -24, -80],
+    [33, 106, -33, -106],
+    [47, 183, -47, -183],
+];
+
+// distances between the paint colours of the T and H modes
+const DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+// Rec. 601 luma weights
+const PERCEPTUAL_WEIGHTS: [f32; 3] = [0.299, 0.587, 0.114];
+const UNIFORM_WEIGHTS: [f32; 3] = [1.0, 1.0, 1.0];
+
+type Rgb = [f32; 3];
+
+pub struct EtcEncoder {
+    quality: Quality,
+    // individual and differential modes only, which is all ETC1 decoders understand
+    etc1_only: bool,
+    // RGB8A1, where texels below the alpha threshold become transparent
+    punch_through: bool,
+    // RGBA8, where an EAC alpha block precedes the colour block
+    alpha: bool,
+    alpha_threshold: f32,
+    weights: [f32; 3],
+}
+
+impl EtcEncoder {
+    pub fn new(target: &PixelFormat, options: &CompressOptions) -> EtcEncoder {
+        EtcEncoder {
+            quality: options.quality,
+            etc1_only: target.tag == "ETC1" || options.etc1_compatible,
+            punch_through: target.tag == "ETC2_A1",
+            alpha: target.tag == "ETC2" && target.comp_layout == CompLayout::R8G8B8A8,
+            alpha_threshold: f32::from(options.alpha_threshold) / 255.0,
+            weights: if options.perceptual {
+                PERCEPTUAL_WEIGHTS
+            } else {
+                UNIFORM_WEIGHTS
+            },
+        }
+    }
+}
+
+struct Pixels {
+    colours: Vec<Rgb>,
+    transparent: [bool; 16],
+    any_transparent: bool,
+}
+
+struct Encoded {
+    error: f32,
+    bits: u64,
+}
+
+const NONE: Encoded = Encoded {
+    error: f32::MAX,
+    bits: 0,
+};
+
+fn keep_best(best: &mut Encoded, candidate: Encoded) {
+    if candidate.error < best.error {
+        *best = candidate;
+    }
+}
+
+impl BlockEncoder for EtcEncoder {
+    fn encode_block(&self, block: &[Texel], out: &mut Vec<u8>) {
+        if self.alpha {
+            let alphas: Vec<f32> = block.iter().map(|texel| texel[3]).collect();
+            eac::encode_alpha(&alphas, self.quality, out);
+        }
+
+        let mut pixels = Pixels {
+            colours: block
+                .iter()
+                .map(|t| {
+                    [
+                        t[0].clamp(0.0, 1.0) * 255.0,
+                        t[1].clamp(0.0, 1.0) * 255.0,
+                        t[2].clamp(0.0, 1.0) * 255.0,
+                    ]
+                })
+                .collect(),
+            transparent: [false; 16],
+            any_transparent: false,
+        };
+        if self.punch_through {
+            for (flag, texel) in pixels.transparent.iter_mut().zip(block) {
+                *flag = texel[3] < self.alpha_threshold;
+            }
+            pixels.any_transparent = pixels.transparent.iter().any(|&flag| flag);
+        }
+
+        let mut best = self.encode_etc1(&pixels);
+        if !self.etc1_only {
+            // planar blocks are always opaque
+            if !pixels.any_transparent {
+                keep_best(&mut best, self.encode_planar(&pixels));
+            }
+            keep_best(&mut best, self.encode_paint(&pixels, false));
+            keep_best(&mut best, self.encode_paint(&pixels, true));
+        }
+        out.extend_from_slice(&best.bits.to_be_bytes());
+    }
+}
+
+pub struct EtcDecoder {
+    etc1: bool,
+    punch_through: bool,
+    alpha: bool,
+}
+
+impl EtcDecoder {
+    pub fn new(format: &PixelFormat) -> EtcDecoder {
+        let encoder = EtcEncoder::new(format, &CompressOptions::default());
+        EtcDecoder {
+            etc1: format.tag == "ETC1",
+            punch_through: encoder.punch_through,
+            alpha: encoder.alpha,
+        }
+    }
+}
+
+impl BlockDecoder for EtcDecoder {
+    fn block_bytes(&self) -> usize {
+        if self.alpha {
+            16
+        } else {
+            8
+        }
+    }
+
+    fn decode_block(&self, block: &[u8], out: &mut [Texel]) {
+        let colour = &block[self.block_bytes() - 8..];
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(colour);
+        let bits = u64::from_be_bytes(bytes);
+
+        // the flag bit, with punch-through formats always taking the differential path
+        let flag = bits >> 33 & 1 == 1;
+        let (differential, opaque) = if self.punch_through {
+            (true, flag)
+        } else {
+            (flag, true)
+        };
+        let mode = if !differential {
+            None
+        } else if self.etc1 {
+            Some(Etc2Mode::Differential)
+        } else {
+            Some(mode_of(bits))
+        };
+
+        let mut colours = [[0i32; 3]; 16];
+        let mut transparent = [false; 16];
+        match mode {
+            Some(Etc2Mode::Planar) => decode_planar(bits, &mut colours),
+            Some(Etc2Mode::T) | Some(Etc2Mode::H) => {
+                let paints = paints(bits, mode == Some(Etc2Mode::H));
+                for (i, colour) in colours.iter_mut().enumerate() {
+                    let index = unpack_index(bits, i);
+                    transparent[i] = !opaque && index == 2;
+                    *colour = paints[index as usize];
+                }
+            }
+            _ => {
+                let (first, second, bits_per_channel) = if differential {
+                    let mut first = [0; 3];
+                    let mut second = [0; 3];
+                    for c in 0..3 {
+                        let shift = 59 - 8 * c as u32;
+                        first[c] = (bits >> shift & 0x1f) as i32;
+                        let delta = ((bits >> (shift - 3) & 7) as i32 ^ 4) - 4;
+                        second[c] = first[c] + delta;
+                    }
+                    (first, second, 5)
+                } else {
+                    let mut first = [0; 3];
+                    let mut second = [0; 3];
+                    for c in 0..3 {
+                        let shift = 60 - 8 * c as u32;
+                        first[c] = (bits >> shift & 0xf) as i32;
+                        second[c] = (bits >> (shift - 4) & 0xf) as i32;
+                    }
+                    (first, second, 4)
+                };
+                let bases = [
+                    expand_colour(&first, bits_per_channel),
+                    expand_colour(&second, bits_per_channel),
+                ];
+                let tables = [(bits >> 37 & 7) as usize, (bits >> 34 & 7) as usize];
+                let flip = bits >> 32 & 1 == 1;
+                for (i, colour) in colours.iter_mut().enumerate() {
+                    let (x, y) = (i % 4, i / 4);
+                    let half = (if flip { y } else { x }) / 2;
+                    let index = unpack_index(bits, i);
+                    // with transparency, index 2 is transparent and index 0 unmodified
+                    let modifier = match (opaque, index) {
+                        (false, 2) => {
+                            transparent[i] = true;
+                            0
+                        }
+                        (false, 0) => 0,
+                        _ => MODIFIERS[tables[half]][index as usize],
+                    };
+                    *colour = offset(&bases[half], modifier);
+                }
+            }
+        }
+
+        for (i, texel) in out.iter_mut().enumerate() {
+            *texel = if transparent[i] {
+                [0.0; 4]
+            } else {
+                let colour = colours[i];
+                [
+                    colour[0] as f32 / 255.0,
+                    colour[1] as f32 / 255.0,
+                    colour[2] as f32 / 255.0,
+                    1.0,
+                ]
+            };
+        }
+        if self.alpha {
+            let mut alphas = [0.0; 16];
+            eac::decode_alpha(&block[..8], &mut alphas);
+            for (texel, alpha) in out.iter_mut().zip(alphas.iter()) {
+                texel[3] = *alpha;
+            }
+        }
+    }
+}
+
+// the inverse of the packing in encode_planar
+fn decode_planar(bits: u64, colours: &mut [[i32; 3]; 16]) {
+    let field = |shift: u32, width: u32| (bits >> shift & ((1 << width) - 1)) as i32;
+    let planes = [
+        [
+            (field(57, 6), 6),
+            (field(34, 5) << 1 | field(32, 1), 6),
+            (field(13, 6), 6),
+        ],
+        [
+            (field(56, 1) << 6 | field(49, 6), 7),
+            (field(25, 7), 7),
+            (field(6, 7), 7),
+        ],
+        [
+            (field(48, 1) << 5 | field(43, 2) << 3 | field(39, 3), 6),
+            (field(19, 6), 6),
+            (field(0, 6), 6),
+        ],
+    ];
+    for (c, plane) in planes.iter().enumerate() {
+        let [o, h, v] = plane.map(|(value, bits)| expand(value, bits));
+        for (i, colour) in colours.iter_mut().enumerate() {
+            let (x, y) = ((i % 4) as i32, (i / 4) as i32);
+            colour[c] = ((x * (h - o) + y * (v - o) + 4 * o + 2) >> 2).clamp(0, 255);
+        }
+    }
+}
+
+// the four paint colours of a T or H block, the inverse of the packing in encode_paint
+fn paints(bits: u64, h_mode: bool) -> [[i32; 3]; 4] {
+    let field = |shift: u32, width: u32| (bits >> shift & ((1 << width) - 1)) as i32;
+    if h_mode {
+        let a = [
+            field(59, 4),
+            field(56, 3) << 1 | field(52, 1),
+            field(51, 1) << 3 | field(47, 3),
+        ];
+        let b = [field(43, 4), field(39, 4), field(35, 4)];
+        let d = field(34, 1) << 2 | field(32, 1) << 1 | (packed_444(&a) >= packed_444(&b)) as i32;
+        let (ea, eb) = (expand_colour(&a, 4), expand_colour(&b, 4));
+        let distance = DISTANCES[d as usize];
+        [
+            offset(&ea, distance),
+            offset(&ea, -distance),
+            offset(&eb, distance),
+            offset(&eb, -distance),
+        ]
+    } else {
+        let a = [field(59, 2) << 2 | field(56, 2), field(52, 4), field(48, 4)];
+        let b = [field(44, 4), field(40, 4), field(36, 4)];
+        let d = field(34, 2) << 1 | field(32, 1);
+        let (ea, eb) = (expand_colour(&a, 4), expand_colour(&b, 4));
+        let distance = DISTANCES[d as usize];
+        [ea, offset(&eb, distance), eb, offset(&eb, -distance)]
+    }
+}
+
+// a subblock's base colour at 4 or 5 bits per channel, its table and texel indices
+struct Subblock {
+    error: f32,
+    colour: [i32; 3],
+    table: usize,
+    indices: Vec<u32>,
+}
+
+impl EtcEncoder {
+    // The bit after the tables is the differential flag, except for punch-through
+    // formats where it flags an opaque block and differential mode is implied.
+    fn flag(&self, pixels: &Pixels) -> u64 {
+        !pixels.any_transparent as u64
+    }
+
+    fn error(&self, pixel: &Rgb, colour: &[i32; 3]) -> f32 {
+        (0..3)
+            .map(|c| {
+                let d = pixel[c] - colour[c] as f32;
+                self.weights[c] * d * d
+            })
+            .sum()
+    }
+
+    // individual and differential modes, with two subblocks side by side or, flipped,
+    // one above the other
+    fn encode_etc1(&self, pixels: &Pixels) -> Encoded {
+        let mut best = NONE;
+        for &flip in &[false, true] {
+            let halves: Vec<Vec<usize>> = (0..2)
+                .map(|half| {
+                    (0..16)
+                        .filter(|&i| {
+                            let (x, y) = (i % 4, i / 4);
+                            (if flip { y } else { x }) / 2 == half
+                        })
+                        .collect()
+                })
+                .collect();
+
+            // punch-through formats have no individual mode, as its flag bit is taken
+            if !self.punch_through {
+                let first = self.fit_subblock(pixels, &halves[0], 4, None);
+                let second = self.fit_subblock(pixels, &halves[1], 4, None);
+                let mut bits = 0;
+                for c in 0..3 {
+                    let shift = 60 - 8 * c as u32;
+                    bits |= (first.colour[c] as u64) << shift
+                        | (second.colour[c] as u64) << (shift - 4);
+                }
+                bits |= pack_subblocks(&halves, &first, &second, flip);
+                keep_best(
+                    &mut best,
+                    Encoded {
+                        error: first.error + second.error,
+                        bits,
+                    },
+                );
+            }
+
+            // the second colour is stored as a 3-bit delta from the first, so when the
+            // independent fits are too far apart, bring each in range of the other
+            let first = self.fit_subblock(pixels, &halves[0], 5, None);
+            let second = self.fit_subblock(pixels, &halves[1], 5, None);
+            let pairs = if delta_fits(&first.colour, &second.colour) {
+                vec![(first, second)]
+            } else {
+                let near_first = self.fit_subblock(
+                    pixels,
+                    &halves[1],
+                    5,
+                    Some(offset_bounds(&first.colour, -4, 3)),
+                );
+                let near_second = self.fit_subblock(
+                    pixels,
+                    &halves[0],
+                    5,
+                    Some(offset_bounds(&second.colour, -3, 4)),
+                );
+                vec![(first, near_first), (near_second, second)]
+            };
+            for (first, second) in pairs {
+                let mut bits = self.flag(pixels) << 33;
+                for c in 0..3 {
+                    let shift = 59 - 8 * c as u32;
+                    let delta = second.colour[c] - first.colour[c];
+                    bits |= (first.colour[c] as u64) << shift | (delta as u64 & 7) << (shift - 3);
+                }
+                bits |= pack_subblocks(&halves, &first, &second, flip);
+                keep_best(
+                    &mut best,
+                    Encoded {
+                        error: first.error + second.error,
+                        bits,
+                    },
+                );
+            }
+        }
+        best
+    }
+
+    // Searches base colours around the quantised mean of the subblock's opaque texels,
+    // within the given per-channel bounds.
+    fn fit_subblock(
+        &self,
+        pixels: &Pixels,
+        members: &[usize],
+        bits: u32,
+        bounds: Option<([i32; 3], [i32; 3])>,
+    ) -> Subblock {
+        let max = (1 << bits) - 1;
+        let (lo, hi) = bounds.unwrap_or(([0; 3], [max; 3]));
+        let opaque: Vec<&Rgb> = members
+            .iter()
+            .filter(|&&i| !pixels.transparent[i])
+            .map(|&i| &pixels.colours[i])
+            .collect();
+        let mut centre = [0i32; 3];
+        for c in 0..3 {
+            let mean = if opaque.is_empty() {
+                0.0
+            } else {
+                opaque.iter().map(|colour| colour[c]).sum::<f32>() / opaque.len() as f32
+            };
+            centre[c] = quantise(mean, bits).clamp(lo[c], hi[c]);
+        }
+
+        let mut best: Option<Subblock> = None;
+        for step in self.colour_steps() {
+            let colour = [
+                centre[0] + step[0],
+                centre[1] + step[1],
+                centre[2] + step[2],
+            ];
+            if (0..3).any(|c| colour[c] < lo[c] || colour[c] > hi[c]) {
+                continue;
+            }
+            let candidate = self.evaluate_subblock(pixels, members, colour, bits);
+            let better = match best {
+                Some(ref best) => candidate.error < best.error,
+                None => true,
+            };
+            if better {
+                best = Some(candidate);
+            }
+        }
+        best.unwrap()
+    }
+
+    // Offsets from the mean colour to try as a base colour. The modifiers already move
+    // along the grey axis, so hue is where a nudge helps most.
+    fn colour_steps(&self) -> Vec<[i32; 3]> {
+        match self.quality {
+            Quality::Fast => vec![[0, 0, 0]],
+            Quality::Normal => vec![
+                [0, 0, 0],
+                [1, 0, 0],
+                [-1, 0, 0],
+                [0, 1, 0],
+                [0, -1, 0],
+                [0, 0, 1],
+                [0, 0, -1],
+            ],
+            Quality::Slow => {
+                let mut steps = vec![];
+                for r in -2..=2 {
+                    for g in -2..=2 {
+                        for b in -2..=2 {
+                            steps.push([r, g, b]);
+                        }
+                    }
+                }
+                steps
+            }
+        }
+    }
+
+    fn evaluate_subblock(
+        &self,
+        pixels: &Pixels,
+        members: &[usize],
+        colour: [i32; 3],
+        bits: u32,
+    ) -> Subblock {
+        let base = [
+            expand(colour[0], bits),
+            expand(colour[1], bits),
+            expand(colour[2], bits),
+        ];
+        let mut best = Subblock {
+            error: f32::MAX,
+            colour,
+            table: 0,
+            indices: vec![],
+        };
+        for (table, modifiers) in MODIFIERS.iter().enumerate() {
+            let mut error = 0.0;
+            let mut indices = Vec::with_capacity(members.len());
+            for &i in members {
+                if pixels.transparent[i] {
+                    indices.push(2);
+                    continue;
+                }
+                let mut best_index = 0;
+                let mut best_error = f32::MAX;
+                for (index, &modifier) in modifiers.iter().enumerate() {
+                    // with transparency, index 2 is transparent and index 0 unmodified
+                    let modifier = match (pixels.any_transparent, index) {
+                        (true, 2) => continue,
+                        (true, 0) => 0,
+                        _ => modifier,
+                    };
+                    let entry_error = self.error(&pixels.colours[i], &offset(&base, modifier));
+                    if entry_error < best_error {
+                        best_index = index as u32;
+                        best_error = entry_error;
+                    }
+                }
+                error += best_error;
+                indices.push(best_index);
+            }
+            if error < best.error {
+                best = Subblock {
+                    error,
+                    colour,
+                    table,
+                    indices,
+                };
+            }
+        }
+        best
+    }
+
+    // A gradient over the block, from a colour at the origin and colours at the ends of
+    // the horizontal and vertical edges, at 6, 7 and 6 bits for R, G and B.
+    fn encode_planar(&self, pixels: &Pixels) -> Encoded {
+        let radius = if self.quality == Quality::Slow { 1 } else { 0 };
+        let mut planes = [[0i32; 3]; 3];
+        let mut error = 0.0;
+        for (c, plane) in planes.iter_mut().enumerate() {
+            let bits = if c == 1 { 7 } else { 6 };
+            // least-squares plane through the channel; x and y both average 1.5 over the
+            // block and are uncorrelated, so the slopes can be fitted separately
+            let mean = pixels.colours.iter().map(|colour| colour[c]).sum::<f32>() / 16.0;
+            let (mut dx, mut dy) = (0.0, 0.0);
+            for (i, colour) in pixels.colours.iter().enumerate() {
+                let (x, y) = ((i % 4) as f32 - 1.5, (i / 4) as f32 - 1.5);
+                dx += x * (colour[c] - mean) / 20.0;
+                dy += y * (colour[c] - mean) / 20.0;
+            }
+            let origin = mean - 1.5 * dx - 1.5 * dy;
+            let centre = [
+                quantise(origin, bits),
+                quantise(origin + 4.0 * dx, bits),
+                quantise(origin + 4.0 * dy, bits),
+            ];
+
+            let mut best = (f32::MAX, centre);
+            for d0 in -radius..=radius {
+                for d1 in -radius..=radius {
+                    for d2 in -radius..=radius {
+                        let max = (1 << bits) - 1;
+                        let candidate = [
+                            (centre[0] + d0).clamp(0, max),
+                            (centre[1] + d1).clamp(0, max),
+                            (centre[2] + d2).clamp(0, max),
+                        ];
+                        let (o, h, v) = (
+                            expand(candidate[0], bits),
+                            expand(candidate[1], bits),
+                            expand(candidate[2], bits),
+                        );
+                        let mut candidate_error = 0.0;
+                        for (i, colour) in pixels.colours.iter().enumerate() {
+                            let (x, y) = ((i % 4) as i32, (i / 4) as i32);
+                            let value =
+                                ((x * (h - o) + y * (v - o) + 4 * o + 2) >> 2).clamp(0, 255);
+                            let d = colour[c] - value as f32;
+                            candidate_error += self.weights[c] * d * d;
+                        }
+                        if candidate_error < best.0 {
+                            best = (candidate_error, candidate);
+                        }
+                    }
+                }
+            }
+            error += best.0;
+            *plane = best.1;
+        }
+
+        let [[ro, rh, rv], [go, gh, gv], [bo, bh, bv]] = planes;
+        let (ro, rh, rv) = (ro as u64, rh as u64, rv as u64);
+        let (go, gh, gv) = (go as u64, gh as u64, gv as u64);
+        let (bo, bh, bv) = (bo as u64, bh as u64, bv as u64);
+        let bits = ro << 57
+            | (go >> 6) << 56
+            | (go & 0x3f) << 49
+            | (bo >> 5) << 48
+            | (bo >> 3 & 3) << 43
+            | (bo & 7) << 39
+            | (rh >> 1) << 34
+            | 1 << 33
+            | (rh & 1) << 32
+            | gh << 25
+            | bh << 19
+            | rv << 13
+            | gv << 6
+            | bv;
+        Encoded {
+            error,
+            bits: signal(bits, &[63, 55, 47, 46, 45, 42], Etc2Mode::Planar),
+        }
+    }
+
+    // The T and H modes: four paint colours from two 4-bit base colours and a distance,
+    // suited to blocks with two distinct colour groups. T paints one base colour as is
+    // and the other at three intensities; H paints each base colour plus and minus the
+    // distance.
+    fn encode_paint(&self, pixels: &Pixels, h_mode: bool) -> Encoded {
+        let opaque: Vec<Rgb> = (0..16)
+            .filter(|&i| !pixels.transparent[i])
+            .map(|i| pixels.colours[i])
+            .collect();
+        let mut best = NONE;
+        for (mean1, mean2) in self.clusters(&opaque) {
+            let c1 = [
+                quantise(mean1[0], 4),
+                quantise(mean1[1], 4),
+                quantise(mean1[2], 4),
+            ];
+            let c2 = [
+                quantise(mean2[0], 4),
+                quantise(mean2[1], 4),
+                quantise(mean2[2], 4),
+            ];
+            for &(a, b) in &[(c1, c2), (c2, c1)] {
+                let (ea, eb) = (expand_colour(&a, 4), expand_colour(&b, 4));
+                for (d, &distance) in DISTANCES.iter().enumerate() {
+                    // H mode stores the distance's low bit as the order of the base colours
+                    if h_mode && (packed_444(&a) >= packed_444(&b)) != (d & 1 == 1) {
+                        continue;
+                    }
+                    let paints = if h_mode {
+                        [
+                            offset(&ea, distance),
+                            offset(&ea, -distance),
+                            offset(&eb, distance),
+                            offset(&eb, -distance),
+                        ]
+                    } else {
+                        [ea, offset(&eb, distance), eb, offset(&eb, -distance)]
+                    };
+
+                    let mut error = 0.0;
+                    let mut index_bits = 0;
+                    for (i, colour) in pixels.colours.iter().enumerate() {
+                        if pixels.transparent[i] {
+                            index_bits |= pack_index(i, 2);
+                            continue;
+                        }
+                        let mut best_index = 0;
+                        let mut best_error = f32::MAX;
+                        for (index, paint) in paints.iter().enumerate() {
+                            if pixels.any_transparent && index == 2 {
+                                continue;
+                            }
+                            let entry_error = self.error(colour, paint);
+                            if entry_error < best_error {
+                                best_index = index as u32;
+                                best_error = entry_error;
+                            }
+                        }
+                        error += best_error;
+                        index_bits |= pack_index(i, best_index);
+                    }
+                    if error >= best.error {
+                        continue;
+                    }
+
+                    let (a, b, d) = (
+                        [a[0] as u64, a[1] as u64, a[2] as u64],
+                        [b[0] as u64, b[1] as u64, b[2] as u64],
+                        d as u64,
+                    );
+                    let flag = self.flag(pixels) << 33;
+                    best = if h_mode {
+                        let bits = a[0] << 59
+                            | (a[1] >> 1) << 56
+                            | (a[1] & 1) << 52
+                            | (a[2] >> 3) << 51
+                            | (a[2] & 7) << 47
+                            | b[0] << 43
+                            | b[1] << 39
+                            | b[2] << 35
+                            | (d >> 2) << 34
+                            | flag
+                            | (d >> 1 & 1) << 32
+                            | index_bits;
+                        Encoded {
+                            error,
+                            bits: signal(bits, &[63, 55, 54, 53, 50], Etc2Mode::H),
+                        }
+                    } else {
+                        let bits = (a[0] >> 2) << 59
+                            | (a[0] & 3) << 56
+                            | a[1] << 52
+                            | a[2] << 48
+                            | b[0] << 44
+                            | b[1] << 40
+                            | b[2] << 36
+                            | (d >> 1) << 34
+                            | flag
+                            | (d & 1) << 32
+                            | index_bits;
+                        Encoded {
+                            error,
+                            bits: signal(bits, &[63, 62, 61, 58], Etc2Mode::T),
+                        }
+                    };
+                }
+            }
+        }
+        best
+    }
+
+    // Pairs of group means from splitting the colours along their principal axis, at
+    // the median or, at the slowest setting, at every point, each refined by a few
+    // rounds of k-means.
+    fn clusters(&self, colours: &[Rgb]) -> Vec<(Rgb, Rgb)> {
+        if colours.len() < 2 {
+            return vec![];
+        }
+        let texels: Vec<Texel> = colours.iter().map(|c| [c[0], c[1], c[2], 0.0]).collect();
+        let (mean, axis) = principal_axis(&texels, 0, 3);
+        let mut order: Vec<(f32, &Rgb)> = colours
+            .iter()
+            .map(|colour| {
+                (
+                    (0..3).map(|c| (colour[c] - mean[c]) * axis[c]).sum(),
+                    colour,
+                )
+            })
+            .collect();
+        order.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let splits: Vec<usize> = if self.quality == Quality::Slow {
+            (1..order.len()).collect()
+        } else {
+            vec![order.len() / 2]
+        };
+        let group_mean = |group: &[(f32, &Rgb)]| {
+            let mut mean = [0.0f32; 3];
+            for (_, colour) in group {
+                for c in 0..3 {
+                    mean[c] += colour[c] / group.len() as f32;
+                }
+            }
+            mean
+        };
+        splits
+            .into_iter()
+            .map(|split| {
+                let mut means = (group_mean(&order[..split]), group_mean(&order[split..]));
+                for _ in 0..3 {
+                    let (first, second): (Vec<_>, Vec<_>) =
+                        order.iter().partition(|(_, colour)| {
+                            distance(colour, &means.0) <= distance(colour, &means.1)
+                        });
+                    if first.is_empty() || second.is_empty() {
+                        break;
+                    }
+                    means = (group_mean(&first), group_mean(&second));
+                }
+                means
+            })
+            .collect()
+    }
+}
+
+fn distance(a: &Rgb, b: &Rgb) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+fn pack_subblocks(halves: &[Vec<usize>], first: &Subblock, second: &Subblock, flip: bool) -> u64 {
+    let mut bits = (first.table as u64) << 37 | (second.table as u64) << 34 | (flip as u64) << 32;
+    for (half, subblock) in halves.iter().zip(&[first, second]) {
+        for (&i, &index) in half.iter().zip(&subblock.indices) {
+            bits |= pack_index(i, index);
+        }
+    }
+    bits
+}
+
+// Texels are stored column by column, with the high bits of their indices in one
+// half-word and the low bits in the other.
+fn pack_index(texel: usize, index: u32) -> u64 {
+    let position = (texel % 4) * 4 + texel / 4;
+    u64::from(index >> 1) << (16 + position) | u64::from(index & 1) << position
+}
+
+fn unpack_index(bits: u64, texel: usize) -> u32 {
+    let position = (texel % 4) * 4 + texel / 4;
+    ((bits >> (16 + position) & 1) << 1 | bits >> position & 1) as u32
+}
+
+fn delta_fits(first: &[i32; 3], second: &[i32; 3]) -> bool {
+    (0..3).all(|c| (-4..=3).contains(&(second[c] - first[c])))
+}
+
+fn offset_bounds(colour: &[i32; 3], below: i32, above: i32) -> ([i32; 3], [i32; 3]) {
+    let mut lo = [0; 3];
+    let mut hi = [0; 3];
+    for c in 0..3 {
+        lo[c] = (colour[c] + below).max(0);
+        hi[c] = (colour[c] + above).min(31);
+    }
+    (lo, hi)
+}
+
+fn quantise(value: f32, bits: u32) -> i32 {
+    let max = (1 << bits) - 1;
+    ((value * max as f32 / 255.0).round() as i32).clamp(0, max)
+}
+
+fn expand(value: i32, bits: u32) -> i32 {
+    (value << (8 - bits)) | (value >> (2 * bits - 8))
+}
+
+fn expand_colour(colour: &[i32; 3], bits: u32) -> [i32; 3] {
+    [
+        expand(colour[0], bits),
+        expand(colour[1], bits),
+        expand(colour[2], bits),
+    ]
+}
+
+fn offset(colour: &[i32; 3], amount: i32) -> [i32; 3] {
+    [
+        (colour[0] + amount).clamp(0, 255),
+        (colour[1] + amount).clamp(0, 255),
+        (colour[2] + amount).clamp(0, 255),
+    ]
+}
+
+fn packed_444(colour: &[i32; 3]) -> i32 {
+    colour[0] << 8 | colour[1] << 4 | colour[2]
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Etc2Mode {
+    Differential,
+    T,
+    H,
+    Planar,
+}
+
+// ETC2 signals its extra modes through differential colours that overflow: red for T,
+// green for H and blue for planar, checked in that order.
+fn mode_of(bits: u64) -> Etc2Mode {
+    let overflows = |shift: u32| {
+        let base = (bits >> shift & 0x1f) as i32;
+        let delta = ((bits >> (shift - 3) & 7) as i32 ^ 4) - 4;
+        base + delta < 0 || base + delta > 31
+    };
+    if overflows(59) {
+        Etc2Mode::T
+    } else if overflows(51) {
+        Etc2Mode::H
+    } else if overflows(43) {
+        Etc2Mode::Planar
+    } else {
+        Etc2Mode::Differential
+    }
+}
+
+// Sets the bits a mode leaves unused so that the block decodes in that mode.
+fn signal(bits: u64, unused: &[u32], mode: Etc2Mode) -> u64 {
+    for combination in 0..1u64 << unused.len() {
+        let mut candidate = bits;
+        for (i, &bit) in unused.iter().enumerate() {
+            candidate |= (combination >> i & 1) << bit;
+        }
+        if mode_of(candidate) == mode {
+            return candidate;
+        }
+    }
+    panic!("Internal error: can't signal ETC2 {:?} mode", mode);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mode_of, Etc2Mode};
+    use crate::compress::{
+        decode_block_for_test, image_for_test, rmse_for_test, round_trip_for_test, CompressOptions,
+        Quality, Texel,
+    };
+    use crate::Dimensions;
+
+    #[test]
+    fn individual() {
+        // 4-bit grey 8 with table 0 in the first subblock and grey 4 with table 7 in the
+        // second; texel (0, 0) takes index 1 and texel (3, 3) index 3, the rest index 0
+        for &flip in &[0, 1] {
+            let block = [0x84, 0x84, 0x84, 0x1c | flip, 0x80, 0x00, 0x80, 0x01];
+            for name in &["ETC1_R8G8B8_UNORM", "ETC2_R8G8B8_UNORM"] {
+                let texels = decode_block_for_test(name, &block);
+                for (i, texel) in texels.iter().enumerate() {
+                    let (x, y) = (i % 4, i / 4);
+                    let second = if flip == 1 { y >= 2 } else { x >= 2 };
+                    let grey = match (x, y) {
+                        (0, 0) => 0x88 + 8,
+                        (3, 3) => 0,
+                        _ if second => 0x44 + 47,
+                        _ => 0x88 + 2,
+                    };
+                    let grey = grey as f32 / 255.0;
+                    assert_eq!(
+                        *texel,
+                        [grey, grey, grey, 1.0],
+                        "{} flip {} texel {}",
+                        name,
+                        flip,
+                        i
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn differential() {
+        // base 5-bit 16, and a delta of -4 for the second subblock's 12, both table 0
+        let block = [0x84, 0x84, 0x84, 0x02, 0x00, 0x00, 0x00, 0x00];
+        let texels = decode_block_for_test("ETC1_R8G8B8_UNORM", &block);
+        let (first, second) = (0x84 as f32 + 2.0, 0x63 as f32 + 2.0);
+        assert_eq!(texels[0][0], first / 255.0);
+        assert_eq!(texels[3][0], second / 255.0);
+    }
+
+    fn options(quality: Quality, etc1_compatible: bool) -> CompressOptions {
+        CompressOptions {
+            quality,
+            etc1_compatible,
+            ..Default::default()
+        }
+    }
+
+    // the mode of a block that has its flag bit set, as ETC2 reads it
+    fn mode(block: &[u8]) -> Option<Etc2Mode> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&block[block.len() - 8..]);
+        let bits = u64::from_be_bytes(bytes);
+        if bits >> 33 & 1 == 1 {
+            Some(mode_of(bits))
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn etc2_modes() {
+        // a smooth gradient for planar; one colour set apart from three greys for T; and
+        // two colours, each a pair of shades, split along the diagonal for H
+        let planar: Vec<Texel> = (0..16)
+            .map(|i| {
+                let (x, y) = ((i % 4) as f32 / 3.0, (i / 4) as f32 / 3.0);
+                [0.2 + 0.6 * x, 0.7 - 0.5 * y, 0.3 + 0.3 * (x + y), 1.0]
+            })
+            .collect();
+        let t: Vec<Texel> = (0..16)
+            .map(|i| {
+                let grey = 0.3 + 0.1 * (i % 3) as f32;
+                if i % 5 == 0 {
+                    [0.9, 0.1, 0.1, 1.0]
+                } else {
+                    [grey, grey, grey, 1.0]
+                }
+            })
+            .collect();
+        let h: Vec<Texel> = (0..16)
+            .map(|i| {
+                let shade = if i % 2 == 0 { 0.06 } else { -0.06 };
+                let colour = if i % 4 > i / 4 {
+                    [0.1, 0.2, 0.8]
+                } else {
+                    [0.8, 0.7, 0.1]
+                };
+                [colour[0] + shade, colour[1] + shade, colour[2] + shade, 1.0]
+            })
+            .collect();
+
+        let dim = Dimensions::new(4, 4, 0);
+        let cases = [
+            (planar, Etc2Mode::Planar, 0.01),
+            (t, Etc2Mode::T, 0.03),
+            (h, Etc2Mode::H, 0.04),
+        ];
+        for (texels, expected, bound) in cases.iter() {
+            for &quality in &[Quality::Fast, Quality::Normal, Quality::Slow] {
+                let options = options(quality, false);
+                let (block, decoded) =
+                    round_trip_for_test("ETC2_R8G8B8_UNORM", &options, texels, dim);
+                assert_eq!(mode(&block), Some(*expected), "{:?}", quality);
+                let error = rmse_for_test(&decoded, texels, 3);
+                assert!(error < *bound, "{:?} {:?}: {}", expected, quality, error);
+            }
+            // ETC1 decoders would misread those modes, and can't get near them
+            let options = options(Quality::Normal, true);
+            let (block, decoded) = round_trip_for_test("ETC2_R8G8B8_UNORM", &options, texels, dim);
+            assert!(matches!(mode(&block), None | Some(Etc2Mode::Differential)));
+            assert!(rmse_for_test(&decoded, texels, 3) > 0.1, "{:?}", expected);
+        }
+    }
+
+    #[test]
+    fn quality_levels() {
+        let image = image_for_test();
+        let dim = Dimensions::new(16, 16, 0);
+        let qualities = [Quality::Fast, Quality::Normal, Quality::Slow];
+        let errors: Vec<f32> = qualities
+            .iter()
+            .map(|&quality| {
+                let options = options(quality, false);
+                let (_, decoded) =
+                    round_trip_for_test("ETC2_R8G8B8A8_UNORM", &options, &image, dim);
+                rmse_for_test(&decoded, &image, 4)
+            })
+            .collect();
+        assert!(errors[0] < 0.02, "{:?}", errors);
+        assert!(
+            errors[1] <= errors[0] && errors[2] <= errors[1],
+            "{:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn punch_through() {
+        // Texels below the alpha threshold come back transparent black, and only those.
+        // Blocks with any cost their planar mode and half their modifiers, so the colours
+        // are kept gentle.
+        let dim = Dimensions::new(8, 8, 0);
+        let texels: Vec<Texel> = (0..64)
+            .map(|i| {
+                let (x, y) = ((i % 8) as f32 / 7.0, (i / 8) as f32 / 7.0);
+                let alpha = if i % 3 == 0 { 0.2 } else { 1.0 };
+                [0.3 + 0.4 * x, 0.6 - 0.3 * y, 0.5, alpha]
+            })
+            .collect();
+        for &quality in &[Quality::Fast, Quality::Normal, Quality::Slow] {
+            let options = options(quality, false);
+            let (_, decoded) = round_trip_for_test("ETC2_R8G8B8A1_UNORM", &options, &texels, dim);
+            let mut opaque = (vec![], vec![]);
+            for (i, (texel, source)) in decoded.iter().zip(&texels).enumerate() {
+                if source[3] < 0.5 {
+                    assert_eq!(*texel, [0.0; 4], "texel {}", i);
+                } else {
+                    assert_eq!(texel[3], 1.0, "texel {}", i);
+                    opaque.0.push(*texel);
+                    opaque.1.push(*source);
+                }
+            }
+            let error = rmse_for_test(&opaque.0, &opaque.1, 3);
+            assert!(error < 0.05, "{:?}: {}", quality, error);
+        }
+    }
+}
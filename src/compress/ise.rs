@@ -0,0 +1,358 @@
+// ASTC's integer sequence encoding: values in a range of 2^n, 3 * 2^n or 5 * 2^n levels
+// are stored as n low bits each, with the trits or quints of five or three values packed
+// together into 8 or 7 bits.
+
+// A quantisation range, as a trit or quint (or neither) above some low bits.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Range {
+    pub trits: bool,
+    pub quints: bool,
+    pub bits: u32,
+}
+
+const fn range(trits: bool, quints: bool, bits: u32) -> Range {
+    Range {
+        trits,
+        quints,
+        bits,
+    }
+}
+
+// every range ASTC knows, in increasing order; weights use the first 12
+pub const RANGES: [Range; 21] = [
+    range(false, false, 1),
+    range(true, false, 0),
+    range(false, false, 2),
+    range(false, true, 0),
+    range(true, false, 1),
+    range(false, false, 3),
+    range(false, true, 1),
+    range(true, false, 2),
+    range(false, false, 4),
+    range(false, true, 2),
+    range(true, false, 3),
+    range(false, false, 5),
+    range(false, true, 3),
+    range(true, false, 4),
+    range(false, false, 6),
+    range(false, true, 4),
+    range(true, false, 5),
+    range(false, false, 7),
+    range(false, true, 5),
+    range(true, false, 6),
+    range(false, false, 8),
+];
+
+impl Range {
+    pub fn levels(self) -> u32 {
+        let base = if self.trits {
+            3
+        } else if self.quints {
+            5
+        } else {
+            1
+        };
+        base << self.bits
+    }
+}
+
+// the number of bits a sequence of count values takes up
+pub fn sequence_bits(count: usize, range: Range) -> u32 {
+    let count = count as u32;
+    let packed = if range.trits {
+        (8 * count).div_ceil(5)
+    } else if range.quints {
+        (7 * count).div_ceil(3)
+    } else {
+        0
+    };
+    count * range.bits + packed
+}
+
+// Appends the encoded sequence to bits, starting at position, and returns the position
+// just past it.
+pub fn encode(values: &[u32], range: Range, bits: &mut u128, position: u32) -> u32 {
+    let (group, spread): (usize, &[u32]) = if range.trits {
+        (5, &[2, 2, 1, 2, 1])
+    } else if range.quints {
+        (3, &[3, 2, 2])
+    } else {
+        (1, &[0])
+    };
+    let mask = (1 << range.bits) - 1;
+
+    let mut position = position;
+    for chunk in values.chunks(group) {
+        let high: Vec<u32> = chunk.iter().map(|v| v >> range.bits).collect();
+        let packed = if range.trits {
+            pack(&high, 3, decode_trits)
+        } else if range.quints {
+            pack(&high, 5, decode_quints)
+        } else {
+            0
+        };
+        let mut shift = 0;
+        for (value, &count) in chunk.iter().zip(spread) {
+            *bits |= u128::from(value & mask) << position;
+            position += range.bits;
+            *bits |= u128::from((packed >> shift) & ((1 << count) - 1)) << position;
+            position += count;
+            shift += count;
+        }
+    }
+    position
+}
+
+// Reads count values of a sequence starting at position, the inverse of encode. Bits
+// past the end of the block read as zero.
+pub fn decode(bits: u128, range: Range, position: u32, count: usize) -> Vec<u32> {
+    let (group, spread): (usize, &[u32]) = if range.trits {
+        (5, &[2, 2, 1, 2, 1])
+    } else if range.quints {
+        (3, &[3, 2, 2])
+    } else {
+        (1, &[0])
+    };
+    let read = |position: u32, count: u32| {
+        if count == 0 || position >= 128 {
+            0
+        } else {
+            ((bits >> position) & ((1 << count) - 1)) as u32
+        }
+    };
+
+    let mut values = Vec::with_capacity(count);
+    let mut position = position;
+    while values.len() < count {
+        let in_group = group.min(count - values.len());
+        let mut low = vec![];
+        let (mut packed, mut shift) = (0, 0);
+        for &spread_bits in &spread[..in_group] {
+            low.push(read(position, range.bits));
+            position += range.bits;
+            packed |= read(position, spread_bits) << shift;
+            position += spread_bits;
+            shift += spread_bits;
+        }
+        let high = if range.trits {
+            decode_trits(packed)
+        } else if range.quints {
+            decode_quints(packed)
+        } else {
+            [0; 5]
+        };
+        for (i, low) in low.iter().enumerate() {
+            values.push(high[i] << range.bits | low);
+        }
+    }
+    values
+}
+
+// The packed encoding of some trits or quints. A short final group drops its top bits,
+// which the decoder reads as zero, so search for an encoding where they are.
+fn pack(high: &[u32], radix: u32, decode: fn(u32) -> [u32; 5]) -> u32 {
+    let index = high.iter().rev().fold(0, |index, &h| index * radix + h);
+    let full = if radix == 3 { 5 } else { 3 };
+    if high.len() == full {
+        let table: &[u8] = if radix == 3 {
+            &TRIT_ENCODINGS
+        } else {
+            &QUINT_ENCODINGS
+        };
+        return u32::from(table[index as usize]);
+    }
+    let kept = if radix == 3 {
+        (8 * high.len() as u32).div_ceil(5)
+    } else {
+        (7 * high.len() as u32).div_ceil(3)
+    };
+    (0..1 << kept)
+        .find(|&packed| decode(packed)[..high.len()] == *high)
+        .unwrap()
+}
+
+lazy_static! {
+    static ref TRIT_ENCODINGS: Vec<u8> = invert(243, 256, 3, decode_trits);
+    static ref QUINT_ENCODINGS: Vec<u8> = invert(125, 128, 5, decode_quints);
+}
+
+fn invert(count: u32, encodings: u32, radix: u32, decode: fn(u32) -> [u32; 5]) -> Vec<u8> {
+    let mut table = vec![0u8; count as usize];
+    // walk backwards so that the lowest encoding of each combination wins
+    for packed in (0..encodings).rev() {
+        let index = decode(packed)
+            .iter()
+            .rev()
+            .fold(0, |index, &value| index * radix + value);
+        if index < count {
+            table[index as usize] = packed as u8;
+        }
+    }
+    table
+}
+
+fn bit(value: u32, n: u32) -> u32 {
+    (value >> n) & 1
+}
+
+fn bits(value: u32, high: u32, low: u32) -> u32 {
+    (value >> low) & ((1 << (high - low + 1)) - 1)
+}
+
+// the five trits that 8 packed bits hold, following the specification's decoding
+fn decode_trits(t: u32) -> [u32; 5] {
+    let (c, t3, t4);
+    if bits(t, 4, 2) == 7 {
+        c = bits(t, 7, 5) << 2 | bits(t, 1, 0);
+        t4 = 2;
+        t3 = 2;
+    } else {
+        c = bits(t, 4, 0);
+        if bits(t, 6, 5) == 3 {
+            t4 = 2;
+            t3 = bit(t, 7);
+        } else {
+            t4 = bit(t, 7);
+            t3 = bits(t, 6, 5);
+        }
+    }
+    let (t0, t1, t2);
+    if bits(c, 1, 0) == 3 {
+        t2 = 2;
+        t1 = bit(c, 4);
+        t0 = bit(c, 3) << 1 | (bit(c, 2) & !bit(c, 3) & 1);
+    } else if bits(c, 3, 2) == 3 {
+        t2 = 2;
+        t1 = 2;
+        t0 = bits(c, 1, 0);
+    } else {
+        t2 = bit(c, 4);
+        t1 = bits(c, 3, 2);
+        t0 = bit(c, 1) << 1 | (bit(c, 0) & !bit(c, 1) & 1);
+    }
+    [t0, t1, t2, t3, t4]
+}
+
+// the three quints that 7 packed bits hold, padded to match decode_trits
+fn decode_quints(q: u32) -> [u32; 5] {
+    let (q0, q1, q2);
+    if bits(q, 2, 1) == 3 && bits(q, 6, 5) == 0 {
+        q2 = bit(q, 0) << 2 | (bit(q, 4) & !bit(q, 0) & 1) << 1 | (bit(q, 3) & !bit(q, 0) & 1);
+        q1 = 4;
+        q0 = 4;
+    } else {
+        let c;
+        if bits(q, 2, 1) == 3 {
+            q2 = 4;
+            c = bits(q, 4, 3) << 3 | (!bits(q, 6, 5) & 3) << 1 | bit(q, 0);
+        } else {
+            q2 = bits(q, 6, 5);
+            c = bits(q, 4, 0);
+        }
+        if bits(c, 2, 0) == 5 {
+            q1 = 4;
+            q0 = bits(c, 4, 3);
+        } else {
+            q1 = bits(c, 4, 3);
+            q0 = bits(c, 2, 0);
+        }
+    }
+    [q0, q1, q2, 0, 0]
+}
+
+// The colour endpoint value, in [0, 255], that an encoded value stands for. Colour
+// endpoints never use the ranges below six levels.
+pub fn unquantise_colour(value: u32, range: Range) -> u32 {
+    if !range.trits && !range.quints {
+        return replicate(value, range.bits, 8);
+    }
+    let (high, low) = (value >> range.bits, value & ((1 << range.bits) - 1));
+    let a = if low & 1 == 1 { 0x1ff } else { 0 };
+    let (b, c) = match (range.trits, range.bits) {
+        (true, 1) => (0, 204),
+        (true, 2) => (bit(low, 1) * 0x116, 93),
+        (true, 3) => (bit(low, 2) * 0x10a + bit(low, 1) * 0x85, 44),
+        (true, 4) => (
+            bit(low, 3) * 0x104 + bit(low, 2) * 0x82 + bit(low, 1) * 0x41,
+            22,
+        ),
+        (true, 5) => (
+            bit(low, 4) * 0x102 + bit(low, 3) * 0x81 + bit(low, 2) * 0x40 + bit(low, 1) * 0x20,
+            11,
+        ),
+        (true, _) => (
+            bit(low, 5) * 0x101
+                + bit(low, 4) * 0x80
+                + bit(low, 3) * 0x40
+                + bit(low, 2) * 0x20
+                + bit(low, 1) * 0x10,
+            5,
+        ),
+        (false, 1) => (0, 113),
+        (false, 2) => (bit(low, 1) * 0x10c, 54),
+        (false, 3) => (bit(low, 2) * 0x105 + bit(low, 1) * 0x82, 26),
+        (false, 4) => (
+            bit(low, 3) * 0x102 + bit(low, 2) * 0x81 + bit(low, 1) * 0x40,
+            13,
+        ),
+        (false, _) => (
+            bit(low, 4) * 0x101 + bit(low, 3) * 0x80 + bit(low, 2) * 0x40 + bit(low, 1) * 0x20,
+            6,
+        ),
+    };
+    let t = (high * c + b) ^ a;
+    (a & 0x80) | (t >> 2)
+}
+
+// The weight, in [0, 64], that an encoded value stands for.
+pub fn unquantise_weight(value: u32, range: Range) -> u32 {
+    let weight = if !range.trits && !range.quints {
+        replicate(value, range.bits, 6)
+    } else if range.bits == 0 {
+        let table: &[u32] = if range.trits {
+            &[0, 32, 63]
+        } else {
+            &[0, 16, 32, 47, 63]
+        };
+        table[value as usize]
+    } else {
+        let (high, low) = (value >> range.bits, value & ((1 << range.bits) - 1));
+        let a = if low & 1 == 1 { 0x7f } else { 0 };
+        let (b, c) = match (range.trits, range.bits) {
+            (true, 1) => (0, 50),
+            (true, 2) => (bit(low, 1) * 0x45, 23),
+            (true, _) => (bit(low, 2) * 0x42 + bit(low, 1) * 0x21, 11),
+            (false, 1) => (0, 28),
+            (false, _) => (bit(low, 1) * 0x42, 13),
+        };
+        let t = (high * c + b) ^ a;
+        (a & 0x20) | (t >> 2)
+    };
+    if weight > 32 {
+        weight + 1
+    } else {
+        weight
+    }
+}
+
+fn replicate(value: u32, bits: u32, to: u32) -> u32 {
+    let mut result = 0;
+    let mut filled = 0;
+    while filled < to {
+        result = result << bits | value;
+        filled += bits;
+    }
+    result >> (filled - to)
+}
+
+// For each value in [0, max], the encoded value whose unquantised value is nearest.
+pub fn quantisation_table(range: Range, max: u32, unquantise: fn(u32, Range) -> u32) -> Vec<u32> {
+    let values: Vec<u32> = (0..range.levels()).map(|v| unquantise(v, range)).collect();
+    (0..=max)
+        .map(|target| {
+            (0..range.levels())
+                .min_by_key(|&v| (values[v as usize] as i32 - target as i32).abs())
+                .unwrap()
+        })
+        .collect()
+}
@@ -0,0 +1,421 @@
+use std::cmp;
+
+use crate::pixel::decode::decode_f64;
+use crate::pixel::encode::encode;
+use crate::pixel::CompContent::*;
+use crate::pixel::{srgb, PixelFormat};
+use crate::{Dimensions, ImageBlob, RuxResult, Texture};
+
+mod astc;
+mod bc1;
+mod bc4;
+mod bc6h;
+mod bc7;
+mod bptc;
+mod eac;
+mod etc;
+mod ise;
+
+// Block encoders see texels as RGBA floats, normalised to [0, 1] for LDR sources.
+pub type Texel = [f32; 4];
+
+clap::arg_enum! {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Quality {
+    Fast,
+    Normal,
+    Slow,
+}
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CompressOptions {
+    pub quality: Quality,
+    // BC1 texels with an alpha below this become punch-through transparent
+    pub alpha_threshold: u8,
+    // BC5 and ASTC store normalised X/Y of a tangent-space normal, leaving Z to be
+    // reconstructed; ASTC keeps X in RGB and Y in alpha
+    pub normal_map: bool,
+    // BC7, ETC and ASTC weigh colour error by luma rather than treating channels equally
+    pub perceptual: bool,
+    // ETC2 targets only use the modes that ETC1 decoders understand
+    pub etc1_compatible: bool,
+}
+
+impl Default for CompressOptions {
+    fn default() -> CompressOptions {
+        CompressOptions {
+            quality: Quality::Normal,
+            alpha_threshold: 128,
+            normal_map: false,
+            perceptual: false,
+            etc1_compatible: false,
+        }
+    }
+}
+
+trait BlockEncoder {
+    fn encode_block(&self, block: &[Texel], out: &mut Vec<u8>);
+}
+
+fn encoder(target: &PixelFormat, options: &CompressOptions) -> Option<Box<dyn BlockEncoder>> {
+    match target.tag {
+        "BC1" | "BC2" | "BC3" => Some(Box::from(bc1::Bc1Encoder::new(target, options))),
+        "BC4" | "BC5" => Some(Box::from(bc4::Bc4Encoder::new(target, options))),
+        "BC6H" => Some(Box::from(bc6h::Bc6hEncoder::new(target, options))),
+        "BC7" => Some(Box::from(bc7::Bc7Encoder::new(options))),
+        "ETC1" | "ETC2" | "ETC2_A1" => Some(Box::from(etc::EtcEncoder::new(target, options))),
+        "EAC" => Some(Box::from(eac::EacEncoder::new(target, options))),
+        "ASTC" => Some(Box::from(astc::AstcEncoder::new(target, options))),
+        _ => None,
+    }
+}
+
+// Block decoders produce texels in the format's own range: [-1, 1] for SNORM, encoded
+// values for sRGB and floats for BC6H.
+trait BlockDecoder {
+    fn block_bytes(&self) -> usize;
+    fn decode_block(&self, block: &[u8], out: &mut [Texel]);
+}
+
+fn decoder(format: &PixelFormat) -> Option<Box<dyn BlockDecoder>> {
+    match format.tag {
+        "BC1" | "BC2" | "BC3" => Some(Box::from(bc1::Bc1Decoder::new(format))),
+        "BC4" | "BC5" => Some(Box::from(bc4::Bc4Decoder::new(format))),
+        "BC6H" => Some(Box::from(bc6h::Bc6hDecoder::new(format))),
+        "BC7" => Some(Box::from(bc7::Bc7Decoder)),
+        "ETC1" | "ETC2" | "ETC2_A1" => Some(Box::from(etc::EtcDecoder::new(format))),
+        "EAC" => Some(Box::from(eac::EacDecoder::new(format))),
+        "ASTC" => Some(Box::from(astc::AstcDecoder::new(format))),
+        _ => None,
+    }
+}
+
+pub fn compress(
+    texture: &Texture,
+    target: &PixelFormat,
+    options: &CompressOptions,
+) -> RuxResult<Texture> {
+    let mut mip_blobs = vec![];
+    for (level, blob) in texture.mip_blobs.iter().enumerate() {
+        let dim = texture.mip_dim(level);
+        let texels = source_texels(&texture.format, target, options, blob)?;
+        let image_count = texture.image_count() * dim.depth();
+        match compress_texels(target, options, &texels, dim, image_count) {
+            Ok(mip_blob) => mip_blobs.push(mip_blob),
+            Err(e) => bail!(format!("Mip level {}: {}", level, e)),
+        }
+    }
+
+    Ok(Texture {
+        format: *target,
+        pixel_dim: texture.pixel_dim,
+        array_size: texture.array_size,
+        face_count: texture.face_count,
+        mip_blobs,
+    })
+}
+
+// Compresses one mip level of 2D images, one after another, from texels as block
+// encoders see them.
+pub fn compress_texels(
+    target: &PixelFormat,
+    options: &CompressOptions,
+    texels: &[Texel],
+    dim: Dimensions,
+    image_count: u32,
+) -> RuxResult<ImageBlob> {
+    if !target.is_compressed() {
+        bail!(format!(
+            "Can't compress into uncompressed format {}.",
+            target
+        ));
+    }
+    let encoder = match encoder(target, options) {
+        Some(encoder) => encoder,
+        None => bail!(format!("Can't compress into {} yet.", target)),
+    };
+    let image_size = (dim.width() * dim.height()) as usize;
+    if texels.len() != image_size * image_count as usize {
+        bail!(format!(
+            "{} texels don't make {} images of {}x{}.",
+            texels.len(),
+            image_count,
+            dim.width(),
+            dim.height()
+        ));
+    }
+
+    let mut blob = ImageBlob::new();
+    for image in texels.chunks(image_size) {
+        encode_image(
+            encoder.as_ref(),
+            image,
+            value_range(target),
+            (dim.width(), dim.height()),
+            target.block_extent(),
+            &mut blob,
+        );
+    }
+    Ok(blob)
+}
+
+// Decodes one mip level of 2D images, one after another, into texels in the format's own
+// range, as decode does for uncompressed formats.
+pub fn decompress_texels(
+    format: &PixelFormat,
+    blob: &[u8],
+    dim: Dimensions,
+    image_count: u32,
+) -> RuxResult<Vec<Texel>> {
+    let decoder = match decoder(format) {
+        Some(decoder) => decoder,
+        None => bail!(format!("Can't decompress {} yet.", format)),
+    };
+    let (width, height) = (dim.width(), dim.height());
+    let (block_w, block_h) = format.block_extent();
+    let (blocks_x, blocks_y) = (width.div_ceil(block_w), height.div_ceil(block_h));
+    let image_bytes = (blocks_x * blocks_y) as usize * decoder.block_bytes();
+    if blob.len() != image_bytes * image_count as usize {
+        bail!(format!(
+            "Image data of {} bytes doesn't hold {} {}x{} images of {}.",
+            blob.len(),
+            image_count,
+            width,
+            height,
+            format
+        ));
+    }
+
+    let image_size = (width * height) as usize;
+    let mut texels = vec![[0.0; 4]; image_size * image_count as usize];
+    let mut block = vec![[0.0; 4]; (block_w * block_h) as usize];
+    let blocks = blob.chunks_exact(decoder.block_bytes());
+    for (i, encoded) in blocks.enumerate() {
+        let image = i / (blocks_x * blocks_y) as usize;
+        let (bx, by) = (i as u32 % blocks_x, i as u32 / blocks_x % blocks_y);
+        decoder.decode_block(encoded, &mut block);
+        // partial blocks along the right and bottom edges drop what lies outside
+        for y in 0..block_h.min(height - by * block_h) {
+            for x in 0..block_w.min(width - bx * block_w) {
+                let texel = ((by * block_h + y) * width + bx * block_w + x) as usize;
+                texels[image * image_size + texel] = block[(y * block_w + x) as usize];
+            }
+        }
+    }
+    Ok(texels)
+}
+
+// decodes a single block of the named format, one footprint of texels
+#[cfg(test)]
+pub(crate) fn decode_block_for_test(name: &str, block: &[u8]) -> Vec<Texel> {
+    let format = crate::pixel::format_for_test(name);
+    let (width, height) = format.block_extent();
+    decompress_texels(format, block, Dimensions::new(width, height, 0), 1).unwrap()
+}
+
+// compresses one image into the named format and decodes it again, with the blob
+#[cfg(test)]
+pub(crate) fn round_trip_for_test(
+    name: &str,
+    options: &CompressOptions,
+    texels: &[Texel],
+    dim: Dimensions,
+) -> (ImageBlob, Vec<Texel>) {
+    let format = crate::pixel::format_for_test(name);
+    let blob = compress_texels(format, options, texels, dim, 1).unwrap();
+    let decoded = decompress_texels(format, &blob, dim, 1).unwrap();
+    (blob, decoded)
+}
+
+// the root mean square error over the first few channels
+#[cfg(test)]
+pub(crate) fn rmse_for_test(actual: &[Texel], expected: &[Texel], channels: usize) -> f32 {
+    let squared: f32 = actual
+        .iter()
+        .zip(expected)
+        .flat_map(|(a, e)| (0..channels).map(move |c| (a[c] - e[c]) * (a[c] - e[c])))
+        .sum();
+    (squared / (expected.len() * channels) as f32).sqrt()
+}
+
+// A 16x16 gradient in every channel, alpha included, with a flat colour cut off by a
+// diagonal edge in the lower right, for round trips to measure.
+#[cfg(test)]
+pub(crate) fn image_for_test() -> Vec<Texel> {
+    (0..256)
+        .map(|i| {
+            let (x, y) = ((i % 16) as f32 / 15.0, (i / 16) as f32 / 15.0);
+            if x > 0.5 && x + y > 1.2 {
+                [0.9, 0.2, 0.1, 1.0]
+            } else {
+                [x, y, 1.0 - x * y, 0.5 + 0.5 * y]
+            }
+        })
+        .collect()
+}
+
+// Decompresses every mip level into an uncompressed format that holds the decoded values.
+pub fn decompress(texture: &Texture) -> RuxResult<Texture> {
+    let target = decompressed_format(&texture.format)?;
+    let mut mip_blobs = vec![];
+    for (level, blob) in texture.mip_blobs.iter().enumerate() {
+        let dim = texture.mip_dim(level);
+        let image_count = texture.image_count() * dim.depth();
+        let texels = decompress_texels(&texture.format, blob, dim, image_count)?;
+        mip_blobs.push(encode(target, &texels)?);
+    }
+    Ok(Texture {
+        format: *target,
+        pixel_dim: texture.pixel_dim,
+        array_size: texture.array_size,
+        face_count: texture.face_count,
+        mip_blobs,
+    })
+}
+
+// eight bits per channel, except for the 11-bit EAC channels and BC6H's halves
+fn decompressed_format(format: &PixelFormat) -> RuxResult<&'static PixelFormat> {
+    if !format.is_compressed() {
+        bail!(format!("{} isn't a compressed format.", format));
+    }
+    let name = match (format.tag, format.comp_content) {
+        ("BC6H", _) => "R16G16B16A16_SFLOAT",
+        ("EAC", SNORM) => "R16G16B16A16_SNORM",
+        ("EAC", _) => "R16G16B16A16_UNORM",
+        (_, SNORM) => "R8G8B8A8_SNORM",
+        (_, SRGB) => "R8G8B8A8_SRGB",
+        _ => "R8G8B8A8_UNORM",
+    };
+    match PixelFormat::for_name(name) {
+        Some(target) => Ok(target),
+        None => bail!(format!("Can't decompress {}; no {} format.", format, name)),
+    }
+}
+
+// partial blocks along the right and bottom edges replicate the last column and row
+fn encode_image(
+    encoder: &dyn BlockEncoder,
+    image: &[Texel],
+    range: (f32, f32),
+    (width, height): (u32, u32),
+    (block_w, block_h): (u32, u32),
+    out: &mut Vec<u8>,
+) {
+    let mut block = Vec::with_capacity((block_w * block_h) as usize);
+    for y0 in (0..height).step_by(block_h as usize) {
+        for x0 in (0..width).step_by(block_w as usize) {
+            block.clear();
+            for y in y0..y0 + block_h {
+                let row = cmp::min(y, height - 1) * width;
+                for x in x0..x0 + block_w {
+                    block.push(clamped(
+                        image[(row + cmp::min(x, width - 1)) as usize],
+                        range,
+                    ));
+                }
+            }
+            encoder.encode_block(&block, out);
+        }
+    }
+}
+
+// Encoders search by comparing errors, which NaN can't take part in and out-of-range
+// values distort, so texels are clamped to what the target holds, with NaN as zero.
+fn clamped(texel: Texel, (min, max): (f32, f32)) -> Texel {
+    texel.map(|value| {
+        if value.is_nan() {
+            0.0
+        } else {
+            value.clamp(min, max)
+        }
+    })
+}
+
+// the values a compressed format holds, with halves the widest of them
+fn value_range(format: &PixelFormat) -> (f32, f32) {
+    match format.comp_content {
+        SNORM => (-1.0, 1.0),
+        UFLOAT => (0.0, 65504.0),
+        SFLOAT => (-65504.0, 65504.0),
+        _ => (0.0, 1.0),
+    }
+}
+
+// the mean of the texels and the direction of greatest variance through it, for the
+// channels first..last
+fn principal_axis(texels: &[Texel], first: usize, last: usize) -> (Texel, Texel) {
+    let n = texels.len() as f32;
+    let mut mean = [0.0f32; 4];
+    for texel in texels {
+        for c in first..last {
+            mean[c] += texel[c] / n;
+        }
+    }
+    let mut cov = [[0.0f32; 4]; 4];
+    for texel in texels {
+        for i in first..last {
+            for j in first..last {
+                cov[i][j] += (texel[i] - mean[i]) * (texel[j] - mean[j]);
+            }
+        }
+    }
+    let mut axis = [0.0f32; 4];
+    for value in &mut axis[first..last] {
+        *value = 1.0;
+    }
+    for _ in 0..8 {
+        let mut next = [0.0f32; 4];
+        for i in first..last {
+            for j in first..last {
+                next[i] += cov[i][j] * axis[j];
+            }
+        }
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        for c in first..last {
+            axis[c] = next[c] / length;
+        }
+    }
+    let length = axis.iter().map(|v| v * v).sum::<f32>().sqrt();
+    for value in &mut axis[first..last] {
+        *value /= length;
+    }
+    (mean, axis)
+}
+
+// Normal map encoders read vectors as colours in [0, 1], with zero at 0.5, so SNORM
+// vectors are moved there first. Everything else reaches encoders in its own range.
+pub(crate) fn unit_colour(texel: &mut Texel) {
+    for value in texel.iter_mut() {
+        *value = (*value + 1.0) / 2.0;
+    }
+}
+
+fn source_texels(
+    format: &PixelFormat,
+    target: &PixelFormat,
+    options: &CompressOptions,
+    blob: &[u8],
+) -> RuxResult<Vec<Texel>> {
+    if format.is_compressed() {
+        bail!(format!("Can't compress from compressed format {}.", format));
+    }
+    let mut texels = decode_f64(format, blob)?;
+    srgb::transfer(&mut texels, format.is_srgb(), target.is_srgb());
+    let mut texels: Vec<Texel> = texels
+        .iter()
+        .map(|t| [t[0] as f32, t[1] as f32, t[2] as f32, t[3] as f32])
+        .collect();
+    match format.comp_content {
+        UINT | SINT | SPECIAL => bail!(format!(
+            "Can't compress from {}; integer, depth and stencil sources have no colour.",
+            format
+        )),
+        SNORM if options.normal_map => texels.iter_mut().for_each(unit_colour),
+        _ => (),
+    }
+    Ok(texels)
+}
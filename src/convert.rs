@@ -0,0 +1,152 @@
+use crate::compress::{compress, decompress, CompressOptions};
+use crate::pixel::decode::{decode_f64, decode_i64};
+use crate::pixel::encode::{encode_f64, encode_i64};
+use crate::pixel::CompContent::*;
+use crate::pixel::{srgb, PixelFormat};
+use crate::{RuxResult, Texture};
+
+// Re-encodes every mip level into another format. Compressed sources are decompressed
+// first, and compressed targets go to the block encoders with the given options. Otherwise
+// values go through canonical RGBA, so integer formats convert exactly between each other,
+// and through f64 for everything else. Colour is linearised or sRGB encoded when the
+// source and target colour spaces differ.
+pub fn convert(
+    texture: &Texture,
+    target: &PixelFormat,
+    options: &CompressOptions,
+) -> RuxResult<Texture> {
+    if texture.format.is_compressed() {
+        if texture.format == *target {
+            // recompressing would only lose quality
+            return Ok(Texture {
+                mip_blobs: texture.mip_blobs.clone(),
+                ..*texture
+            });
+        }
+        return convert(&decompress(texture)?, target, options);
+    }
+    if target.is_compressed() {
+        return compress(texture, target, options);
+    }
+    let is_integer = |format: &PixelFormat| matches!(format.comp_content, UINT | SINT);
+
+    let mut mip_blobs = vec![];
+    for blob in &texture.mip_blobs {
+        let mip_blob = if is_integer(&texture.format) && is_integer(target) {
+            encode_i64(target, &decode_i64(&texture.format, blob)?)?
+        } else {
+            let mut texels = decode_f64(&texture.format, blob)?;
+            srgb::transfer(&mut texels, texture.format.is_srgb(), target.is_srgb());
+            encode_f64(target, &texels)?
+        };
+        mip_blobs.push(mip_blob);
+    }
+
+    Ok(Texture {
+        format: *target,
+        pixel_dim: texture.pixel_dim,
+        array_size: texture.array_size,
+        face_count: texture.face_count,
+        mip_blobs,
+    })
+}
+
+// Relabels a texture as sRGB or linear without touching its data, compressed or not; the
+// fix for textures whose colour space was declared wrong.
+pub fn reinterpret(texture: &Texture, srgb: bool) -> RuxResult<Texture> {
+    let format = match texture.format.in_colour_space(srgb) {
+        Some(format) => format,
+        None => bail!(format!(
+            "{} has no {} counterpart.",
+            texture.format,
+            if srgb { "sRGB" } else { "linear" }
+        )),
+    };
+    Ok(Texture {
+        format: *format,
+        mip_blobs: texture.mip_blobs.clone(),
+        ..*texture
+    })
+}
+
+// The depth plane of a depth or depth/stencil texture, as R32_SFLOAT.
+pub fn extract_depth(texture: &Texture) -> RuxResult<Texture> {
+    if !texture.format.has_depth() {
+        bail!(format!("{} has no depth to extract.", texture.format));
+    }
+    // depth always decodes into red
+    extract_plane(texture, 0, "R32_SFLOAT")
+}
+
+// The stencil plane of a stencil or depth/stencil texture, as S8_UINT.
+pub fn extract_stencil(texture: &Texture) -> RuxResult<Texture> {
+    if !texture.format.has_stencil() {
+        bail!(format!("{} has no stencil to extract.", texture.format));
+    }
+    // stencil decodes into green alongside depth, and into red on its own
+    let channel = if texture.format.has_depth() { 1 } else { 0 };
+    extract_plane(texture, channel, "S8_UINT")
+}
+
+fn extract_plane(texture: &Texture, channel: usize, name: &str) -> RuxResult<Texture> {
+    let target = PixelFormat::for_name(name).unwrap();
+    let mut mip_blobs = vec![];
+    for blob in &texture.mip_blobs {
+        let texels: Vec<[f64; 4]> = decode_f64(&texture.format, blob)?
+            .iter()
+            .map(|texel| [texel[channel], 0.0, 0.0, 1.0])
+            .collect();
+        mip_blobs.push(encode_f64(target, &texels)?);
+    }
+    Ok(Texture {
+        format: *target,
+        mip_blobs,
+        ..*texture
+    })
+}
+
+// Merges a depth plane, in any format whose red holds depth, and an S8_UINT stencil plane
+// into a combined depth/stencil format. The planes must match in shape and mip count.
+pub fn merge_depth_stencil(
+    depth: &Texture,
+    stencil: &Texture,
+    target: &PixelFormat,
+) -> RuxResult<Texture> {
+    if !target.has_depth() || !target.has_stencil() {
+        bail!(format!("{} isn't a combined depth/stencil format.", target));
+    }
+    if stencil.format.has_depth() || !stencil.format.has_stencil() {
+        bail!(format!(
+            "Can't merge {} as a stencil plane; extract the stencil first.",
+            stencil.format
+        ));
+    }
+    if depth.pixel_dim != stencil.pixel_dim
+        || depth.image_count() != stencil.image_count()
+        || depth.mip_blobs.len() != stencil.mip_blobs.len()
+    {
+        bail!("Depth and stencil planes differ in dimensions, images or mip levels.");
+    }
+
+    let mut mip_blobs = vec![];
+    for (depth_blob, stencil_blob) in depth.mip_blobs.iter().zip(&stencil.mip_blobs) {
+        let depths = decode_f64(&depth.format, depth_blob)?;
+        let stencils = decode_f64(&stencil.format, stencil_blob)?;
+        if depths.len() != stencils.len() {
+            bail!("Depth and stencil planes differ in texel count.");
+        }
+        let texels: Vec<[f64; 4]> = depths
+            .iter()
+            .zip(&stencils)
+            .map(|(d, s)| [d[0], s[0], 0.0, 1.0])
+            .collect();
+        mip_blobs.push(encode_f64(target, &texels)?);
+    }
+    Ok(Texture {
+        format: *target,
+        pixel_dim: depth.pixel_dim,
+        array_size: depth.array_size,
+        face_count: depth.face_count,
+        mip_blobs,
+    })
+}
@@ -0,0 +1,496 @@
+// Conversions between cubemaps and other ways of laying out the sphere of directions.
+// Faces go +X, -X, +Y, -Y, +Z, -Z, as every container stores them, each with its top row
+// first.
+
+use std::f64::consts::PI;
+
+use crate::compress::CompressOptions;
+use crate::pixel::srgb;
+use crate::resample::{check_filterable, decode_level, encode_level};
+use crate::{Dimensions, RuxResult, Texture};
+
+clap::arg_enum! {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CubeConvention {
+    // right-handed, looking down -Z, as GL, Vulkan and KTX have it
+    Vulkan,
+    // left-handed, looking down +Z, as D3D and DDS have it; the same faces seen from a
+    // world with Z mirrored
+    D3D,
+}
+}
+
+clap::arg_enum! {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CubeLayout {
+    // 4x3 faces, with +Y, then -X +Z +X -Z, then -Y
+    HorizontalCross,
+    // 3x4 faces, with +Y, then -X +Z +X, then -Y, then -Z upside down
+    VerticalCross,
+    // 6x1 faces in cubemap order
+    HorizontalStrip,
+    // 1x6 faces in cubemap order
+    VerticalStrip,
+}
+}
+
+impl CubeLayout {
+    // the layout whose proportions the dimensions have, if any
+    pub fn detect(dim: Dimensions) -> Option<CubeLayout> {
+        let (width, height) = (dim.width(), dim.height());
+        [
+            CubeLayout::HorizontalCross,
+            CubeLayout::VerticalCross,
+            CubeLayout::HorizontalStrip,
+            CubeLayout::VerticalStrip,
+        ]
+        .iter()
+        .cloned()
+        .find(|layout| {
+            let (columns, rows) = layout.grid();
+            width % columns == 0 && height % rows == 0 && width / columns == height / rows
+        })
+    }
+
+    // the layout's size in faces
+    fn grid(self) -> (u32, u32) {
+        match self {
+            CubeLayout::HorizontalCross => (4, 3),
+            CubeLayout::VerticalCross => (3, 4),
+            CubeLayout::HorizontalStrip => (6, 1),
+            CubeLayout::VerticalStrip => (1, 6),
+        }
+    }
+
+    // the column and row of each face, and whether it's turned upside down
+    fn cells(self) -> [(u32, u32, bool); 6] {
+        match self {
+            CubeLayout::HorizontalCross => [
+                (2, 1, false),
+                (0, 1, false),
+                (1, 0, false),
+                (1, 2, false),
+                (1, 1, false),
+                (3, 1, false),
+            ],
+            CubeLayout::VerticalCross => [
+                (2, 1, false),
+                (0, 1, false),
+                (1, 0, false),
+                (1, 2, false),
+                (1, 1, false),
+                (1, 3, true),
+            ],
+            CubeLayout::HorizontalStrip => [
+                (0, 0, false),
+                (1, 0, false),
+                (2, 0, false),
+                (3, 0, false),
+                (4, 0, false),
+                (5, 0, false),
+            ],
+            CubeLayout::VerticalStrip => [
+                (0, 0, false),
+                (0, 1, false),
+                (0, 2, false),
+                (0, 3, false),
+                (0, 4, false),
+                (0, 5, false),
+            ],
+        }
+    }
+}
+
+// The direction through a point on a face, with s and t in [-1, 1] running right and
+// down the face image, as the GL and D3D face selection tables have it.
+pub fn face_direction(face: usize, s: f64, t: f64) -> [f64; 3] {
+    match face {
+        0 => [1.0, -t, -s],
+        1 => [-1.0, -t, s],
+        2 => [s, 1.0, t],
+        3 => [s, -1.0, -t],
+        4 => [s, -t, 1.0],
+        _ => [-s, -t, -1.0],
+    }
+}
+
+// The face a direction points through and where on it, the inverse of face_direction.
+pub fn direction_face(direction: [f64; 3]) -> (usize, f64, f64) {
+    let [x, y, z] = direction;
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    if ax >= ay && ax >= az {
+        if x > 0.0 {
+            (0, -z / ax, -y / ax)
+        } else {
+            (1, z / ax, -y / ax)
+        }
+    } else if ay >= az {
+        if y > 0.0 {
+            (2, x / ay, z / ay)
+        } else {
+            (3, x / ay, -z / ay)
+        }
+    } else if z > 0.0 {
+        (4, x / az, -y / az)
+    } else {
+        (5, -x / az, -y / az)
+    }
+}
+
+// the direction at the centre of a face texel, normalised
+pub fn texel_direction(face: usize, x: f64, y: f64, size: u32) -> [f64; 3] {
+    let s = 2.0 * x / f64::from(size) - 1.0;
+    let t = 2.0 * y / f64::from(size) - 1.0;
+    normalise(face_direction(face, s, t))
+}
+
+pub fn normalise(v: [f64; 3]) -> [f64; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    [v[0] / length, v[1] / length, v[2] / length]
+}
+
+// Projects an equirectangular panorama, twice as wide as it is high, onto a cubemap of
+// the given face size. Each face texel averages enough bilinear samples to cover the
+// panorama texels that fall inside it.
+pub fn equirect_to_cubemap(
+    texture: &Texture,
+    face_size: u32,
+    convention: CubeConvention,
+    options: &CompressOptions,
+) -> RuxResult<Texture> {
+    if texture.face_count > 1 || texture.pixel_dim.depth() > 1 {
+        bail!("Only 2D panoramas convert to cubemaps.");
+    }
+    if face_size == 0 {
+        bail!("Cubemap faces need a size of at least one texel.");
+    }
+    let (panoramas, dim) = decode_base(texture)?;
+    let (width, height) = (dim.width(), dim.height());
+    // panorama texels per face texel, along the equator
+    let ratio = f64::from(width) / 4.0 / f64::from(face_size);
+    let samples = ratio.ceil().clamp(1.0, 8.0) as u32;
+
+    let mut texels = vec![];
+    for panorama in panoramas.chunks((width * height) as usize) {
+        for face in 0..6 {
+            for y in 0..face_size {
+                for x in 0..face_size {
+                    texels.push(supersample(samples, |dx, dy| {
+                        let direction =
+                            texel_direction(face, f64::from(x) + dx, f64::from(y) + dy, face_size);
+                        let (u, v) = equirect_position(direction, convention);
+                        bilinear(
+                            panorama,
+                            width,
+                            height,
+                            u * f64::from(width),
+                            v * f64::from(height),
+                        )
+                    }));
+                }
+            }
+        }
+    }
+    let dim = Dimensions::new(face_size, face_size, 0);
+    encode_base(texture, texels, dim, 6, options)
+}
+
+// Unwraps a cubemap into an equirectangular panorama of the given width, and half as
+// high.
+pub fn cubemap_to_equirect(
+    texture: &Texture,
+    width: u32,
+    convention: CubeConvention,
+    options: &CompressOptions,
+) -> RuxResult<Texture> {
+    if texture.face_count != 6 {
+        bail!("Only cubemaps unwrap into panoramas.");
+    }
+    if width < 2 {
+        bail!("Panoramas need a width of at least two texels.");
+    }
+    let height = width / 2;
+    let (cubes, dim) = decode_base(texture)?;
+    let size = dim.width();
+    let ratio = f64::from(size) * 4.0 / f64::from(width);
+    let samples = ratio.ceil().clamp(1.0, 8.0) as u32;
+
+    let mut texels = vec![];
+    for cube in cubes.chunks((size * size * 6) as usize) {
+        for y in 0..height {
+            for x in 0..width {
+                texels.push(supersample(samples, |dx, dy| {
+                    let u = (f64::from(x) + dx) / f64::from(width);
+                    let v = (f64::from(y) + dy) / f64::from(height);
+                    sample_cube(cube, size, equirect_direction(u, v, convention))
+                }));
+            }
+        }
+    }
+    let dim = Dimensions::new(width, height, 0);
+    encode_base(texture, texels, dim, 0, options)
+}
+
+// Cuts a cross or strip out into the faces of a cubemap, detecting the layout from the
+// image's proportions unless told which it is. Only the base level carries over.
+pub fn cubemap_from_layout(
+    texture: &Texture,
+    layout: Option<CubeLayout>,
+    options: &CompressOptions,
+) -> RuxResult<Texture> {
+    if texture.face_count > 1 || texture.pixel_dim.depth() > 1 {
+        bail!("Only 2D images hold cubemap crosses and strips.");
+    }
+    let dim = texture.mip_dim(0);
+    let layout = match layout.or_else(|| CubeLayout::detect(dim)) {
+        Some(layout) => layout,
+        None => bail!(format!(
+            "A {}x{} image isn't shaped like any cubemap cross or strip.",
+            dim.width(),
+            dim.height()
+        )),
+    };
+    let (columns, rows) = layout.grid();
+    let size = dim.width() / columns;
+    if size == 0 || dim.width() != size * columns || dim.height() != size * rows {
+        bail!(format!(
+            "A {}x{} image doesn't hold a {:?} of square faces.",
+            dim.width(),
+            dim.height(),
+            layout
+        ));
+    }
+
+    let images = stored_base(texture)?;
+    let width = dim.width();
+    let mut texels = Vec::with_capacity(images.len() / (columns * rows) as usize * 6);
+    for image in images.chunks((width * dim.height()) as usize) {
+        for &(column, row, flipped) in layout.cells().iter() {
+            for y in 0..size {
+                for x in 0..size {
+                    let (x, y) = if flipped {
+                        (size - 1 - x, size - 1 - y)
+                    } else {
+                        (x, y)
+                    };
+                    texels.push(image[((row * size + y) * width + column * size + x) as usize]);
+                }
+            }
+        }
+    }
+    encode_stored(texture, texels, Dimensions::new(size, size, 0), 6, options)
+}
+
+// Lays the faces of a cubemap's base level out as a cross or strip, for viewing; cells
+// that no face covers are transparent black.
+pub fn cubemap_to_layout(
+    texture: &Texture,
+    layout: CubeLayout,
+    options: &CompressOptions,
+) -> RuxResult<Texture> {
+    if texture.face_count != 6 {
+        bail!("Only cubemaps lay out as crosses and strips.");
+    }
+    let size = texture.mip_dim(0).width();
+    let (columns, rows) = layout.grid();
+    let (width, height) = (size * columns, size * rows);
+
+    let cubes = stored_base(texture)?;
+    let mut texels = vec![];
+    for cube in cubes.chunks((size * size * 6) as usize) {
+        let mut image = vec![[0.0; 4]; (width * height) as usize];
+        for (face, &(column, row, flipped)) in layout.cells().iter().enumerate() {
+            for y in 0..size {
+                for x in 0..size {
+                    let (fx, fy) = if flipped {
+                        (size - 1 - x, size - 1 - y)
+                    } else {
+                        (x, y)
+                    };
+                    image[((row * size + y) * width + column * size + x) as usize] =
+                        cube[(face as u32 * size * size + fy * size + fx) as usize];
+                }
+            }
+        }
+        texels.extend(image);
+    }
+    encode_stored(
+        texture,
+        texels,
+        Dimensions::new(width, height, 0),
+        0,
+        options,
+    )
+}
+
+// The filtered value of a cubemap, one face after another, in a direction. Samples near
+// a face's edge clamp to it rather than reaching across to the neighbouring face.
+pub fn sample_cube(cube: &[[f64; 4]], size: u32, direction: [f64; 3]) -> [f64; 4] {
+    let (face, s, t) = direction_face(direction);
+    let face_texels = (size * size) as usize;
+    let image = &cube[face * face_texels..(face + 1) * face_texels];
+    let scale = f64::from(size) / 2.0;
+    bilinear_clamped(image, size, size, (s + 1.0) * scale, (t + 1.0) * scale)
+}
+
+// where a direction lands on a panorama, in [0, 1] across and down
+fn equirect_position(direction: [f64; 3], convention: CubeConvention) -> (f64, f64) {
+    let [x, y, z] = direction;
+    let forward = match convention {
+        CubeConvention::Vulkan => -z,
+        CubeConvention::D3D => z,
+    };
+    let u = 0.5 + x.atan2(forward) / (2.0 * PI);
+    let v = y.clamp(-1.0, 1.0).acos() / PI;
+    (u, v)
+}
+
+fn equirect_direction(u: f64, v: f64, convention: CubeConvention) -> [f64; 3] {
+    let longitude = (u - 0.5) * 2.0 * PI;
+    let latitude = v * PI;
+    let forward = latitude.sin() * longitude.cos();
+    let z = match convention {
+        CubeConvention::Vulkan => -forward,
+        CubeConvention::D3D => forward,
+    };
+    [latitude.sin() * longitude.sin(), latitude.cos(), z]
+}
+
+// averages samples on an even grid within a texel, given offsets into it
+fn supersample<F: Fn(f64, f64) -> [f64; 4]>(samples: u32, sample: F) -> [f64; 4] {
+    let mut sum = [0.0; 4];
+    for j in 0..samples {
+        for i in 0..samples {
+            let dx = (f64::from(i) + 0.5) / f64::from(samples);
+            let dy = (f64::from(j) + 0.5) / f64::from(samples);
+            for (sum, value) in sum.iter_mut().zip(sample(dx, dy).iter()) {
+                *sum += value;
+            }
+        }
+    }
+    let count = f64::from(samples * samples);
+    [
+        sum[0] / count,
+        sum[1] / count,
+        sum[2] / count,
+        sum[3] / count,
+    ]
+}
+
+// bilinear sampling at a position in texels, wrapping across and clamping down
+fn bilinear(image: &[[f64; 4]], width: u32, height: u32, x: f64, y: f64) -> [f64; 4] {
+    let x = (x - 0.5).rem_euclid(f64::from(width));
+    let column = |i: i64| i.rem_euclid(i64::from(width)) as u32;
+    interpolate(image, width, height, x, y - 0.5, column)
+}
+
+fn bilinear_clamped(image: &[[f64; 4]], width: u32, height: u32, x: f64, y: f64) -> [f64; 4] {
+    let column = |i: i64| i.clamp(0, i64::from(width) - 1) as u32;
+    interpolate(image, width, height, x - 0.5, y - 0.5, column)
+}
+
+fn interpolate<F: Fn(i64) -> u32>(
+    image: &[[f64; 4]],
+    width: u32,
+    height: u32,
+    x: f64,
+    y: f64,
+    column: F,
+) -> [f64; 4] {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let row = |j: i64| j.clamp(0, i64::from(height) - 1) as u32;
+    let texel = |i: f64, j: f64| image[(row(j as i64) * width + column(i as i64)) as usize];
+    let (a, b) = (texel(x0, y0), texel(x0 + 1.0, y0));
+    let (c, d) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+    let mut out = [0.0; 4];
+    for (i, value) in out.iter_mut().enumerate() {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        *value = top + (bottom - top) * fy;
+    }
+    out
+}
+
+// the base level's images as the format stores them, for moving texels around unfiltered
+fn stored_base(texture: &Texture) -> RuxResult<Vec<[f64; 4]>> {
+    let base = match texture.mip_blobs.first() {
+        Some(base) => base,
+        None => bail!("Texture has no base level."),
+    };
+    decode_level(
+        &texture.format,
+        base,
+        texture.mip_dim(0),
+        texture.image_count(),
+    )
+}
+
+fn encode_stored(
+    source: &Texture,
+    texels: Vec<[f64; 4]>,
+    dim: Dimensions,
+    face_count: u32,
+    options: &CompressOptions,
+) -> RuxResult<Texture> {
+    let image_count = source.array_size.max(1) * face_count.max(1);
+    let blob = encode_level(&source.format, &texels, dim, image_count, options)?;
+    Ok(Texture {
+        format: source.format,
+        pixel_dim: dim,
+        array_size: source.array_size,
+        face_count,
+        mip_blobs: vec![blob],
+    })
+}
+
+// The base level's images, as linear light if the format is sRGB, and their dimensions.
+pub fn decode_base(texture: &Texture) -> RuxResult<(Vec<[f64; 4]>, Dimensions)> {
+    let format = &texture.format;
+    check_filterable(format)?;
+    let base = match texture.mip_blobs.first() {
+        Some(base) => base,
+        None => bail!("Texture has no base level."),
+    };
+    let dim = texture.mip_dim(0);
+    let mut texels = decode_level(format, base, dim, texture.image_count())?;
+    srgb::transfer(&mut texels, format.is_srgb(), false);
+    Ok((texels, dim))
+}
+
+// A single-level texture in the source's format, with the source's array size, from
+// linear texels.
+pub fn encode_base(
+    source: &Texture,
+    mut texels: Vec<[f64; 4]>,
+    dim: Dimensions,
+    face_count: u32,
+    options: &CompressOptions,
+) -> RuxResult<Texture> {
+    srgb::transfer(&mut texels, false, source.format.is_srgb());
+    encode_stored(source, texels, dim, face_count, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn face_round_trip() {
+        let points = [(0.0, 0.0), (0.5, -0.25), (-0.9, 0.75), (0.99, 0.99)];
+        for face in 0..6 {
+            // the centre of each face lies along one axis, in the order +X -X +Y -Y +Z -Z
+            let mut axis = [0.0; 3];
+            axis[face / 2] = if face % 2 == 0 { 1.0 } else { -1.0 };
+            assert_eq!(face_direction(face, 0.0, 0.0), axis, "face {}", face);
+            for &(s, t) in &points {
+                // the direction's length doesn't matter
+                let direction = face_direction(face, s, t).map(|v| v * 3.0);
+                let (found, found_s, found_t) = direction_face(direction);
+                assert_eq!(found, face, "({}, {})", s, t);
+                assert!((found_s - s).abs() < 1e-12, "face {}: s {}", face, found_s);
+                assert!((found_t - t).abs() < 1e-12, "face {}: t {}", face, found_t);
+            }
+        }
+    }
+}
@@ -0,0 +1,490 @@
+// Image-based lighting from environment cubemaps.
+
+use std::f64::consts::PI;
+
+use crate::compress::CompressOptions;
+use crate::cubemap::{decode_base, encode_base, normalise, sample_cube, texel_direction};
+use crate::mipmap::full_level_count;
+use crate::pixel::gl::GlFormat;
+use crate::pixel::PixelFormat;
+use crate::resample::{encode_level, resample, EdgeMode, Filter};
+use crate::{Dimensions, RuxResult, Texture};
+
+clap::arg_enum! {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SpecularFormat {
+    Rgba16f,
+    Bc6h,
+}
+}
+
+impl SpecularFormat {
+    fn pixel_format(self) -> Option<&'static PixelFormat> {
+        match self {
+            SpecularFormat::Rgba16f => PixelFormat::for_name("R16G16B16A16_SFLOAT"),
+            SpecularFormat::Bc6h => {
+                let gl_format = GlFormat::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT_ARB;
+                PixelFormat::for_gl_format(gl_format).into_iter().next()
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SpecularOptions {
+    // GGX lobe samples for each texel
+    pub samples: u32,
+    // how many levels, with roughness going from 0 at the base to 1 at the last; all of
+    // them down to 1x1 if None
+    pub levels: Option<usize>,
+    pub format: SpecularFormat,
+    // how BC6H is encoded
+    pub compress: CompressOptions,
+}
+
+impl Default for SpecularOptions {
+    fn default() -> SpecularOptions {
+        SpecularOptions {
+            samples: 512,
+            levels: None,
+            format: SpecularFormat::Rgba16f,
+            compress: CompressOptions::default(),
+        }
+    }
+}
+
+// Builds the specular half of split-sum image-based lighting: a cubemap whose mip levels
+// hold the environment convolved with GGX lobes of increasing roughness, taking the view
+// and reflection directions to equal the normal. The lobe is importance sampled, with
+// each sample read from a box-filtered mip of the source that matches the solid angle
+// it stands for, which keeps low sample counts from turning into noise.
+pub fn prefilter_specular(texture: &Texture, options: &SpecularOptions) -> RuxResult<Texture> {
+    if texture.face_count != 6 {
+        bail!("Only cubemaps prefilter into environment maps.");
+    }
+    if options.samples == 0 {
+        bail!("Prefiltering needs at least one sample per texel.");
+    }
+    let target = match options.format.pixel_format() {
+        Some(target) => target,
+        None => bail!(format!("No pixel format for {}.", options.format)),
+    };
+    let (cubes, dim) = decode_base(texture)?;
+    let size = dim.width();
+    let full = full_level_count(dim);
+    let levels = match options.levels {
+        Some(0) => bail!("An environment map needs at least one level."),
+        Some(levels) => levels.min(full),
+        None => full,
+    };
+
+    let mut level_texels = vec![vec![]; levels];
+    for cube in cubes.chunks((size * size * 6) as usize) {
+        let chain = source_chain(cube, size);
+        for (level, texels) in level_texels.iter_mut().enumerate() {
+            // a perfect mirror is the environment itself
+            if level == 0 {
+                texels.extend(cube.iter().map(|t| [t[0], t[1], t[2], 1.0]));
+                continue;
+            }
+            let roughness = level as f64 / (levels - 1) as f64;
+            let samples = ggx_samples(roughness, options.samples, size);
+            let level_size = (size >> level).max(1);
+            for face in 0..6 {
+                for y in 0..level_size {
+                    for x in 0..level_size {
+                        let (x, y) = (f64::from(x) + 0.5, f64::from(y) + 0.5);
+                        let normal = texel_direction(face, x, y, level_size);
+                        texels.push(integrate(&chain, normal, &samples));
+                    }
+                }
+            }
+        }
+    }
+
+    let image_count = texture.array_size.max(1) * 6;
+    let mut mip_blobs = vec![];
+    for (level, texels) in level_texels.iter().enumerate() {
+        let dim = texture.mip_dim(level);
+        match encode_level(target, texels, dim, image_count, &options.compress) {
+            Ok(blob) => mip_blobs.push(blob),
+            Err(e) => bail!(format!("Mip level {}: {}", level, e)),
+        }
+    }
+    Ok(Texture {
+        format: *target,
+        mip_blobs,
+        ..*texture
+    })
+}
+
+// Irradiance as real spherical harmonics up to L2, one RGB coefficient per basis function
+// in the order (0, 0), (1, -1), (1, 0), (1, 1), (2, -2), (2, -1), (2, 0), (2, 1), (2, 2),
+// with the cosine lobe already convolved in, in the cubemap's own frame.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SphericalHarmonics {
+    pub coefficients: [[f64; 3]; 9],
+}
+
+// how much the cosine lobe keeps of each band
+const BAND_SCALES: [f64; 3] = [PI, 2.0 * PI / 3.0, PI / 4.0];
+
+impl SphericalHarmonics {
+    // the irradiance arriving at a surface facing the direction
+    pub fn evaluate(&self, direction: [f64; 3]) -> [f64; 3] {
+        let basis = sh_basis(direction);
+        let mut irradiance = [0.0; 3];
+        for (coefficient, weight) in self.coefficients.iter().zip(basis.iter()) {
+            for (value, c) in irradiance.iter_mut().zip(coefficient.iter()) {
+                *value += c * weight;
+            }
+        }
+        irradiance
+    }
+
+    // JSON has no NaN or infinity, which only a source holding them would give
+    pub fn to_json(&self) -> RuxResult<String> {
+        if self.coefficients.iter().flatten().any(|c| !c.is_finite()) {
+            bail!("Irradiance isn't finite; the source holds NaN or infinite values.");
+        }
+        let rows: Vec<String> = self
+            .coefficients
+            .iter()
+            .map(|c| format!("    [{}, {}, {}]", c[0], c[1], c[2]))
+            .collect();
+        Ok(format!(
+            "{{\n  \"coefficients\": [\n{}\n  ]\n}}",
+            rows.join(",\n")
+        ))
+    }
+}
+
+// the nine real L2 basis functions at a unit direction
+fn sh_basis(direction: [f64; 3]) -> [f64; 9] {
+    let [x, y, z] = direction;
+    [
+        0.282_095,
+        0.488_603 * y,
+        0.488_603 * z,
+        0.488_603 * x,
+        1.092_548 * x * y,
+        1.092_548 * y * z,
+        0.315_392 * (3.0 * z * z - 1.0),
+        1.092_548 * x * z,
+        0.546_274 * (x * x - y * y),
+    ]
+}
+
+// Projects each cube of a cubemap, one per array layer, onto L2 spherical harmonics, with
+// every texel weighted by the solid angle it covers, and convolves the result with the
+// cosine lobe into irradiance.
+pub fn irradiance_sh(texture: &Texture) -> RuxResult<Vec<SphericalHarmonics>> {
+    if texture.face_count != 6 {
+        bail!("Only cubemaps have irradiance.");
+    }
+    let (cubes, dim) = decode_base(texture)?;
+    let size = dim.width();
+    let mut result = vec![];
+    for cube in cubes.chunks((size * size * 6) as usize) {
+        let mut coefficients = [[0.0; 3]; 9];
+        for (i, texel) in cube.iter().enumerate() {
+            let face = i / (size * size) as usize;
+            let (x, y) = ((i as u32 % size), (i as u32 / size % size));
+            let (x, y) = (f64::from(x), f64::from(y));
+            let direction = texel_direction(face, x + 0.5, y + 0.5, size);
+            let weight = texel_solid_angle(x, y, size);
+            for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction).iter()) {
+                for (c, value) in coefficient.iter_mut().zip(texel.iter()) {
+                    *c += value * basis * weight;
+                }
+            }
+        }
+        for (i, coefficient) in coefficients.iter_mut().enumerate() {
+            let band = match i {
+                0 => 0,
+                1..=3 => 1,
+                _ => 2,
+            };
+            for c in coefficient.iter_mut() {
+                *c *= BAND_SCALES[band];
+            }
+        }
+        result.push(SphericalHarmonics { coefficients });
+    }
+    Ok(result)
+}
+
+// The solid angle a face texel covers, exactly, from the area its corners enclose when
+// projected onto the unit sphere.
+fn texel_solid_angle(x: f64, y: f64, size: u32) -> f64 {
+    let scale = 2.0 / f64::from(size);
+    let (x0, y0) = (x * scale - 1.0, y * scale - 1.0);
+    let (x1, y1) = (x0 + scale, y0 + scale);
+    let area = |x: f64, y: f64| (x * y).atan2((x * x + y * y + 1.0).sqrt());
+    area(x0, y0) - area(x0, y1) - area(x1, y0) + area(x1, y1)
+}
+
+// Renders a small RGBA16F cubemap of the irradiance spherical harmonics, divided by pi, so
+// that diffuse lighting is the albedo times the value in the normal's direction.
+pub fn irradiance_cubemap(texture: &Texture, size: u32) -> RuxResult<Texture> {
+    if size == 0 {
+        bail!("Cubemap faces need a size of at least one texel.");
+    }
+    let target = match PixelFormat::for_name("R16G16B16A16_SFLOAT") {
+        Some(target) => target,
+        None => bail!("No R16G16B16A16_SFLOAT format to hold irradiance."),
+    };
+    let mut texels = vec![];
+    for sh in irradiance_sh(texture)? {
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    let (x, y) = (f64::from(x) + 0.5, f64::from(y) + 0.5);
+                    let e = sh.evaluate(texel_direction(face, x, y, size));
+                    texels.push([e[0] / PI, e[1] / PI, e[2] / PI, 1.0]);
+                }
+            }
+        }
+    }
+    let source = Texture {
+        format: *target,
+        mip_blobs: vec![],
+        ..*texture
+    };
+    let dim = Dimensions::new(size, size, 0);
+    encode_base(&source, texels, dim, 6, &CompressOptions::default())
+}
+
+// one importance sample of a GGX lobe around +Z: the light direction, its weight and the
+// source mip level to read it from
+struct Sample {
+    direction: [f64; 3],
+    weight: f64,
+    lod: f64,
+}
+
+fn ggx_samples(roughness: f64, count: u32, size: u32) -> Vec<Sample> {
+    let alpha = roughness * roughness;
+    let alpha2 = alpha * alpha;
+    // the solid angle of a source texel, near enough
+    let texel_angle = 4.0 * PI / (6.0 * f64::from(size) * f64::from(size));
+    let mut samples = vec![];
+    for i in 0..count {
+        let (u, v) = hammersley(i, count);
+        let phi = 2.0 * PI * u;
+        let cos_theta = ((1.0 - v) / (1.0 + (alpha2 - 1.0) * v)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let half = [sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta];
+        // the half vector's reflection of the view direction, which is +Z
+        let light = [
+            2.0 * cos_theta * half[0],
+            2.0 * cos_theta * half[1],
+            2.0 * cos_theta * cos_theta - 1.0,
+        ];
+        if light[2] <= 0.0 {
+            continue;
+        }
+        // with the view along the normal, the pdf of the light direction is D / 4
+        let d = alpha2 / (PI * (cos_theta * cos_theta * (alpha2 - 1.0) + 1.0).powi(2));
+        let sample_angle = 4.0 / (f64::from(count) * d);
+        samples.push(Sample {
+            direction: light,
+            weight: light[2],
+            lod: (0.5 * (sample_angle / texel_angle).log2() + 1.0).max(0.0),
+        });
+    }
+    samples
+}
+
+// the i-th of n points of the Hammersley set in the unit square
+fn hammersley(i: u32, n: u32) -> (f64, f64) {
+    (
+        f64::from(i) / f64::from(n),
+        f64::from(i.reverse_bits()) / 4_294_967_296.0,
+    )
+}
+
+fn integrate(chain: &[(u32, Vec<[f64; 4]>)], normal: [f64; 3], samples: &[Sample]) -> [f64; 4] {
+    let (tangent, bitangent) = basis(normal);
+    let (mut sum, mut total) = ([0.0; 3], 0.0);
+    for sample in samples {
+        let [x, y, z] = sample.direction;
+        let direction = [
+            tangent[0] * x + bitangent[0] * y + normal[0] * z,
+            tangent[1] * x + bitangent[1] * y + normal[1] * z,
+            tangent[2] * x + bitangent[2] * y + normal[2] * z,
+        ];
+        let value = sample_chain(chain, direction, sample.lod);
+        for (sum, value) in sum.iter_mut().zip(value.iter()) {
+            *sum += value * sample.weight;
+        }
+        total += sample.weight;
+    }
+    if total == 0.0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+    [sum[0] / total, sum[1] / total, sum[2] / total, 1.0]
+}
+
+// two unit vectors at right angles to the normal and each other
+fn basis(normal: [f64; 3]) -> ([f64; 3], [f64; 3]) {
+    let up = if normal[2].abs() < 0.999 {
+        [0.0, 0.0, 1.0]
+    } else {
+        [1.0, 0.0, 0.0]
+    };
+    let tangent = normalise(cross(up, normal));
+    (tangent, cross(normal, tangent))
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+// The cube and box-filtered copies of it, halving down to 1x1, with their face sizes.
+fn source_chain(cube: &[[f64; 4]], size: u32) -> Vec<(u32, Vec<[f64; 4]>)> {
+    let mut chain = vec![(size, cube.to_vec())];
+    while chain.last().unwrap().0 > 1 {
+        let (size, texels) = chain.last().unwrap();
+        let (from, to) = (*size, size / 2);
+        let faces = texels
+            .chunks((from * from) as usize)
+            .flat_map(|face| {
+                resample(
+                    face,
+                    Dimensions::new(from, from, 1),
+                    Dimensions::new(to, to, 1),
+                    Filter::Box,
+                    [EdgeMode::Clamp; 3],
+                )
+            })
+            .collect();
+        chain.push((to, faces));
+    }
+    chain
+}
+
+// trilinear sampling of the chain, between the two levels around lod
+fn sample_chain(chain: &[(u32, Vec<[f64; 4]>)], direction: [f64; 3], lod: f64) -> [f64; 4] {
+    let last = (chain.len() - 1) as f64;
+    let lod = lod.min(last);
+    let (low, high) = (lod.floor() as usize, lod.ceil() as usize);
+    let a = sample_cube(&chain[low].1, chain[low].0, direction);
+    if low == high {
+        return a;
+    }
+    let b = sample_cube(&chain[high].1, chain[high].0, direction);
+    let f = lod - low as f64;
+    [
+        a[0] + (b[0] - a[0]) * f,
+        a[1] + (b[1] - a[1]) * f,
+        a[2] + (b[2] - a[2]) * f,
+        a[3] + (b[3] - a[3]) * f,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::decode::decode_f64;
+    use crate::pixel::encode::encode_f64;
+    use crate::pixel::format_for_test;
+
+    const RADIANCE: [f64; 3] = [0.5, 1.0, 2.0];
+
+    // the same radiance from every direction, which every lighting integral should return
+    fn constant_cube(size: u32) -> Texture {
+        let format = format_for_test("R32G32B32A32_SFLOAT");
+        let [r, g, b] = RADIANCE;
+        let texels = vec![[r, g, b, 1.0]; (size * size * 6) as usize];
+        Texture {
+            format: *format,
+            pixel_dim: Dimensions::new(size, size, 0),
+            array_size: 0,
+            face_count: 6,
+            mip_blobs: vec![encode_f64(format, &texels).unwrap()],
+        }
+    }
+
+    fn assert_radiance(texture: &Texture, level: usize) {
+        let texels = decode_f64(&texture.format, &texture.mip_blobs[level]).unwrap();
+        for texel in texels {
+            for (value, expected) in texel.iter().zip(RADIANCE.iter()) {
+                // within the precision of a half
+                assert!(
+                    (value - expected).abs() < 2e-3,
+                    "level {}: {:?}",
+                    level,
+                    texel
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn constant_harmonics() {
+        // L projects onto the constant basis function 1 / (2 sqrt(pi)) as 2 sqrt(pi) L,
+        // which the cosine lobe scales by pi, and the other bands cancel out
+        let harmonics = irradiance_sh(&constant_cube(8)).unwrap();
+        assert_eq!(harmonics.len(), 1);
+        let coefficients = harmonics[0].coefficients;
+        for (c, radiance) in RADIANCE.iter().enumerate() {
+            let expected = 2.0 * PI.sqrt() * PI * radiance;
+            assert!(
+                (coefficients[0][c] - expected).abs() < 1e-4,
+                "{:?}",
+                coefficients
+            );
+            for coefficient in &coefficients[1..] {
+                assert!(coefficient[c].abs() < 1e-9, "{:?}", coefficients);
+            }
+        }
+        // which is pi L arriving at a surface facing any way
+        for &direction in &[
+            [1.0, 0.0, 0.0],
+            [0.0, -1.0, 0.0],
+            normalise([1.0, 2.0, 3.0]),
+        ] {
+            let irradiance = harmonics[0].evaluate(direction);
+            for (value, radiance) in irradiance.iter().zip(RADIANCE.iter()) {
+                assert!((value - PI * radiance).abs() < 1e-4, "{:?}", irradiance);
+            }
+        }
+    }
+
+    #[test]
+    fn constant_irradiance() {
+        // divided by pi, the irradiance map holds L again
+        let irradiance = irradiance_cubemap(&constant_cube(8), 4).unwrap();
+        assert_eq!(irradiance.pixel_dim, Dimensions::new(4, 4, 0));
+        assert_radiance(&irradiance, 0);
+    }
+
+    #[test]
+    fn constant_prefilter() {
+        // GGX lobes of any roughness average L to L
+        let options = SpecularOptions {
+            samples: 32,
+            ..Default::default()
+        };
+        let specular = prefilter_specular(&constant_cube(8), &options).unwrap();
+        assert_eq!(specular.mip_blobs.len(), 4);
+        for level in 0..4 {
+            assert_radiance(&specular, level);
+        }
+    }
+
+    #[test]
+    fn json_needs_finite_values() {
+        let mut harmonics = irradiance_sh(&constant_cube(2)).unwrap()[0];
+        assert!(harmonics.to_json().unwrap().contains("\"coefficients\""));
+        harmonics.coefficients[4][1] = f64::NAN;
+        assert!(harmonics.to_json().is_err());
+        harmonics.coefficients[4][1] = f64::INFINITY;
+        assert!(harmonics.to_json().is_err());
+    }
+}
@@ -8,8 +8,8 @@ use num_traits::*;
 use crate::file::{FileCodec, FileFormat};
 use crate::{Dimensions, FileBlob, RuxResult, Texture};
 
-use crate::pixel::gl::{gl_upgrade_old_formats, GlBaseFormat, GlDataType, GlFormat};
-use crate::pixel::PixelFormat;
+use crate::pixel::gl::{gl_upgrade_old_formats, to_gl, GlBaseFormat, GlDataType, GlFormat};
+use crate::pixel::{CompLayout, PixelFormat};
 
 // https://www.khronos.org/opengles/sdk/tools/KTX/file_format_spec/#1
 //
@@ -81,20 +81,19 @@ impl FileCodec for Ktx1Codec {
         let mut endian_buf = [0x00; 4];
         cursor.read_exact(&mut endian_buf)?;
 
-        let mut reader = ByteOrdered::runtime(
-            cursor,
-            match endian_buf {
-                KTX1_BIG_ENDIAN => Endianness::Big,
-                KTX1_LITTLE_ENDIAN => Endianness::Little,
-                _ => bail!("KTX1: Field 'endianness' is invalid"),
-            },
-        );
+        let endianness = match endian_buf {
+            KTX1_BIG_ENDIAN => Endianness::Big,
+            KTX1_LITTLE_ENDIAN => Endianness::Little,
+            _ => bail!("KTX1: Field 'endianness' is invalid"),
+        };
+        let mut reader = ByteOrdered::runtime(cursor, endianness);
 
         let gl_type = reader.read_u32()?;
-        let _gl_type_size = reader.read_u32()?; // TODO: validate
+        let gl_type_size = reader.read_u32()?;
         let gl_format = reader.read_u32()?;
 
         let gl_internal_format_num = reader.read_u32()?;
+        let _gl_base_internal_format = reader.read_u32()?;
         let gl_internal_format =
             GlFormat::from_u32(gl_internal_format_num).map(gl_upgrade_old_formats);
 
@@ -169,36 +168,216 @@ impl FileCodec for Ktx1Codec {
             let kv_size = reader.read_u32()?;
             let mut kv_buf = vec![0x00; kv_size as usize];
             reader.read_exact(&mut kv_buf)?;
-            bytes_of_key_value_data -= 4 + kv_size;
-            while (bytes_of_key_value_data % 4) != 0 {
+            bytes_of_key_value_data -= 4 + kv_size as isize;
+            for _ in 0..padding(kv_size as usize) {
                 reader.read_u8()?;
             }
+            bytes_of_key_value_data -= padding(kv_size as usize) as isize;
         }
 
-        let mut mip_blobs = vec![];
-        // TODO: properly handle imageSize & cubePadding in the cubemap case
-        for _mip_level in 0..number_of_mipmap_levels {
-            let image_size = reader.read_u32()?;
-            // TODO: validate image_size against dimensions & pixel format
-            let mut image_buf = vec![0x00; image_size as usize];
-            reader.read_exact(&mut image_buf)?;
-            mip_blobs.push(image_buf);
-        }
-
-        Ok(Texture {
+        let texture = Texture {
             format: *format,
             pixel_dim: Dimensions(pixel_width, pixel_height, pixel_depth),
             array_size: number_of_array_elements,
             face_count: number_of_faces,
+            mip_blobs: vec![],
+        };
+        // a cubemap that isn't an array gives the size of one face, with each face padded
+        let chunks = if is_bare_cubemap(&texture) { 6 } else { 1 };
+        let swap = match endianness {
+            Endianness::Big => gl_type_size as usize,
+            Endianness::Little => 1,
+        };
+
+        let mut mip_blobs = vec![];
+        for level in 0..number_of_mipmap_levels as usize {
+            let image_size = reader.read_u32()? as usize;
+            let mut level_buf = vec![];
+            for _ in 0..chunks {
+                let mut chunk = vec![0x00; image_size];
+                reader.read_exact(&mut chunk)?;
+                level_buf.extend_from_slice(&chunk);
+                for _ in 0..padding(image_size) {
+                    reader.read_u8()?;
+                }
+            }
+            let mut blob = match unpad_rows(&texture, level, &level_buf) {
+                Ok(blob) => blob,
+                Err(e) => bail!(format!("KTX1: Mip level {}: {}", level, e)),
+            };
+            if swap == 2 || swap == 4 {
+                for value in blob.chunks_exact_mut(swap) {
+                    value.reverse();
+                }
+            }
+            mip_blobs.push(blob);
+        }
+
+        Ok(Texture {
             mip_blobs,
+            ..texture
         })
     }
-    fn generate(
-        &self,
-        _texture: &Texture,
-        _format: FileFormat,
-    ) -> RuxResult<(FileFormat, FileBlob)> {
-        bail!("KTX1 generation not yet implemented");
+    fn generate(&self, texture: &Texture, format: FileFormat) -> RuxResult<(FileFormat, FileBlob)> {
+        let pixel_format = &texture.format;
+        let gl_internal_format = match pixel_format.gl_format {
+            Some(gl_internal_format) => gl_internal_format,
+            None => bail!(format!(
+                "KTX1: {} has no OpenGL internal format",
+                pixel_format
+            )),
+        };
+        // compressed data has no type or format of its own, only a base internal format
+        let (gl_type, gl_type_size, gl_format, gl_base_format) = if pixel_format.is_compressed() {
+            (0, 1, 0, compressed_base_format(pixel_format.comp_layout))
+        } else {
+            match to_gl(&pixel_format.comp_layout, &pixel_format.comp_content) {
+                Some((gl_format, gl_type)) => (
+                    gl_type.to_u32().unwrap(),
+                    type_size(gl_type),
+                    gl_format.to_u32().unwrap(),
+                    base_internal_format(gl_format),
+                ),
+                None => bail!(format!("KTX1: {} has no OpenGL type", pixel_format)),
+            }
+        };
+
+        let dim = texture.pixel_dim;
+        let mut out = KTX1_MAGIC.to_vec();
+        out.extend_from_slice(&KTX1_LITTLE_ENDIAN);
+        for field in &[
+            gl_type,
+            gl_type_size,
+            gl_format,
+            gl_internal_format.to_u32().unwrap(),
+            gl_base_format.to_u32().unwrap(),
+            dim.0,
+            dim.1,
+            dim.2,
+            texture.array_size,
+            texture.face_count,
+            texture.mip_blobs.len() as u32,
+            // no key-value data
+            0,
+        ] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+
+        for (level, blob) in texture.mip_blobs.iter().enumerate() {
+            let level_buf = match pad_rows(texture, level, blob) {
+                Ok(level_buf) => level_buf,
+                Err(e) => bail!(format!("KTX1: Mip level {}: {}", level, e)),
+            };
+            // padded rows and whole blocks make every face a multiple of four bytes, so
+            // there is never any cube padding
+            let image_size = if is_bare_cubemap(texture) {
+                level_buf.len() / 6
+            } else {
+                level_buf.len()
+            };
+            out.extend_from_slice(&(image_size as u32).to_le_bytes());
+            out.extend_from_slice(&level_buf);
+            out.resize(out.len() + padding(level_buf.len()), 0x00);
+        }
+        Ok((format, out))
+    }
+}
+
+fn is_bare_cubemap(texture: &Texture) -> bool {
+    texture.array_size == 0 && texture.face_count == 6
+}
+
+fn padding(size: usize) -> usize {
+    3 - ((size + 3) % 4)
+}
+
+// Uncompressed rows are stored as if GL_UNPACK_ALIGNMENT were 4; textures keep them tight.
+fn row_sizes(texture: &Texture, level: usize) -> Option<(usize, usize, usize)> {
+    let texel_bytes = texture.format.texel_bytes()?;
+    let dim = texture.mip_dim(level);
+    let row_bytes = dim.width() as usize * texel_bytes;
+    let rows = (texture.image_count() * dim.depth() * dim.height()) as usize;
+    Some((row_bytes, row_bytes + padding(row_bytes), rows))
+}
+
+fn pad_rows(texture: &Texture, level: usize, blob: &[u8]) -> RuxResult<Vec<u8>> {
+    let (row_bytes, padded_bytes, rows) = match row_sizes(texture, level) {
+        Some(sizes) => sizes,
+        None => return Ok(blob.to_vec()),
+    };
+    if blob.len() != row_bytes * rows {
+        bail!(format!(
+            "{} bytes aren't {} rows of {} bytes",
+            blob.len(),
+            rows,
+            row_bytes
+        ));
+    }
+    let mut padded = Vec::with_capacity(padded_bytes * rows);
+    for row in blob.chunks_exact(row_bytes) {
+        padded.extend_from_slice(row);
+        padded.resize(padded.len() + padded_bytes - row_bytes, 0x00);
+    }
+    Ok(padded)
+}
+
+fn unpad_rows(texture: &Texture, level: usize, level_buf: &[u8]) -> RuxResult<Vec<u8>> {
+    let (row_bytes, padded_bytes, rows) = match row_sizes(texture, level) {
+        Some(sizes) => sizes,
+        None => return Ok(level_buf.to_vec()),
+    };
+    if level_buf.len() != padded_bytes * rows {
+        bail!(format!(
+            "{} bytes aren't {} rows of {} bytes",
+            level_buf.len(),
+            rows,
+            padded_bytes
+        ));
+    }
+    Ok(level_buf
+        .chunks_exact(padded_bytes)
+        .flat_map(|row| row[..row_bytes].iter().copied())
+        .collect())
+}
+
+// the size of the values that a reader of the other endianness must swap
+fn type_size(gl_type: GlDataType) -> u32 {
+    use GlDataType::*;
+    match gl_type {
+        BYTE | UNSIGNED_BYTE | UNSIGNED_BYTE_3_3_2 | UNSIGNED_BYTE_2_3_3_REV => 1,
+        SHORT
+        | UNSIGNED_SHORT
+        | HALF_FLOAT
+        | UNSIGNED_SHORT_5_6_5
+        | UNSIGNED_SHORT_5_6_5_REV
+        | UNSIGNED_SHORT_4_4_4_4
+        | UNSIGNED_SHORT_4_4_4_4_REV
+        | UNSIGNED_SHORT_5_5_5_1
+        | UNSIGNED_SHORT_1_5_5_5_REV => 2,
+        _ => 4,
+    }
+}
+
+// base internal formats have no integer or BGR variants
+fn base_internal_format(gl_format: GlBaseFormat) -> GlBaseFormat {
+    use GlBaseFormat::*;
+    match gl_format {
+        RED_INTEGER => RED,
+        GREEN_INTEGER => GREEN,
+        BLUE_INTEGER => BLUE,
+        RG_INTEGER => RG,
+        RGB_INTEGER | BGR | BGR_INTEGER => RGB,
+        RGBA_INTEGER | BGRA | BGRA_INTEGER => RGBA,
+        _ => gl_format,
+    }
+}
+
+fn compressed_base_format(comp_layout: CompLayout) -> GlBaseFormat {
+    match comp_layout {
+        CompLayout::R8 | CompLayout::R11 => GlBaseFormat::RED,
+        CompLayout::R8G8 | CompLayout::R11G11 => GlBaseFormat::RG,
+        CompLayout::R8G8B8 | CompLayout::R16G16B16 => GlBaseFormat::RGB,
+        _ => GlBaseFormat::RGBA,
     }
 }
 
@@ -477,3 +656,93 @@ fn _foo() {
         ),
     ];
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(texture: &Texture) -> (FileBlob, Texture) {
+        let (_, blob) = Ktx1Codec.generate(texture, FileFormat::KTX1).unwrap();
+        let parsed = Ktx1Codec.parse(&blob).unwrap();
+        (blob, parsed)
+    }
+
+    #[test]
+    fn rows_are_padded() {
+        // 3x3 RGB8 rows take 9 bytes, padded to 12; the 1x1 level's 3 bytes to 4
+        let texture = Texture {
+            format: *PixelFormat::for_name("R8G8B8_UNORM").unwrap(),
+            pixel_dim: Dimensions::new(3, 3, 0),
+            array_size: 0,
+            face_count: 1,
+            mip_blobs: vec![(0..27).collect(), vec![1, 2, 3]],
+        };
+        let (blob, parsed) = round_trip(&texture);
+        assert_eq!(parsed, texture);
+        assert_eq!(blob.len(), HEADER_SIZE + 4 + 36 + 4 + 4);
+        let field = |i: usize| u32::from_le_bytes([blob[i], blob[i + 1], blob[i + 2], blob[i + 3]]);
+        assert_eq!(field(16), 0x1401);
+        assert_eq!(field(28), 0x8051);
+        assert_eq!(field(32), 0x1907);
+        assert_eq!(field(HEADER_SIZE), 36);
+    }
+
+    #[test]
+    fn compressed_cubemap() {
+        // a non-array cubemap's image size is that of one face
+        let texture = Texture {
+            format: *PixelFormat::for_name("BC1_RGBA_UNORM").unwrap(),
+            pixel_dim: Dimensions::new(8, 8, 0),
+            array_size: 0,
+            face_count: 6,
+            mip_blobs: vec![(0..192).collect(), (0..48).collect()],
+        };
+        let (blob, parsed) = round_trip(&texture);
+        assert_eq!(parsed, texture);
+        assert_eq!(blob[HEADER_SIZE], 32);
+        assert_eq!(&blob[20..28], &[1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn skips_key_value_data() {
+        let texture = Texture {
+            format: *PixelFormat::for_name("R8_UNORM").unwrap(),
+            pixel_dim: Dimensions::new(2, 2, 0),
+            array_size: 2,
+            face_count: 1,
+            mip_blobs: vec![vec![1, 2, 3, 4, 5, 6, 7, 8]],
+        };
+        let (mut blob, _) = round_trip(&texture);
+        // a five-byte pair, padded to eight
+        let pair = [5, 0, 0, 0, b'k', 0, b'v', b'a', 0, 0, 0, 0];
+        blob[60..64].copy_from_slice(&(pair.len() as u32).to_le_bytes());
+        blob.splice(HEADER_SIZE..HEADER_SIZE, pair.iter().copied());
+        assert_eq!(Ktx1Codec.parse(&blob).unwrap(), texture);
+    }
+
+    #[test]
+    fn big_endian() {
+        let mut blob = KTX1_MAGIC.to_vec();
+        blob.extend_from_slice(&KTX1_BIG_ENDIAN);
+        // one 16-bit texel, then the image size, which counts the row's padding
+        for field in &[0x1403, 2, 0x1903, 0x822A, 0x1903, 1, 1, 0, 0, 1, 1, 0, 4] {
+            blob.extend_from_slice(&(*field as u32).to_be_bytes());
+        }
+        blob.extend_from_slice(&[0x12, 0x34, 0x00, 0x00]);
+        let texture = Ktx1Codec.parse(&blob).unwrap();
+        assert_eq!(texture.format, *PixelFormat::for_name("R16_UNORM").unwrap());
+        assert_eq!(texture.mip_blobs, vec![vec![0x34, 0x12]]);
+    }
+
+    #[test]
+    fn rejects_formats_without_gl() {
+        let texture = Texture {
+            format: *PixelFormat::for_name("R64_SFLOAT").unwrap(),
+            pixel_dim: Dimensions::new(1, 1, 0),
+            array_size: 0,
+            face_count: 1,
+            mip_blobs: vec![vec![0; 8]],
+        };
+        assert!(Ktx1Codec.generate(&texture, FileFormat::KTX1).is_err());
+    }
+}
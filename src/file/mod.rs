@@ -1,16 +1,18 @@
 use crate::*;
 
 mod ktx1;
+mod png;
 
 clap::arg_enum! {
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum FileFormat {
     KTX1,
     DDS,
+    PNG,
 }
 }
 
-pub const FILE_FORMATS: [FileFormat; 2] = [FileFormat::KTX1, FileFormat::DDS];
+pub const FILE_FORMATS: [FileFormat; 3] = [FileFormat::KTX1, FileFormat::DDS, FileFormat::PNG];
 
 pub trait FileCodec {
     fn claim_for_parsing(&self, contents: &FileBlob) -> bool;
@@ -23,12 +25,14 @@ impl FileFormat {
         match *self {
             FileFormat::KTX1 => "ktx",
             FileFormat::DDS => "dds",
+            FileFormat::PNG => "png",
         }
     }
 
     pub fn codec(&self) -> Option<Box<FileCodec>> {
         match *self {
             FileFormat::KTX1 => Some(Box::from(ktx1::Ktx1Codec {})),
+            FileFormat::PNG => Some(Box::from(png::PngCodec {})),
             _ => None,
         }
     }
@@ -0,0 +1,257 @@
+use png::{
+    BitDepth, ColorType, Decoder, Encoder, ScaledFloat, SrgbRenderingIntent, Transformations,
+};
+
+use crate::compress::decompress;
+use crate::file::{FileCodec, FileFormat};
+use crate::pixel::decode::{decode_f64, decode_i64};
+use crate::pixel::CompContent::*;
+use crate::pixel::{srgb, PixelFormat};
+use crate::{Dimensions, FileBlob, RuxResult, Texture};
+
+// https://www.w3.org/TR/png/
+//
+// Palettes and bit depths below 8 are expanded on reading, and tRNS becomes alpha. PNG
+// stores 16-bit samples big-endian; textures store them little-endian.
+
+const PNG_MAGIC: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+pub struct PngCodec;
+
+impl FileCodec for PngCodec {
+    fn claim_for_parsing(&self, contents: &FileBlob) -> bool {
+        contents.len() >= 8 && contents[0..8] == PNG_MAGIC
+    }
+    fn parse(&self, contents: &FileBlob) -> RuxResult<Texture> {
+        let mut decoder = Decoder::new(&contents[..]);
+        decoder.set_transformations(Transformations::EXPAND);
+        let mut reader = match decoder.read_info() {
+            Ok(reader) => reader,
+            Err(e) => bail!(format!("PNG: Invalid header: {}", e)),
+        };
+        let mut data = vec![0x00; reader.output_buffer_size()];
+        let output = match reader.next_frame(&mut data) {
+            Ok(output) => output,
+            Err(e) => bail!(format!("PNG: Invalid image data: {}", e)),
+        };
+        data.truncate(output.buffer_size());
+
+        let wide = output.bit_depth == BitDepth::Sixteen;
+        if wide {
+            for sample in data.chunks_exact_mut(2) {
+                sample.swap(0, 1);
+            }
+        }
+        let bits = if wide { 16 } else { 8 };
+        let layout = match output.color_type {
+            ColorType::Grayscale => format!("R{}", bits),
+            ColorType::GrayscaleAlpha => format!("R{0}G{0}", bits),
+            ColorType::Rgb => format!("R{0}G{0}B{0}", bits),
+            ColorType::Rgba => format!("R{0}G{0}B{0}A{0}", bits),
+            ColorType::Indexed => bail!("PNG: Palette survived expansion"),
+        };
+
+        // an sRGB chunk, or any gamma but 1.0, means sRGB, as does no colour information
+        // at all; there are no 16-bit sRGB formats, so those stay UNORM
+        let info = reader.info();
+        let linear = info.srgb.is_none()
+            && info
+                .gama_chunk
+                .is_some_and(|gamma| (gamma.into_value() - 1.0).abs() < 0.01);
+        let content = if linear || wide { "UNORM" } else { "SRGB" };
+        let format = match PixelFormat::for_name(&format!("{}_{}", layout, content)) {
+            Some(format) => format,
+            None => match PixelFormat::for_name(&format!("{}_UNORM", layout)) {
+                Some(format) => format,
+                None => bail!(format!("PNG: No pixel format for {} samples", layout)),
+            },
+        };
+
+        Ok(Texture {
+            format: *format,
+            pixel_dim: Dimensions::new(output.width, output.height, 0),
+            array_size: 0,
+            face_count: 1,
+            mip_blobs: vec![data],
+        })
+    }
+    fn generate(&self, texture: &Texture, format: FileFormat) -> RuxResult<(FileFormat, FileBlob)> {
+        if texture.image_count() != 1 || texture.pixel_dim.depth() != 1 {
+            bail!(format!(
+                "PNG: Holds one 2D image, not {} of {:?}; extract one first",
+                texture.image_count(),
+                texture.pixel_dim
+            ));
+        }
+        // only the base level, as PNG has no mips
+        let base = Texture {
+            mip_blobs: texture.mip_blobs.iter().take(1).cloned().collect(),
+            ..*texture
+        };
+        let base = if base.format.is_compressed() {
+            decompress(&base)?
+        } else {
+            base
+        };
+        let blob = match base.mip_blobs.first() {
+            Some(blob) => blob,
+            None => bail!("PNG: Texture has no base level"),
+        };
+        let pixel_format = base.format;
+
+        // channels and bits per sample from the layout's name, as in "B5G6R5"
+        let layout = format!("{:?}", pixel_format.comp_layout);
+        let has = |channel: char| layout.contains(channel);
+        let color_type = match (has('G'), has('B'), has('A')) {
+            // depth and stencil come out as grey
+            (false, _, false) => ColorType::Grayscale,
+            (false, _, true) if has('R') => ColorType::Rgba,
+            (false, _, true) => ColorType::GrayscaleAlpha,
+            // two channels go back to grey and alpha, as they were read
+            (true, false, _) => ColorType::GrayscaleAlpha,
+            (true, true, false) => ColorType::Rgb,
+            (true, true, true) => ColorType::Rgba,
+        };
+        let widest = layout
+            .split(|c: char| !c.is_ascii_digit())
+            .filter_map(|bits| bits.parse::<u32>().ok())
+            .max()
+            .unwrap_or(8);
+        let content = pixel_format.comp_content;
+        let wide = widest > 8 || matches!(content, SFLOAT | UFLOAT);
+        let max = if wide { 65535.0 } else { 255.0 };
+
+        // integers keep their values as far as they fit; everything else is normalised
+        // to 0..1, with float colour taken to be linear and so sRGB encoded
+        let texels: Vec<[f64; 4]> = if matches!(content, UINT | SINT) {
+            decode_i64(&pixel_format, blob)?
+                .iter()
+                .map(|texel| {
+                    let mut normalised = [0.0; 4];
+                    for (n, value) in normalised.iter_mut().zip(texel) {
+                        *n = (*value as f64).clamp(0.0, max) / max;
+                    }
+                    normalised
+                })
+                .collect()
+        } else {
+            let mut texels = decode_f64(&pixel_format, blob)?;
+            match content {
+                SNORM => {
+                    for texel in texels.iter_mut() {
+                        for value in texel.iter_mut() {
+                            *value = (*value + 1.0) / 2.0;
+                        }
+                    }
+                }
+                SFLOAT | UFLOAT => srgb::transfer(&mut texels, false, true),
+                _ => {}
+            }
+            texels
+        };
+
+        // the alpha-only A8 shows as white
+        let channels: &[usize] = match color_type {
+            ColorType::Grayscale => &[0],
+            ColorType::GrayscaleAlpha if has('G') => &[0, 1],
+            ColorType::GrayscaleAlpha => &[4, 3],
+            ColorType::Rgb => &[0, 1, 2],
+            _ => &[0, 1, 2, 3],
+        };
+        let mut data = vec![];
+        for texel in &texels {
+            for &channel in channels {
+                let value = texel.get(channel).map_or(1.0, |v| v.clamp(0.0, 1.0));
+                let sample = (value * max).round() as u16;
+                if wide {
+                    data.extend_from_slice(&sample.to_be_bytes());
+                } else {
+                    data.push(sample as u8);
+                }
+            }
+        }
+
+        let dim = base.pixel_dim;
+        let mut file_blob = vec![];
+        {
+            let mut encoder = Encoder::new(&mut file_blob, dim.width(), dim.height());
+            encoder.set_color(color_type);
+            encoder.set_depth(if wide {
+                BitDepth::Sixteen
+            } else {
+                BitDepth::Eight
+            });
+            // a UNORM format with an sRGB twin was chosen as linear; without one, as for
+            // 16-bit samples, the colour space is unknown and left unsaid
+            if pixel_format.is_srgb() || matches!(content, SFLOAT | UFLOAT) {
+                encoder.set_source_srgb(SrgbRenderingIntent::Perceptual);
+            } else if content == UNORM && pixel_format.in_colour_space(true).is_some() {
+                encoder.set_source_gamma(ScaledFloat::new(1.0));
+            }
+            let written = encoder
+                .write_header()
+                .and_then(|mut writer| writer.write_image_data(&data));
+            if let Err(e) = written {
+                bail!(format!("PNG: Failed to encode: {}", e));
+            }
+        }
+        Ok((format, file_blob))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(color_type: ColorType, depth: BitDepth, linear: bool, data: &[u8]) -> FileBlob {
+        let mut blob = vec![];
+        {
+            let mut encoder = Encoder::new(&mut blob, 1, 1);
+            encoder.set_color(color_type);
+            encoder.set_depth(depth);
+            if linear {
+                encoder.set_source_gamma(ScaledFloat::new(1.0));
+            }
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(data).unwrap();
+        }
+        blob
+    }
+
+    fn resave(contents: &FileBlob) -> (Texture, FileBlob) {
+        let texture = PngCodec.parse(contents).unwrap();
+        let (_, blob) = PngCodec.generate(&texture, FileFormat::PNG).unwrap();
+        (texture, blob)
+    }
+
+    fn has_chunk(blob: &[u8], name: &[u8]) -> bool {
+        blob.windows(4).any(|window| window == name)
+    }
+
+    #[test]
+    fn colour_space_survives() {
+        // untagged 8-bit colour is sRGB, and gamma 1.0 linear
+        let (texture, blob) = resave(&png(ColorType::Rgb, BitDepth::Eight, false, &[1, 2, 3]));
+        assert!(texture.format.is_srgb());
+        assert!(has_chunk(&blob, b"sRGB"));
+        let (texture, blob) = resave(&png(ColorType::Rgb, BitDepth::Eight, true, &[1, 2, 3]));
+        assert_eq!(texture.format.comp_content, UNORM);
+        assert!(has_chunk(&blob, b"gAMA") && !has_chunk(&blob, b"sRGB"));
+        // 16-bit samples can't say, so an untagged file stays untagged
+        let (_, blob) = resave(&png(
+            ColorType::Grayscale,
+            BitDepth::Sixteen,
+            false,
+            &[1, 2],
+        ));
+        assert!(!has_chunk(&blob, b"gAMA") && !has_chunk(&blob, b"sRGB"));
+    }
+
+    #[test]
+    fn grey_and_alpha_survive() {
+        let contents = png(ColorType::GrayscaleAlpha, BitDepth::Eight, false, &[10, 20]);
+        let (texture, blob) = resave(&contents);
+        assert_eq!(texture.mip_blobs[0], vec![10, 20]);
+        assert_eq!(PngCodec.parse(&blob).unwrap(), texture);
+    }
+}
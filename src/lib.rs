@@ -6,6 +6,8 @@ extern crate simple_error;
 
 extern crate byteordered;
 
+extern crate png;
+
 #[macro_use]
 extern crate enum_primitive_derive;
 extern crate num_traits;
@@ -13,13 +15,32 @@ extern crate num_traits;
 #[macro_use]
 extern crate lazy_static;
 
+use std::cmp;
 use std::result;
 
+pub mod assemble;
+
+pub mod compress;
+
+pub mod convert;
+
+pub mod cubemap;
+
+pub mod environment;
+
 pub mod file;
 use file::{FileFormat, FILE_FORMATS};
 
+pub mod mipmap;
+
 pub mod pixel;
 
+pub mod resample;
+
+pub mod resize;
+
+pub mod swizzle;
+
 pub type FileBlob = Vec<u8>;
 pub type ImageBlob = Vec<u8>;
 
@@ -28,6 +49,34 @@ pub type RuxResult<T> = result::Result<T, Box<std::error::Error>>;
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct Dimensions(u32, u32, u32);
 
+impl Dimensions {
+    pub fn new(width: u32, height: u32, depth: u32) -> Dimensions {
+        Dimensions(width, height, depth)
+    }
+
+    // containers use 0 for unused dimensions, which we treat as extent 1
+    pub fn width(&self) -> u32 {
+        cmp::max(1, self.0)
+    }
+    pub fn height(&self) -> u32 {
+        cmp::max(1, self.1)
+    }
+    pub fn depth(&self) -> u32 {
+        cmp::max(1, self.2)
+    }
+
+    pub fn for_mip_level(&self, level: usize) -> Dimensions {
+        let shrink = |extent: u32| {
+            if extent == 0 {
+                0
+            } else {
+                cmp::max(1, extent >> level)
+            }
+        };
+        Dimensions(shrink(self.0), shrink(self.1), shrink(self.2))
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub struct Texture {
     pub format: pixel::PixelFormat,
@@ -37,6 +86,17 @@ pub struct Texture {
     pub mip_blobs: Vec<ImageBlob>,
 }
 
+impl Texture {
+    pub fn mip_dim(&self, level: usize) -> Dimensions {
+        self.pixel_dim.for_mip_level(level)
+    }
+
+    // number of 2D images stacked in each mip blob, ignoring depth slices
+    pub fn image_count(&self) -> u32 {
+        cmp::max(1, self.array_size) * cmp::max(1, self.face_count)
+    }
+}
+
 pub fn parse(contents: &FileBlob) -> RuxResult<Texture> {
     for variant in &FILE_FORMATS {
         if let Some(codec) = variant.codec() {
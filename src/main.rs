@@ -1,28 +1,54 @@
 extern crate clap;
 
-use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 use std::str::FromStr;
 
+use ruxture::assemble::Assembly;
+use ruxture::compress::{CompressOptions, Quality};
+use ruxture::cubemap::{CubeConvention, CubeLayout};
+use ruxture::environment::{SpecularFormat, SpecularOptions};
 use ruxture::file::FileFormat;
+use ruxture::mipmap::{MipOptions, RoughnessMode, RoughnessOptions};
+use ruxture::pixel::{CompContent, PixelFormat};
+use ruxture::resample::{EdgeMode, Filter};
+use ruxture::resize::ResizeOptions;
+use ruxture::swizzle::ChannelSource;
 use ruxture::*;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 
 fn main() {
     let matches = App::new("Ruxture")
         .version("0.1.0")
         .author("Pär Winzell <par.winzell@alyx.com>")
         .about("Texture file format conversion and exploration.")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("in_file")
                 .required(true)
                 .short("i")
                 .long("input")
                 .takes_value(true)
-                .help("Input file to read"),
+                .multiple(true)
+                .help("Input file to read, or several to assemble, e.g. slice_%03d.ktx"),
+        )
+        .arg(
+            Arg::with_name("assemble")
+                .long("assemble")
+                .takes_value(true)
+                .possible_values(&Assembly::variants())
+                .case_insensitive(true)
+                .help("Combine the input files into an array, cubemap, cube array or volume"),
+        )
+        .arg(
+            Arg::with_name("face_order")
+                .long("face-order")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .requires("assemble")
+                .help("Faces the inputs hold, in order [default: +X,-X,+Y,-Y,+Z,-Z]"),
         )
         .arg(
             Arg::with_name("out_file")
@@ -39,49 +65,1086 @@ fn main() {
                 .possible_values(&FileFormat::variants())
                 .help("Texture file format"),
         )
+        .arg(
+            Arg::with_name("pixel_format")
+                .long("pixel-format")
+                .takes_value(true)
+                .help("Pixel format to convert to, e.g. B5G6R5_UNORM, BC7_SRGB or ASTC_8x8_UNORM"),
+        )
+        .arg(
+            Arg::with_name("quality")
+                .long("quality")
+                .takes_value(true)
+                .possible_values(&Quality::variants())
+                .case_insensitive(true)
+                .help("How hard every block encoder searches [default: Normal]"),
+        )
+        .arg(
+            Arg::with_name("alpha_threshold")
+                .long("alpha-threshold")
+                .takes_value(true)
+                .help("Alpha from 0 to 255 below which BC1 texels turn transparent [default: 128]"),
+        )
+        .arg(
+            Arg::with_name("etc1_compatible")
+                .long("etc1-compatible")
+                .help("Keep ETC2 RGB blocks to the modes that ETC1 decoders understand"),
+        )
+        .arg(
+            Arg::with_name("perceptual")
+                .long("perceptual")
+                .help("Weigh BC7, ETC and ASTC colour error by luma rather than evenly"),
+        )
+        .arg(
+            Arg::with_name("swizzle")
+                .long("swizzle")
+                .takes_value(true)
+                .help("Channels to build from the inputs, e.g. R=in.b,G=rough.r,B=1-gloss.r,A=1"),
+        )
+        .arg(
+            Arg::with_name("pack_input")
+                .long("pack-input")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("swizzle")
+                .help("Another input for --swizzle, as NAME=FILE"),
+        )
+        .arg(
+            Arg::with_name("reinterpret")
+                .long("reinterpret")
+                .takes_value(true)
+                .possible_values(&["linear", "srgb"])
+                .help("Relabel the colour space without touching the data"),
+        )
+        .arg(
+            Arg::with_name("to_cubemap")
+                .long("to-cubemap")
+                .takes_value(true)
+                .conflicts_with("to_equirect")
+                .help("Project an equirectangular panorama onto a cubemap with faces this size"),
+        )
+        .arg(
+            Arg::with_name("to_equirect")
+                .long("to-equirect")
+                .takes_value(true)
+                .help("Unwrap a cubemap into an equirectangular panorama this wide"),
+        )
+        .arg(
+            Arg::with_name("from_layout")
+                .long("from-layout")
+                .takes_value(true)
+                .possible_values(&[
+                    "auto",
+                    "horizontalcross",
+                    "verticalcross",
+                    "horizontalstrip",
+                    "verticalstrip",
+                ])
+                .case_insensitive(true)
+                .conflicts_with_all(&["to_cubemap", "to_equirect", "to_layout"])
+                .help("Cut a cubemap cross or strip image into faces"),
+        )
+        .arg(
+            Arg::with_name("to_layout")
+                .long("to-layout")
+                .takes_value(true)
+                .possible_values(&CubeLayout::variants())
+                .case_insensitive(true)
+                .conflicts_with_all(&["to_cubemap", "to_equirect"])
+                .help("Lay a cubemap's faces out as a cross or strip"),
+        )
+        .arg(
+            Arg::with_name("cube_convention")
+                .long("cube-convention")
+                .takes_value(true)
+                .possible_values(&CubeConvention::variants())
+                .case_insensitive(true)
+                .help("Cubemap orientation [default: Vulkan for KTX, D3D for DDS]"),
+        )
+        .arg(
+            Arg::with_name("resize")
+                .long("resize")
+                .takes_value(true)
+                .help("Resample to new dimensions, as WxH or WxHxD"),
+        )
+        .arg(
+            Arg::with_name("max_size")
+                .long("max-size")
+                .takes_value(true)
+                .help("Shrink to fit within this extent on every axis, keeping proportions"),
+        )
+        .arg(
+            Arg::with_name("resize_filter")
+                .long("resize-filter")
+                .takes_value(true)
+                .possible_values(&Filter::variants())
+                .case_insensitive(true)
+                .help("Filter for resizing [default: Lanczos]"),
+        )
+        .arg(
+            Arg::with_name("power_of_two")
+                .long("power-of-two")
+                .help("Round resized extents to the nearest power of two"),
+        )
+        .arg(
+            Arg::with_name("square")
+                .long("square")
+                .help("Make resized width and height equal, the larger of the two"),
+        )
+        .arg(
+            Arg::with_name("pad_to_blocks")
+                .long("pad-to-blocks")
+                .help("Pad resized width and height to whole compression blocks"),
+        )
+        .arg(
+            Arg::with_name("prefilter_specular")
+                .long("prefilter-specular")
+                .conflicts_with("mipmaps")
+                .help("Prefilter a cubemap's mips with GGX lobes for image-based lighting"),
+        )
+        .arg(
+            Arg::with_name("specular_samples")
+                .long("specular-samples")
+                .takes_value(true)
+                .help("GGX samples per texel when prefiltering [default: 512]"),
+        )
+        .arg(
+            Arg::with_name("specular_format")
+                .long("specular-format")
+                .takes_value(true)
+                .possible_values(&SpecularFormat::variants())
+                .case_insensitive(true)
+                .help("Pixel format of the prefiltered cubemap [default: Rgba16f]"),
+        )
+        .arg(
+            Arg::with_name("sh_irradiance")
+                .long("sh-irradiance")
+                .conflicts_with("out_file")
+                .help("Print a cubemap's L2 spherical harmonic irradiance as JSON"),
+        )
+        .arg(
+            Arg::with_name("irradiance_cubemap")
+                .long("irradiance-cubemap")
+                .takes_value(true)
+                .conflicts_with("prefilter_specular")
+                .help("Replace a cubemap with its diffuse irradiance, with faces this size"),
+        )
+        .arg(
+            Arg::with_name("extract_slice")
+                .long("extract-slice")
+                .takes_value(true)
+                .help("Write one depth slice of a volume, or all of them to e.g. out_%03d.ktx"),
+        )
+        .arg(
+            Arg::with_name("mipmaps")
+                .long("mipmaps")
+                .help("Generate a mip chain from the base level, replacing any mips"),
+        )
+        .arg(
+            Arg::with_name("mip_filter")
+                .long("mip-filter")
+                .takes_value(true)
+                .possible_values(&Filter::variants())
+                .case_insensitive(true)
+                .help("Filter for mip generation [default: Kaiser]"),
+        )
+        .arg(
+            Arg::with_name("mip_levels")
+                .long("mip-levels")
+                .takes_value(true)
+                .help("Number of mip levels to generate, including the base"),
+        )
+        .arg(
+            Arg::with_name("mip_edge")
+                .long("mip-edge")
+                .takes_value(true)
+                .help("Wrap or Clamp at the edges, for all axes or per axis, e.g. wrap,clamp"),
+        )
+        .arg(
+            Arg::with_name("mip_space")
+                .long("mip-space")
+                .takes_value(true)
+                .possible_values(&["linear", "encoded"])
+                .help("Filter sRGB colour as linear light or as stored [default: linear]"),
+        )
+        .arg(
+            Arg::with_name("mip_alpha_coverage")
+                .long("mip-alpha-coverage")
+                .takes_value(true)
+                .help("Keep the coverage of alpha above this reference, e.g. 0.5, on every level"),
+        )
+        .arg(
+            Arg::with_name("normal_map")
+                .long("normal-map")
+                .help("Treat the texture as a tangent-space normal map in mips, BC5 and ASTC"),
+        )
+        .arg(
+            Arg::with_name("roughness_output")
+                .long("roughness-output")
+                .takes_value(true)
+                .requires("normal_map")
+                .help("Roughness texture file to write alongside normal map mips"),
+        )
+        .arg(
+            Arg::with_name("roughness_mode")
+                .long("roughness-mode")
+                .takes_value(true)
+                .possible_values(&RoughnessMode::variants())
+                .case_insensitive(true)
+                .help("How mip roughness follows normal variance [default: Toksvig]"),
+        )
+        .arg(
+            Arg::with_name("base_roughness")
+                .long("base-roughness")
+                .takes_value(true)
+                .help("Perceptual roughness of the material, which normal variance adds to"),
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Write single images out of a texture, decompressed")
+                .arg(
+                    Arg::with_name("in_file")
+                        .required(true)
+                        .short("i")
+                        .long("input")
+                        .takes_value(true)
+                        .help("Input file to read"),
+                )
+                .arg(
+                    Arg::with_name("out_file")
+                        .required(true)
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .help("Output file, with {mip}, {layer}, {face} or {slice} for each that's all"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&FileFormat::variants())
+                        .help("Texture file format"),
+                )
+                .arg(
+                    Arg::with_name("mip")
+                        .long("mip")
+                        .takes_value(true)
+                        .help("Mip level, or all [default: 0]"),
+                )
+                .arg(
+                    Arg::with_name("layer")
+                        .long("layer")
+                        .takes_value(true)
+                        .help("Array layer, or all [default: 0]"),
+                )
+                .arg(
+                    Arg::with_name("face")
+                        .long("face")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .help("Cube face as +X, -X, +Y, -Y, +Z, -Z or 0 to 5, or all [default: +X]"),
+                )
+                .arg(
+                    Arg::with_name("slice")
+                        .long("slice")
+                        .takes_value(true)
+                        .help("Depth slice, or all [default: the whole volume]"),
+                ),
+        )
         .get_matches();
 
-    let in_file = matches.value_of("in_file").unwrap();
+    if let Some(matches) = matches.subcommand_matches("extract") {
+        extract_images(matches);
+        return;
+    }
+
+    let assembly = matches
+        .value_of("assemble")
+        .map(|assembly| Assembly::from_str(assembly).unwrap());
+
+    // numbered files only make sense as several inputs to assemble
+    let in_files: Vec<String> = matches
+        .values_of("in_file")
+        .unwrap()
+        .flat_map(|in_file| match assembly {
+            Some(_) => numbered_files(in_file),
+            None => vec![in_file.to_string()],
+        })
+        .collect();
+    let in_file = in_files[0].as_str();
+    if assembly.is_none() && in_files.len() > 1 {
+        println!("Several inputs need --assemble to say what to make of them.");
+        std::process::exit(1);
+    }
+    let face_order = match matches
+        .value_of("face_order")
+        .map(ruxture::assemble::parse_face_order)
+    {
+        None => [0, 1, 2, 3, 4, 5],
+        Some(Ok(face_order)) => face_order,
+        Some(Err(why)) => {
+            println!("Bad face order:\n{}", why);
+            std::process::exit(1);
+        }
+    };
 
     let format = matches
         .value_of("format")
         .and_then(|format_str| Some(FileFormat::from_str(format_str).unwrap()));
 
+    let pixel_format =
+        matches
+            .value_of("pixel_format")
+            .map(|name| match PixelFormat::for_name(name) {
+                Some(pixel_format) => *pixel_format,
+                None => {
+                    println!("Unknown pixel format: {}", name);
+                    std::process::exit(1);
+                }
+            });
+
+    let swizzle = matches
+        .value_of("swizzle")
+        .map(|spec| parse_swizzle_args(&matches, in_file, spec));
+
+    let reinterpret = matches.value_of("reinterpret").map(|space| space == "srgb");
+
+    let resize_args = if [
+        "resize",
+        "max_size",
+        "power_of_two",
+        "square",
+        "pad_to_blocks",
+    ]
+    .iter()
+    .any(|name| matches.is_present(name))
+    {
+        Some(parse_resize_args(&matches))
+    } else {
+        None
+    };
+
+    let specular = if matches.is_present("prefilter_specular") {
+        Some(parse_specular_options(&matches))
+    } else {
+        None
+    };
+
+    let irradiance = matches
+        .value_of("irradiance_cubemap")
+        .map(|size| match size.parse() {
+            Ok(size) if size > 0 => size,
+            _ => {
+                println!("Bad size: {}", size);
+                std::process::exit(1);
+            }
+        });
+
+    let mip_args = if matches.is_present("mipmaps") {
+        Some(parse_mip_args(&matches))
+    } else {
+        None
+    };
+
+    let projection = parse_projection(&matches);
+
+    let slices = matches.value_of("extract_slice").map(|slice| match slice {
+        "all" => None,
+        _ => match slice.parse() {
+            Ok(z) => Some(z),
+            Err(_) => {
+                println!("Bad slice, expected a number or all: {}", slice);
+                std::process::exit(1);
+            }
+        },
+    });
+
+    let texture = match assembly {
+        Some(assembly) => assemble_files(&in_files, assembly, face_order),
+        None => read_and_parse(Path::new(in_file)),
+    };
+
+    let compress = parse_compress_options(&matches);
+
     if let Some(out_file) = matches.value_of("out_file") {
-        convert_file(in_file, format, out_file);
+        let steps = Steps {
+            compress,
+            swizzle,
+            reinterpret,
+            pixel_format,
+            projection,
+            resize: resize_args,
+            specular,
+            irradiance,
+            mips: mip_args,
+            slices,
+        };
+        convert_file(texture, format, steps, out_file);
+    } else if matches.is_present("sh_irradiance") {
+        print_irradiance(&texture);
+    } else {
+        identify_texture(&texture);
+    }
+}
+
+// everything to do to a texture between reading and writing it, in order, and how to
+// encode the compressed formats any of them produce
+struct Steps {
+    compress: CompressOptions,
+    swizzle: Option<SwizzleArgs>,
+    reinterpret: Option<bool>,
+    // compressed formats are converted to last, so that nothing filters compressed data
+    pixel_format: Option<PixelFormat>,
+    projection: Option<Projection>,
+    resize: Option<ResizeArgs>,
+    specular: Option<SpecularOptions>,
+    irradiance: Option<u32>,
+    mips: Option<MipArgs>,
+    // one depth slice to keep, or all of them to write separately
+    slices: Option<Option<u32>>,
+}
+
+fn parse_compress_options(matches: &clap::ArgMatches) -> CompressOptions {
+    let mut options = CompressOptions {
+        normal_map: matches.is_present("normal_map"),
+        perceptual: matches.is_present("perceptual"),
+        etc1_compatible: matches.is_present("etc1_compatible"),
+        ..CompressOptions::default()
+    };
+    if let Some(quality) = matches.value_of("quality") {
+        options.quality = Quality::from_str(quality).unwrap();
+    }
+    if let Some(threshold) = matches.value_of("alpha_threshold") {
+        options.alpha_threshold = match threshold.parse() {
+            Ok(threshold) => threshold,
+            Err(_) => {
+                println!("Alpha threshold must be between 0 and 255: {}", threshold);
+                std::process::exit(1);
+            }
+        };
+    }
+    options
+}
+
+// where each output channel comes from, and the inputs besides the main one
+struct SwizzleArgs {
+    sources: [ChannelSource; 4],
+    pack_inputs: Vec<String>,
+}
+
+// the main input goes by "in" or its file stem, the others by the names they're given
+fn parse_swizzle_args(matches: &clap::ArgMatches, in_file: &str, spec: &str) -> SwizzleArgs {
+    let stem = Path::new(in_file)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("in");
+    let mut names = vec![stem];
+    let mut pack_inputs = vec![];
+    for input in matches.values_of("pack_input").into_iter().flatten() {
+        match input.find('=') {
+            Some(equals) => {
+                names.push(&input[..equals]);
+                pack_inputs.push(input[equals + 1..].to_string());
+            }
+            None => {
+                println!("Expected NAME=FILE: {}", input);
+                std::process::exit(1);
+            }
+        }
+    }
+    // "in" comes last, so that an input actually named that wins
+    names.push("in");
+    let sources = match ruxture::swizzle::parse_spec(spec, &names) {
+        Ok(sources) => sources,
+        Err(why) => {
+            println!("Bad swizzle:\n{}", why);
+            std::process::exit(1);
+        }
+    };
+    // the second "in" is really the main input
+    let last = names.len() - 1;
+    let sources = sources.map(|source| match source {
+        ChannelSource::Channel {
+            input,
+            channel,
+            invert,
+        } if input == last => ChannelSource::Channel {
+            input: 0,
+            channel,
+            invert,
+        },
+        source => source,
+    });
+    SwizzleArgs {
+        sources,
+        pack_inputs,
+    }
+}
+
+// a change in how a texture lays out the sphere of directions: between cubemap and
+// panorama, with the cubemap's orientation if given, or between cubemap and a flat layout
+// of its faces, detected if not given
+enum Projection {
+    ToCubemap(u32, Option<CubeConvention>),
+    ToEquirect(u32, Option<CubeConvention>),
+    FromLayout(Option<CubeLayout>),
+    ToLayout(CubeLayout),
+}
+
+fn parse_projection(matches: &clap::ArgMatches) -> Option<Projection> {
+    let convention = matches
+        .value_of("cube_convention")
+        .map(|convention| CubeConvention::from_str(convention).unwrap());
+    let size = |name: &str| {
+        matches.value_of(name).map(|size| match size.parse() {
+            Ok(size) if size > 0 => size,
+            _ => {
+                println!("Bad size: {}", size);
+                std::process::exit(1);
+            }
+        })
+    };
+    if let Some(face_size) = size("to_cubemap") {
+        Some(Projection::ToCubemap(face_size, convention))
+    } else if let Some(width) = size("to_equirect") {
+        Some(Projection::ToEquirect(width, convention))
+    } else if let Some(layout) = matches.value_of("from_layout") {
+        Some(Projection::FromLayout(CubeLayout::from_str(layout).ok()))
     } else {
-        identify_file(in_file);
+        matches
+            .value_of("to_layout")
+            .map(|layout| Projection::ToLayout(CubeLayout::from_str(layout).unwrap()))
     }
 }
 
-fn identify_file(in_file: &str) {
-    let in_path = Path::new(in_file);
-    let contents = read_and_parse(in_path);
+// the dimensions asked for, if any, and at most how large they may be
+struct ResizeArgs {
+    options: ResizeOptions,
+    dim: Option<Dimensions>,
+    max_size: Option<u32>,
+}
+
+fn parse_resize_args(matches: &clap::ArgMatches) -> ResizeArgs {
+    let mut options = ResizeOptions::default();
+    if let Some(filter) = matches.value_of("resize_filter") {
+        options.filter = Filter::from_str(filter).unwrap();
+    }
+    options.power_of_two = matches.is_present("power_of_two");
+    options.square = matches.is_present("square");
+    options.pad_to_blocks = matches.is_present("pad_to_blocks");
+
+    let dim = matches.value_of("resize").map(|size| {
+        let extents: Vec<Option<u32>> = size
+            .split('x')
+            .map(|extent| extent.parse().ok().filter(|&extent| extent > 0))
+            .collect();
+        match extents.as_slice() {
+            [Some(width), Some(height)] => Dimensions::new(*width, *height, 0),
+            [Some(width), Some(height), Some(depth)] => Dimensions::new(*width, *height, *depth),
+            _ => {
+                println!("Bad size, expected WxH or WxHxD: {}", size);
+                std::process::exit(1);
+            }
+        }
+    });
+    let max_size = matches.value_of("max_size").map(|size| match size.parse() {
+        Ok(size) if size > 0 => size,
+        _ => {
+            println!("Bad maximum size: {}", size);
+            std::process::exit(1);
+        }
+    });
+    ResizeArgs {
+        options,
+        dim,
+        max_size,
+    }
+}
+
+fn parse_specular_options(matches: &clap::ArgMatches) -> SpecularOptions {
+    let mut options = SpecularOptions::default();
+    if let Some(samples) = matches.value_of("specular_samples") {
+        options.samples = match samples.parse() {
+            Ok(samples) if samples > 0 => samples,
+            _ => {
+                println!("Bad sample count: {}", samples);
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(format) = matches.value_of("specular_format") {
+        options.format = SpecularFormat::from_str(format).unwrap();
+    }
+    if let Some(levels) = matches.value_of("mip_levels") {
+        options.levels = match levels.parse() {
+            Ok(levels) => Some(levels),
+            Err(_) => {
+                println!("Bad mip level count: {}", levels);
+                std::process::exit(1);
+            }
+        };
+    }
+    options
+}
+
+// mip options, and for normal maps where to write roughness if anywhere
+struct MipArgs {
+    options: MipOptions,
+    normal_map: bool,
+    roughness: Option<(RoughnessOptions, String)>,
+}
+
+fn parse_mip_args(matches: &clap::ArgMatches) -> MipArgs {
+    let mut options = MipOptions::default();
+    if let Some(filter) = matches.value_of("mip_filter") {
+        options.filter = Filter::from_str(filter).unwrap();
+    }
+    if let Some(levels) = matches.value_of("mip_levels") {
+        options.levels = match levels.parse() {
+            Ok(levels) => Some(levels),
+            Err(_) => {
+                println!("Bad mip level count: {}", levels);
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(edges) = matches.value_of("mip_edge") {
+        let modes: Vec<&str> = edges.split(',').collect();
+        if modes.len() > 3 {
+            println!("At most three edge modes, one per axis: {}", edges);
+            std::process::exit(1);
+        }
+        // the last mode given carries over to the remaining axes
+        for axis in 0..3 {
+            let mode = modes[axis.min(modes.len() - 1)];
+            options.edges[axis] = match EdgeMode::from_str(mode) {
+                Ok(mode) => mode,
+                Err(_) => {
+                    println!("Unknown edge mode: {}", mode);
+                    std::process::exit(1);
+                }
+            };
+        }
+    }
+    if let Some(space) = matches.value_of("mip_space") {
+        options.linear = space == "linear";
+    }
+    if let Some(reference) = matches.value_of("mip_alpha_coverage") {
+        options.alpha_reference = match reference.parse::<f64>() {
+            Ok(reference) if (0.0..=1.0).contains(&reference) => Some(reference),
+            _ => {
+                println!("Alpha reference must be between 0 and 1: {}", reference);
+                std::process::exit(1);
+            }
+        };
+    }
+    let normal_map = matches.is_present("normal_map");
+
+    let roughness = matches.value_of("roughness_output").map(|path| {
+        let mode = matches
+            .value_of("roughness_mode")
+            .map_or(RoughnessMode::Toksvig, |mode| {
+                RoughnessMode::from_str(mode).unwrap()
+            });
+        let base = match matches.value_of("base_roughness").map(str::parse::<f64>) {
+            None => 0.0,
+            Some(Ok(base)) if (0.0..=1.0).contains(&base) => base,
+            Some(_) => {
+                println!("Base roughness must be between 0 and 1.");
+                std::process::exit(1);
+            }
+        };
+        (RoughnessOptions { mode, base }, path.to_string())
+    });
+
+    MipArgs {
+        options,
+        normal_map,
+        roughness,
+    }
+}
+
+fn identify_texture(contents: &Texture) {
     println!("Pixel Format: {:?}", contents.format);
     println!("Texture Dimensions: {:?}", contents.pixel_dim);
 }
 
-fn convert_file(in_file: &str, format: Option<FileFormat>, out_file: &str) {
-    let texture = read_and_parse(Path::new(in_file));
+// one JSON object for a cubemap, or an array of them for a cubemap array
+fn print_irradiance(texture: &Texture) {
+    let harmonics = match ruxture::environment::irradiance_sh(texture) {
+        Err(why) => {
+            println!("Failed to compute irradiance:\n{}", why);
+            std::process::exit(1);
+        }
+        Ok(harmonics) => harmonics,
+    };
+    let objects: Vec<String> = match harmonics.iter().map(|sh| sh.to_json()).collect() {
+        Err(why) => {
+            println!("Failed to write irradiance:\n{}", why);
+            std::process::exit(1);
+        }
+        Ok(objects) => objects,
+    };
+    if texture.array_size == 0 {
+        println!("{}", objects.join("\n"));
+    } else {
+        println!("[\n{}\n]", objects.join(",\n"));
+    }
+}
 
+fn convert_file(mut texture: Texture, format: Option<FileFormat>, steps: Steps, out_file: &str) {
     let out_path = Path::new(out_file);
     let format = match format {
         None => format_from_path(out_path),
         Some(f) => f,
     };
 
+    if let Some(swizzle) = steps.swizzle {
+        // several inputs pack into RGBA unless an uncompressed format is asked for, and
+        // a compressed one, converted to last, gets RGBA that holds what it can
+        let pack_inputs: Vec<Texture> = swizzle
+            .pack_inputs
+            .iter()
+            .map(|path| read_and_parse(Path::new(path)))
+            .collect();
+        let target = match steps.pixel_format {
+            Some(pixel_format) if !pixel_format.is_compressed() => pixel_format,
+            Some(pixel_format) => {
+                let name = match pixel_format.comp_content {
+                    CompContent::SFLOAT | CompContent::UFLOAT => "R16G16B16A16_SFLOAT",
+                    CompContent::SNORM => "R16G16B16A16_SNORM",
+                    CompContent::SRGB => "R8G8B8A8_SRGB",
+                    _ => "R16G16B16A16_UNORM",
+                };
+                *PixelFormat::for_name(name).unwrap()
+            }
+            None if pack_inputs.is_empty() => texture.format,
+            None => *PixelFormat::for_name("R8G8B8A8_UNORM").unwrap(),
+        };
+        let inputs: Vec<&Texture> = std::iter::once(&texture).chain(&pack_inputs).collect();
+        texture = match ruxture::swizzle::pack(&inputs, &swizzle.sources, &target, &steps.compress)
+        {
+            Err(why) => {
+                println!("Failed to swizzle texture:\n{}", why);
+                std::process::exit(1);
+            }
+            Ok(texture) => texture,
+        };
+    }
+
+    if let Some(srgb) = steps.reinterpret {
+        texture = match ruxture::convert::reinterpret(&texture, srgb) {
+            Err(why) => {
+                println!("Failed to reinterpret texture:\n{}", why);
+                std::process::exit(1);
+            }
+            Ok(texture) => texture,
+        };
+    }
+
+    if let Some(pixel_format) = steps.pixel_format.filter(|f| !f.is_compressed()) {
+        texture = convert(&texture, &pixel_format, &steps.compress);
+    }
+
+    if let Some(projection) = steps.projection {
+        // each container has its own convention, unless one is asked for
+        let default_convention = match format {
+            FileFormat::DDS => CubeConvention::D3D,
+            _ => CubeConvention::Vulkan,
+        };
+        let options = steps.compress;
+        let projected = match projection {
+            Projection::ToCubemap(face_size, convention) => ruxture::cubemap::equirect_to_cubemap(
+                &texture,
+                face_size,
+                convention.unwrap_or(default_convention),
+                &options,
+            ),
+            Projection::ToEquirect(width, convention) => ruxture::cubemap::cubemap_to_equirect(
+                &texture,
+                width,
+                convention.unwrap_or(default_convention),
+                &options,
+            ),
+            Projection::FromLayout(layout) => {
+                ruxture::cubemap::cubemap_from_layout(&texture, layout, &options)
+            }
+            Projection::ToLayout(layout) => {
+                ruxture::cubemap::cubemap_to_layout(&texture, layout, &options)
+            }
+        };
+        texture = match projected {
+            Err(why) => {
+                println!("Failed to project texture:\n{}", why);
+                std::process::exit(1);
+            }
+            Ok(texture) => texture,
+        };
+    }
+
+    if let Some(resize_args) = steps.resize {
+        // a 2D resize leaves the depth of 3D textures alone
+        let mut dim = resize_args.dim.map_or(texture.pixel_dim, |dim| {
+            if dim.depth() == 1 {
+                Dimensions::new(dim.width(), dim.height(), texture.pixel_dim.depth())
+            } else {
+                dim
+            }
+        });
+        if let Some(max_size) = resize_args.max_size {
+            dim = ruxture::resize::fit(dim, max_size);
+        }
+        let options = ResizeOptions {
+            compress: steps.compress,
+            ..resize_args.options
+        };
+        texture = match ruxture::resize::resize(&texture, dim, &options) {
+            Err(why) => {
+                println!("Failed to resize texture:\n{}", why);
+                std::process::exit(1);
+            }
+            Ok(texture) => texture,
+        };
+    }
+
+    if let Some(specular) = steps.specular {
+        let specular = SpecularOptions {
+            compress: steps.compress,
+            ..specular
+        };
+        texture = match ruxture::environment::prefilter_specular(&texture, &specular) {
+            Err(why) => {
+                println!("Failed to prefilter environment map:\n{}", why);
+                std::process::exit(1);
+            }
+            Ok(texture) => texture,
+        };
+    }
+
+    if let Some(size) = steps.irradiance {
+        texture = match ruxture::environment::irradiance_cubemap(&texture, size) {
+            Err(why) => {
+                println!("Failed to render irradiance:\n{}", why);
+                std::process::exit(1);
+            }
+            Ok(texture) => texture,
+        };
+    }
+
+    if let Some(mip_args) = steps.mips {
+        let options = MipOptions {
+            compress: steps.compress,
+            ..mip_args.options
+        };
+        let generated = if mip_args.normal_map {
+            let roughness = mip_args.roughness.as_ref().map(|(options, _)| options);
+            ruxture::mipmap::generate_normal_mips(&texture, &options, roughness)
+        } else {
+            ruxture::mipmap::generate_mips(&texture, &options).map(|t| (t, None))
+        };
+        texture = match generated {
+            Err(why) => {
+                println!("Failed to generate mips:\n{}", why);
+                std::process::exit(1);
+            }
+            Ok((texture, roughness_texture)) => {
+                if let (Some(roughness), Some((_, path))) = (roughness_texture, mip_args.roughness)
+                {
+                    let bytes_written = generate_and_write(&roughness, format, Path::new(&path));
+                    println!("Wrote {} bytes of roughness to {}.", bytes_written, path);
+                }
+                texture
+            }
+        };
+    }
+
+    if let Some(pixel_format) = steps.pixel_format.filter(|f| f.is_compressed()) {
+        texture = convert(&texture, &pixel_format, &steps.compress);
+    }
+
+    match steps.slices {
+        Some(Some(z)) => texture = extract_slice(&texture, z),
+        Some(None) => {
+            if numbered_path(out_file, 0).is_none() {
+                println!("Writing every slice needs a numbered output, e.g. out_%03d.ktx");
+                std::process::exit(1);
+            }
+            for z in 0..texture.pixel_dim.depth() {
+                let slice = extract_slice(&texture, z);
+                let path = numbered_path(out_file, z as usize).unwrap();
+                let bytes_written = generate_and_write(&slice, format, Path::new(&path));
+                println!("Wrote {} bytes to {}.", bytes_written, path);
+            }
+            return;
+        }
+        None => {}
+    }
+
     let bytes_written = generate_and_write(&texture, format, out_path);
     println!("Wrote {} bytes to {}.", bytes_written, out_file);
 }
 
+// Writes the images the selectors pick, one file each. Every selector that's all needs its
+// placeholder in the output path, or else gets its number added to the file name.
+fn extract_images(matches: &clap::ArgMatches) {
+    let texture = read_and_parse(Path::new(matches.value_of("in_file").unwrap()));
+    let out_file = matches.value_of("out_file").unwrap();
+    let format = match matches.value_of("format") {
+        Some(format) => FileFormat::from_str(format).unwrap(),
+        None => format_from_path(Path::new(out_file)),
+    };
+
+    let select = |name: &str, count: usize| -> Option<Vec<usize>> {
+        let value = matches.value_of(name)?;
+        if value == "all" {
+            return Some((0..count).collect());
+        }
+        let index = match value.parse() {
+            Ok(index) => Some(index),
+            Err(_) if name == "face" => ruxture::assemble::FACE_NAMES
+                .iter()
+                .position(|face| face.eq_ignore_ascii_case(value)),
+            Err(_) => None,
+        };
+        match index {
+            Some(index) => Some(vec![index]),
+            None => {
+                println!("Bad {}, expected a number or all: {}", name, value);
+                std::process::exit(1);
+            }
+        }
+    };
+    let levels = select("mip", texture.mip_blobs.len()).unwrap_or_else(|| vec![0]);
+    let layers = select("layer", texture.array_size.max(1) as usize).unwrap_or_else(|| vec![0]);
+    let faces = select("face", texture.face_count.max(1) as usize).unwrap_or_else(|| vec![0]);
+
+    let mut path = out_file.to_string();
+    for name in &["mip", "layer", "face", "slice"] {
+        let placeholder = format!("{{{}}}", name);
+        if matches.value_of(name) == Some("all") && !path.contains(&placeholder) {
+            let numbered = {
+                let path = Path::new(&path);
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let extension = path
+                    .extension()
+                    .map_or(String::new(), |ext| format!(".{}", ext.to_string_lossy()));
+                let name = format!("{}_{}{}{}", stem, name, placeholder, extension);
+                path.with_file_name(name).to_string_lossy().into_owned()
+            };
+            path = numbered;
+        }
+    }
+
+    for &level in &levels {
+        // each level has its own depth
+        let depth = texture.mip_dim(level).depth() as usize;
+        let slices = match select("slice", depth) {
+            Some(slices) => slices.into_iter().map(|z| Some(z as u32)).collect(),
+            None => vec![None],
+        };
+        for &layer in &layers {
+            for &face in &faces {
+                for &slice in &slices {
+                    let image = match ruxture::assemble::extract(
+                        &texture,
+                        level,
+                        layer as u32,
+                        face as u32,
+                        slice,
+                    ) {
+                        Err(why) => {
+                            println!("Failed to extract image:\n{}", why);
+                            std::process::exit(1);
+                        }
+                        Ok(image) => image,
+                    };
+                    let image_path = path
+                        .replace("{mip}", &level.to_string())
+                        .replace("{layer}", &layer.to_string())
+                        .replace("{face}", &face.to_string())
+                        .replace("{slice}", &slice.unwrap_or(0).to_string());
+                    let bytes_written = generate_and_write(&image, format, Path::new(&image_path));
+                    println!("Wrote {} bytes to {}.", bytes_written, image_path);
+                }
+            }
+        }
+    }
+}
+
+fn convert(texture: &Texture, pixel_format: &PixelFormat, options: &CompressOptions) -> Texture {
+    match ruxture::convert::convert(texture, pixel_format, options) {
+        Err(why) => {
+            println!("Failed to convert texture:\n{}", why);
+            std::process::exit(1);
+        }
+        Ok(texture) => texture,
+    }
+}
+
+fn extract_slice(texture: &Texture, z: u32) -> Texture {
+    match ruxture::assemble::extract_slice(texture, z) {
+        Err(why) => {
+            println!("Failed to extract slice:\n{}", why);
+            std::process::exit(1);
+        }
+        Ok(slice) => slice,
+    }
+}
+
+// The path with a printf-style %d or %03d in it replaced by the number, if it has one.
+fn numbered_path(pattern: &str, number: usize) -> Option<String> {
+    let start = pattern.find('%')?;
+    let end = start + 1 + pattern[start + 1..].find('d')?;
+    let width = &pattern[start + 1..end];
+    if !width.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!(
+        "{}{:0width$}{}",
+        &pattern[..start],
+        number,
+        &pattern[end + 1..],
+        width = width.parse().unwrap_or(0)
+    ))
+}
+
+// Every file a numbered path names, counting up from 0 or 1 until one is missing.
+fn numbered_files(pattern: &str) -> Vec<String> {
+    let exists = |number| numbered_path(pattern, number).filter(|path| Path::new(path).exists());
+    let first = match exists(0) {
+        Some(_) => 0,
+        None if exists(1).is_some() => 1,
+        None if numbered_path(pattern, 0).is_none() => return vec![pattern.to_string()],
+        None => {
+            println!("No files numbered from 0 or 1 match {}.", pattern);
+            std::process::exit(1);
+        }
+    };
+    (first..).map_while(exists).collect()
+}
+
+fn assemble_files(in_files: &[String], assembly: Assembly, face_order: [usize; 6]) -> Texture {
+    let inputs: Vec<Texture> = in_files
+        .iter()
+        .map(|in_file| read_and_parse(Path::new(in_file)))
+        .collect();
+    match ruxture::assemble::assemble(&inputs, assembly, face_order) {
+        Err(why) => {
+            println!("Failed to assemble inputs:\n{}", why);
+            std::process::exit(1);
+        }
+        Ok(texture) => texture,
+    }
+}
+
 fn format_from_path(out_path: &Path) -> FileFormat {
     if let Some(ext) = out_path.extension() {
         match ext.to_str() {
             Some("dds") => FileFormat::DDS,
-            Some("ktx") => {
-                println!("Use --format KTX1 or --format KTX2 to identify output format.");
-                std::process::exit(1);
-            }
+            Some("png") => FileFormat::PNG,
+            Some("ktx") => FileFormat::KTX1,
             _ => FileFormat::DDS,
         }
     } else {
@@ -96,7 +1159,7 @@ fn format_from_path(out_path: &Path) -> FileFormat {
 fn generate_and_write(texture: &Texture, format: FileFormat, out_path: &Path) -> usize {
     let (generated_format, file_blob) = match ruxture::generate(&texture, format) {
         Err(why) => {
-            println!("Failed to generate texture:\n{}", why.description());
+            println!("Failed to generate texture:\n{}", why);
             std::process::exit(1);
         }
         Ok(blob) => blob,
@@ -108,7 +1171,7 @@ fn generate_and_write(texture: &Texture, format: FileFormat, out_path: &Path) ->
             println!(
                 "Failed to create file {} for writing:\n{}",
                 out_path.display(),
-                why.description(),
+                why,
             );
             std::process::exit(1);
         }
@@ -120,7 +1183,7 @@ fn generate_and_write(texture: &Texture, format: FileFormat, out_path: &Path) ->
             println!(
                 "Failed to write data to file {}:\n{}",
                 out_path.display(),
-                why.description(),
+                why,
             );
             std::process::exit(1);
         }
@@ -134,7 +1197,7 @@ fn read_and_parse(in_path: &Path) -> Texture {
             println!(
                 "Failed to open file {} for reading:\n{}",
                 in_path.display(),
-                why.description(),
+                why,
             );
             std::process::exit(1);
         }
@@ -146,7 +1209,7 @@ fn read_and_parse(in_path: &Path) -> Texture {
         println!(
             "Failed to read data from file {}:\n{}",
             in_path.display(),
-            why.description(),
+            why,
         );
         std::process::exit(1);
     }
@@ -156,7 +1219,7 @@ fn read_and_parse(in_path: &Path) -> Texture {
             println!(
                 "Failed to parse contents of file {}:\n{}",
                 in_path.display(),
-                why.description(),
+                why,
             );
             std::process::exit(1);
         }
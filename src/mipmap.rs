@@ -0,0 +1,361 @@
+use crate::compress::CompressOptions;
+use crate::pixel::encode::encode_f64;
+use crate::pixel::CompContent::*;
+use crate::pixel::{srgb, PixelFormat};
+use crate::resample::{check_filterable, decode_level, encode_level, resample, EdgeMode, Filter};
+use crate::{Dimensions, ImageBlob, RuxResult, Texture};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MipOptions {
+    pub filter: Filter,
+    // for the x, y and z axes; cube faces always clamp, since they don't wrap onto
+    // themselves
+    pub edges: [EdgeMode; 3],
+    // how many levels the chain should have, including the base; all of them if None
+    pub levels: Option<usize>,
+    // sRGB colour is filtered as linear light rather than as its encoded values
+    pub linear: bool,
+    // keeps the fraction of texels with alpha above this value the same on every level,
+    // so that alpha-tested cutouts don't thin out with distance
+    pub alpha_reference: Option<f64>,
+    // how compressed textures are re-encoded
+    pub compress: CompressOptions,
+}
+
+impl Default for MipOptions {
+    fn default() -> MipOptions {
+        MipOptions {
+            filter: Filter::Kaiser,
+            edges: [EdgeMode::Clamp; 3],
+            levels: None,
+            linear: true,
+            alpha_reference: None,
+            compress: CompressOptions::default(),
+        }
+    }
+}
+
+// the number of levels in a full chain, down to 1x1x1
+pub fn full_level_count(dim: Dimensions) -> usize {
+    let largest = dim.width().max(dim.height()).max(dim.depth());
+    (32 - largest.leading_zeros()) as usize
+}
+
+// Replaces any existing mips with a chain filtered from the base level, each level from
+// the one above it. Every array layer and face is filtered on its own, while 3D textures
+// also shrink in depth. Odd extents round down, with the filter spread over the whole
+// source so that no texel is dropped.
+pub fn generate_mips(texture: &Texture, options: &MipOptions) -> RuxResult<Texture> {
+    let format = &texture.format;
+    let base = base_level(texture)?;
+    let (levels, edges) = chain(texture, options)?;
+
+    let image_count = texture.image_count();
+    let mut dim = texture.mip_dim(0);
+    let mut texels = decode_level(format, base, dim, image_count)?;
+    let linear = options.linear && format.is_srgb();
+    if linear {
+        srgb::transfer(&mut texels, true, false);
+    }
+    let coverages: Vec<f64> = match options.alpha_reference {
+        Some(reference) => texels
+            .chunks(volume(dim))
+            .map(|image| alpha_coverage(image, reference, 1.0))
+            .collect(),
+        None => vec![],
+    };
+
+    let mut mip_blobs = vec![base.clone()];
+    for level in 1..levels {
+        let next = texture.mip_dim(level);
+        texels = shrink(&texels, dim, next, options.filter, edges);
+
+        // each level is filtered from the one above as it was before any alpha scaling
+        let mut level_texels = texels.clone();
+        if let Some(reference) = options.alpha_reference {
+            let images = level_texels.chunks_mut(volume(next));
+            for (image, &coverage) in images.zip(&coverages) {
+                scale_alpha(image, reference, coverage);
+            }
+        }
+        if linear {
+            srgb::transfer(&mut level_texels, false, true);
+        }
+        match encode_level(format, &level_texels, next, image_count, &options.compress) {
+            Ok(blob) => mip_blobs.push(blob),
+            Err(e) => bail!(format!("Mip level {}: {}", level, e)),
+        }
+        dim = next;
+    }
+
+    Ok(Texture {
+        mip_blobs,
+        ..*texture
+    })
+}
+
+clap::arg_enum! {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RoughnessMode {
+    // from how much shorter the average of a texel's normals is than unit length
+    Toksvig,
+    // from the variance of normal slopes, filtered down the chain with the normals
+    Lean,
+}
+}
+
+// Roughness that grows with the spread of the normals each mip texel stands for, so that
+// bumps too small to see still widen highlights instead of sparkling.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RoughnessOptions {
+    pub mode: RoughnessMode,
+    // the material's own perceptual roughness, which the spread adds to
+    pub base: f64,
+}
+
+// Mip generation for tangent-space normal maps: vectors are filtered and renormalised
+// rather than treated as colour, and with roughness options an R8_UNORM texture of
+// perceptual roughness comes back alongside, with the same chain. Two-channel formats,
+// and ASTC compressed as a normal map, hold only X and Y, with Z reconstructed.
+pub fn generate_normal_mips(
+    texture: &Texture,
+    options: &MipOptions,
+    roughness: Option<&RoughnessOptions>,
+) -> RuxResult<(Texture, Option<Texture>)> {
+    let format = &texture.format;
+    let base = base_level(texture)?;
+    let (levels, edges) = chain(texture, options)?;
+    let packing = Packing::of(format, &options.compress);
+    let roughness_format = match PixelFormat::for_name("R8_UNORM") {
+        Some(roughness_format) => roughness_format,
+        None => bail!("No R8_UNORM format to hold roughness."),
+    };
+
+    let image_count = texture.image_count();
+    let mut dim = texture.mip_dim(0);
+    let mut normals: Vec<[f64; 4]> = decode_level(format, base, dim, image_count)?
+        .iter()
+        .map(|texel| packing.read(texel))
+        .collect();
+    let mut moments: Vec<[f64; 4]> = normals.iter().map(slope_moments).collect();
+
+    let mut mip_blobs = vec![base.clone()];
+    let mut roughness_blobs = vec![];
+    if let Some(roughness) = roughness {
+        let base = [roughness.base, 0.0, 0.0, 1.0];
+        roughness_blobs.push(encode_f64(roughness_format, &vec![base; normals.len()])?);
+    }
+    for level in 1..levels {
+        let next = texture.mip_dim(level);
+        // the chain keeps the shortened averages, which Toksvig measures, and only what
+        // gets encoded is renormalised
+        normals = shrink(&normals, dim, next, options.filter, edges);
+        let level_texels: Vec<[f64; 4]> = normals
+            .iter()
+            .map(|normal| packing.write(&normalise(normal)))
+            .collect();
+        match encode_level(format, &level_texels, next, image_count, &options.compress) {
+            Ok(blob) => mip_blobs.push(blob),
+            Err(e) => bail!(format!("Mip level {}: {}", level, e)),
+        }
+
+        if let Some(roughness) = roughness {
+            let alpha = roughness.base * roughness.base;
+            let variances: Vec<f64> = match roughness.mode {
+                RoughnessMode::Toksvig => normals
+                    .iter()
+                    .map(|normal| {
+                        let length = normal[..3].iter().map(|v| v * v).sum::<f64>().sqrt();
+                        let length = length.clamp(1e-4, 1.0);
+                        2.0 * (1.0 - length) / length
+                    })
+                    .collect(),
+                RoughnessMode::Lean => {
+                    moments = shrink(&moments, dim, next, options.filter, edges);
+                    moments
+                        .iter()
+                        .map(|m| (m[2] - m[0] * m[0]).max(0.0) + (m[3] - m[1] * m[1]).max(0.0))
+                        .collect()
+                }
+            };
+            // with Beckmann-like lobes, the slope variances of both axes add up to the
+            // square of alpha
+            let values: Vec<[f64; 4]> = variances
+                .iter()
+                .map(|variance| {
+                    let spread = (alpha * alpha + variance).sqrt().min(1.0);
+                    [spread.sqrt(), 0.0, 0.0, 1.0]
+                })
+                .collect();
+            roughness_blobs.push(encode_f64(roughness_format, &values)?);
+        }
+        dim = next;
+    }
+
+    let roughness_texture = roughness.map(|_| Texture {
+        format: *roughness_format,
+        mip_blobs: roughness_blobs,
+        ..*texture
+    });
+    Ok((
+        Texture {
+            mip_blobs,
+            ..*texture
+        },
+        roughness_texture,
+    ))
+}
+
+// where a format keeps the components of a normal
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Packing {
+    Xyz { signed: bool },
+    Xy { signed: bool },
+    // ASTC normal maps keep X in the colour channels and Y in alpha
+    XInColourYInAlpha,
+}
+
+impl Packing {
+    fn of(format: &PixelFormat, options: &CompressOptions) -> Packing {
+        use crate::pixel::CompLayout::*;
+        let signed = matches!(format.comp_content, SNORM | SFLOAT);
+        if format.tag == "ASTC" && options.normal_map {
+            Packing::XInColourYInAlpha
+        } else if matches!(format.comp_layout, R8G8 | R11G11 | R16G16 | R32G32 | R64G64) {
+            Packing::Xy { signed }
+        } else {
+            Packing::Xyz { signed }
+        }
+    }
+
+    // the unit normal a decoded texel holds, with the texel's alpha
+    fn read(self, texel: &[f64; 4]) -> [f64; 4] {
+        let unpack = |value: f64, signed: bool| if signed { value } else { value * 2.0 - 1.0 };
+        let (x, y, z, alpha) = match self {
+            Packing::Xyz { signed } => (
+                unpack(texel[0], signed),
+                unpack(texel[1], signed),
+                unpack(texel[2], signed),
+                texel[3],
+            ),
+            Packing::Xy { signed } => {
+                (unpack(texel[0], signed), unpack(texel[1], signed), 0.0, 1.0)
+            }
+            Packing::XInColourYInAlpha => {
+                (unpack(texel[0], false), unpack(texel[3], false), 0.0, 1.0)
+            }
+        };
+        let z = match self {
+            Packing::Xyz { .. } => z,
+            _ => (1.0 - x * x - y * y).max(0.0).sqrt(),
+        };
+        normalise(&[x, y, z, alpha])
+    }
+
+    // All three components go to the encoder, which for two-channel targets writes
+    // what it needs of them.
+    fn write(self, normal: &[f64; 4]) -> [f64; 4] {
+        let signed = match self {
+            Packing::Xyz { signed } | Packing::Xy { signed } => signed,
+            Packing::XInColourYInAlpha => false,
+        };
+        let pack = |value: f64| if signed { value } else { (value + 1.0) / 2.0 };
+        let alpha = match self {
+            Packing::Xyz { .. } => normal[3],
+            _ => 1.0,
+        };
+        [pack(normal[0]), pack(normal[1]), pack(normal[2]), alpha]
+    }
+}
+
+// a vector of unit length, or straight up if it has none
+fn normalise(normal: &[f64; 4]) -> [f64; 4] {
+    let length = normal[..3].iter().map(|v| v * v).sum::<f64>().sqrt();
+    if length < 1e-9 {
+        return [0.0, 0.0, 1.0, normal[3]];
+    }
+    [
+        normal[0] / length,
+        normal[1] / length,
+        normal[2] / length,
+        normal[3],
+    ]
+}
+
+// the slopes of a unit normal and their squares, whose filtered values give the slope
+// variance of a mip texel
+fn slope_moments(normal: &[f64; 4]) -> [f64; 4] {
+    let z = normal[2].max(1e-3);
+    let (x, y) = (normal[0] / z, normal[1] / z);
+    [x, y, x * x, y * y]
+}
+
+// the base level, if the format is one that filters
+fn base_level(texture: &Texture) -> RuxResult<&ImageBlob> {
+    check_filterable(&texture.format)?;
+    match texture.mip_blobs.first() {
+        Some(base) => Ok(base),
+        None => bail!("Texture has no base level to generate mips from."),
+    }
+}
+
+// the number of levels to generate and the edge modes to filter them with
+fn chain(texture: &Texture, options: &MipOptions) -> RuxResult<(usize, [EdgeMode; 3])> {
+    let full = full_level_count(texture.pixel_dim);
+    let levels = match options.levels {
+        Some(0) => bail!("A mip chain needs at least one level."),
+        Some(levels) => levels.min(full),
+        None => full,
+    };
+    let mut edges = options.edges;
+    if texture.face_count == 6 {
+        edges[0] = EdgeMode::Clamp;
+        edges[1] = EdgeMode::Clamp;
+    }
+    Ok((levels, edges))
+}
+
+// filters every image of a level down to the next level's dimensions
+fn shrink(
+    texels: &[[f64; 4]],
+    dim: Dimensions,
+    next: Dimensions,
+    filter: Filter,
+    edges: [EdgeMode; 3],
+) -> Vec<[f64; 4]> {
+    texels
+        .chunks(volume(dim))
+        .flat_map(|image| resample(image, dim, next, filter, edges))
+        .collect()
+}
+
+fn volume(dim: Dimensions) -> usize {
+    (dim.width() * dim.height() * dim.depth()) as usize
+}
+
+// the fraction of texels whose alpha, once scaled, is above the reference
+fn alpha_coverage(texels: &[[f64; 4]], reference: f64, scale: f64) -> f64 {
+    let covered = texels
+        .iter()
+        .filter(|texel| (texel[3] * scale).min(1.0) > reference)
+        .count();
+    covered as f64 / texels.len() as f64
+}
+
+// Scales alpha so that the image's coverage comes as close as it can to the given one,
+// searching for the scale by bisection since coverage only grows with it.
+fn scale_alpha(texels: &mut [[f64; 4]], reference: f64, coverage: f64) {
+    let (mut low, mut high) = (0.0, 4.0);
+    for _ in 0..20 {
+        let scale = (low + high) / 2.0;
+        if alpha_coverage(texels, reference, scale) > coverage {
+            high = scale;
+        } else {
+            low = scale;
+        }
+    }
+    let scale = (low + high) / 2.0;
+    for texel in texels {
+        texel[3] = (texel[3] * scale).min(1.0);
+    }
+}
@@ -7,7 +7,7 @@ use crate::pixel::CompLayout::*;
 use crate::pixel::{CompContent, CompLayout, Dimensions, PixelFormat};
 
 pub fn get_formats() -> Vec<PixelFormat> {
-    vec![bc_formats(), eac_formats()]
+    vec![bc_formats(), etc_formats(), eac_formats(), astc_formats()]
         .iter()
         .cloned()
         .flatten()
@@ -26,12 +26,12 @@ fn bc_formats() -> Vec<PixelFormat> {
             .with_gl(GlFormat::COMPRESSED_RGBA_S3TC_DXT1_EXT)
             .with_dxt10(Dxt10Format::DXGI_FORMAT_BC1_UNORM)
             .with_vulkan(VkFormat::VK_FORMAT_BC1_RGBA_UNORM_BLOCK),
-        block_2d("BC1", 4, 4, R8G8B8A8, SRGB)
-            .with_gl(GlFormat::COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT)
+        block_2d("BC1", 4, 4, R8G8B8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB_S3TC_DXT1_EXT)
             // no dxt10
             .with_vulkan(VkFormat::VK_FORMAT_BC1_RGB_SRGB_BLOCK),
         block_2d("BC1", 4, 4, R8G8B8A8, SRGB)
-            .with_gl(GlFormat::COMPRESSED_SRGB_S3TC_DXT1_EXT)
+            .with_gl(GlFormat::COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT)
             .with_dxt10(Dxt10Format::DXGI_FORMAT_BC1_UNORM_SRGB)
             .with_vulkan(VkFormat::VK_FORMAT_BC1_RGBA_SRGB_BLOCK),
         // BC2
@@ -42,6 +42,83 @@ fn bc_formats() -> Vec<PixelFormat> {
         block_2d("BC2", 4, 4, R8G8B8A8, SRGB)
             .with_gl(GlFormat::COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT)
             .with_vulkan(VkFormat::VK_FORMAT_BC2_SRGB_BLOCK),
+        // BC3
+        block_2d("BC3", 4, 4, R8G8B8A8, UNORM)
+            .with_four_cc(b"DXT5")
+            .with_gl(GlFormat::COMPRESSED_RGBA_S3TC_DXT5_EXT)
+            .with_dxt10(Dxt10Format::DXGI_FORMAT_BC3_UNORM)
+            .with_vulkan(VkFormat::VK_FORMAT_BC3_UNORM_BLOCK),
+        block_2d("BC3", 4, 4, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT)
+            .with_dxt10(Dxt10Format::DXGI_FORMAT_BC3_UNORM_SRGB)
+            .with_vulkan(VkFormat::VK_FORMAT_BC3_SRGB_BLOCK),
+        // BC4
+        block_2d("BC4", 4, 4, R8, UNORM)
+            .with_four_cc(b"BC4U")
+            .with_gl(GlFormat::COMPRESSED_RED_RGTC1)
+            .with_dxt10(Dxt10Format::DXGI_FORMAT_BC4_UNORM)
+            .with_vulkan(VkFormat::VK_FORMAT_BC4_UNORM_BLOCK),
+        block_2d("BC4", 4, 4, R8, SNORM)
+            .with_four_cc(b"BC4S")
+            .with_gl(GlFormat::COMPRESSED_SIGNED_RED_RGTC1)
+            .with_dxt10(Dxt10Format::DXGI_FORMAT_BC4_SNORM)
+            .with_vulkan(VkFormat::VK_FORMAT_BC4_SNORM_BLOCK),
+        // BC5
+        block_2d("BC5", 4, 4, R8G8, UNORM)
+            .with_four_cc(b"ATI2")
+            .with_gl(GlFormat::COMPRESSED_RG_RGTC2)
+            .with_dxt10(Dxt10Format::DXGI_FORMAT_BC5_UNORM)
+            .with_vulkan(VkFormat::VK_FORMAT_BC5_UNORM_BLOCK),
+        block_2d("BC5", 4, 4, R8G8, SNORM)
+            .with_four_cc(b"BC5S")
+            .with_gl(GlFormat::COMPRESSED_SIGNED_RG_RGTC2)
+            .with_dxt10(Dxt10Format::DXGI_FORMAT_BC5_SNORM)
+            .with_vulkan(VkFormat::VK_FORMAT_BC5_SNORM_BLOCK),
+        // BC6H
+        block_2d("BC6H", 4, 4, R16G16B16, UFLOAT)
+            .with_gl(GlFormat::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT_ARB)
+            .with_dxt10(Dxt10Format::DXGI_FORMAT_BC6H_UF16)
+            .with_vulkan(VkFormat::VK_FORMAT_BC6H_UFLOAT_BLOCK),
+        block_2d("BC6H", 4, 4, R16G16B16, SFLOAT)
+            .with_gl(GlFormat::COMPRESSED_RGB_BPTC_SIGNED_FLOAT_ARB)
+            .with_dxt10(Dxt10Format::DXGI_FORMAT_BC6H_SF16)
+            .with_vulkan(VkFormat::VK_FORMAT_BC6H_SFLOAT_BLOCK),
+        // BC7
+        block_2d("BC7", 4, 4, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_BPTC_UNORM_ARB)
+            .with_dxt10(Dxt10Format::DXGI_FORMAT_BC7_UNORM)
+            .with_vulkan(VkFormat::VK_FORMAT_BC7_UNORM_BLOCK),
+        block_2d("BC7", 4, 4, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB_ALPHA_BPTC_UNORM_ARB)
+            .with_dxt10(Dxt10Format::DXGI_FORMAT_BC7_UNORM_SRGB)
+            .with_vulkan(VkFormat::VK_FORMAT_BC7_SRGB_BLOCK),
+    ]
+}
+
+fn etc_formats() -> Vec<PixelFormat> {
+    vec![
+        // ETC1
+        block_2d("ETC1", 4, 4, R8G8B8, UNORM).with_gl(GlFormat::ETC1_RGB8_OES),
+        // ETC2
+        block_2d("ETC2", 4, 4, R8G8B8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGB8_ETC2)
+            .with_vulkan(VkFormat::VK_FORMAT_ETC2_R8G8B8_UNORM_BLOCK),
+        block_2d("ETC2", 4, 4, R8G8B8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ETC2)
+            .with_vulkan(VkFormat::VK_FORMAT_ETC2_R8G8B8_SRGB_BLOCK),
+        block_2d("ETC2", 4, 4, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA8_ETC2_EAC)
+            .with_vulkan(VkFormat::VK_FORMAT_ETC2_R8G8B8A8_UNORM_BLOCK),
+        block_2d("ETC2", 4, 4, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC)
+            .with_vulkan(VkFormat::VK_FORMAT_ETC2_R8G8B8A8_SRGB_BLOCK),
+        // ETC2 with punch-through alpha
+        block_2d("ETC2_A1", 4, 4, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGB8_PUNCHTHROUGH_ALPHA1_ETC2)
+            .with_vulkan(VkFormat::VK_FORMAT_ETC2_R8G8B8A1_UNORM_BLOCK),
+        block_2d("ETC2_A1", 4, 4, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_PUNCHTHROUGH_ALPHA1_ETC2)
+            .with_vulkan(VkFormat::VK_FORMAT_ETC2_R8G8B8A1_SRGB_BLOCK),
     ]
 }
 
@@ -64,6 +141,96 @@ fn eac_formats() -> Vec<PixelFormat> {
     ]
 }
 
+// LDR only; the footprint alone sets the bit rate, from 8 bpp at 4x4 down to 0.89 at 12x12
+fn astc_formats() -> Vec<PixelFormat> {
+    vec![
+        block_2d("ASTC", 4, 4, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_4x4_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_4x4_UNORM_BLOCK),
+        block_2d("ASTC", 4, 4, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_4x4_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_4x4_SRGB_BLOCK),
+        block_2d("ASTC", 5, 4, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_5x4_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_5x4_UNORM_BLOCK),
+        block_2d("ASTC", 5, 4, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_5x4_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_5x4_SRGB_BLOCK),
+        block_2d("ASTC", 5, 5, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_5x5_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_5x5_UNORM_BLOCK),
+        block_2d("ASTC", 5, 5, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_5x5_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_5x5_SRGB_BLOCK),
+        block_2d("ASTC", 6, 5, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_6x5_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_6x5_UNORM_BLOCK),
+        block_2d("ASTC", 6, 5, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_6x5_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_6x5_SRGB_BLOCK),
+        block_2d("ASTC", 6, 6, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_6x6_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_6x6_UNORM_BLOCK),
+        block_2d("ASTC", 6, 6, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_6x6_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_6x6_SRGB_BLOCK),
+        block_2d("ASTC", 8, 5, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_8x5_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_8x5_UNORM_BLOCK),
+        block_2d("ASTC", 8, 5, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_8x5_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_8x5_SRGB_BLOCK),
+        block_2d("ASTC", 8, 6, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_8x6_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_8x6_UNORM_BLOCK),
+        block_2d("ASTC", 8, 6, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_8x6_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_8x6_SRGB_BLOCK),
+        block_2d("ASTC", 8, 8, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_8x8_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_8x8_UNORM_BLOCK),
+        block_2d("ASTC", 8, 8, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_8x8_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_8x8_SRGB_BLOCK),
+        block_2d("ASTC", 10, 5, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_10x5_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_10x5_UNORM_BLOCK),
+        block_2d("ASTC", 10, 5, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_10x5_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_10x5_SRGB_BLOCK),
+        block_2d("ASTC", 10, 6, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_10x6_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_10x6_UNORM_BLOCK),
+        block_2d("ASTC", 10, 6, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_10x6_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_10x6_SRGB_BLOCK),
+        block_2d("ASTC", 10, 8, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_10x8_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_10x8_UNORM_BLOCK),
+        block_2d("ASTC", 10, 8, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_10x8_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_10x8_SRGB_BLOCK),
+        block_2d("ASTC", 10, 10, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_10x10_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_10x10_UNORM_BLOCK),
+        block_2d("ASTC", 10, 10, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_10x10_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_10x10_SRGB_BLOCK),
+        block_2d("ASTC", 12, 10, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_12x10_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_12x10_UNORM_BLOCK),
+        block_2d("ASTC", 12, 10, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_12x10_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_12x10_SRGB_BLOCK),
+        block_2d("ASTC", 12, 12, R8G8B8A8, UNORM)
+            .with_gl(GlFormat::COMPRESSED_RGBA_ASTC_12x12_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_12x12_UNORM_BLOCK),
+        block_2d("ASTC", 12, 12, R8G8B8A8, SRGB)
+            .with_gl(GlFormat::COMPRESSED_SRGB8_ALPHA8_ASTC_12x12_KHR)
+            .with_vulkan(VkFormat::VK_FORMAT_ASTC_12x12_SRGB_BLOCK),
+    ]
+}
+
 fn block_2d(
     tag: &'static str,
     block_x: u32,
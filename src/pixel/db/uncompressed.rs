@@ -34,10 +34,6 @@ fn vulkan_only_formats() -> Vec<PixelFormat> {
         uncompressed(A2B10G10R10, SNORM).with_vulkan(VkFormat::VK_FORMAT_A2B10G10R10_SNORM_PACK32),
         uncompressed(A2B10G10R10, SINT).with_vulkan(VkFormat::VK_FORMAT_A2B10G10R10_SINT_PACK32),
         uncompressed(R64, UNORM).with_vulkan(VkFormat::VK_FORMAT_R4G4_UNORM_PACK8),
-        uncompressed(B4G4R4A4, UNORM).with_vulkan(VkFormat::VK_FORMAT_B4G4R4A4_UNORM_PACK16),
-        uncompressed(B5G6R5, UNORM).with_vulkan(VkFormat::VK_FORMAT_B5G6R5_UNORM_PACK16),
-        uncompressed(B5G5R5A1, UNORM).with_vulkan(VkFormat::VK_FORMAT_B5G5R5A1_UNORM_PACK16),
-        uncompressed(A1R5G5B5, UNORM).with_vulkan(VkFormat::VK_FORMAT_A1R5G5B5_UNORM_PACK16),
         uncompressed(R64, UINT).with_vulkan(VkFormat::VK_FORMAT_R64_UINT),
         uncompressed(R64, SINT).with_vulkan(VkFormat::VK_FORMAT_R64_SINT),
         uncompressed(R64, SFLOAT).with_vulkan(VkFormat::VK_FORMAT_R64_SFLOAT),
@@ -59,22 +55,35 @@ fn gl_supported_formats() -> Vec<PixelFormat> {
         // OpenGL ES 2
         uncompressed(A8, UNORM).with_dxt10(Dxt10Format::DXGI_FORMAT_A8_UNORM),
         // OpenGL ES 3
-        uncompressed(R8, UNORM)
+        uncompressed(R8, SRGB)
             .with_gl(GlFormat::SR8)
             .with_vulkan(VkFormat::VK_FORMAT_R8_SRGB),
-        uncompressed(R8G8, UNORM)
+        uncompressed(R8G8, SRGB)
             .with_gl(GlFormat::SRG8)
             .with_vulkan(VkFormat::VK_FORMAT_R8G8_SRGB),
         // OpenGL 4
         uncompressed(R4G4B4A4, UNORM)
             .with_gl(GlFormat::RGBA4)
             .with_vulkan(VkFormat::VK_FORMAT_R4G4B4A4_UNORM_PACK16),
+        // the reversed orders share an internal format, and differ in format and type
+        uncompressed(B4G4R4A4, UNORM)
+            .with_gl(GlFormat::RGBA4)
+            .with_vulkan(VkFormat::VK_FORMAT_B4G4R4A4_UNORM_PACK16),
         uncompressed(R5G6B5, UNORM)
             .with_gl(GlFormat::RGB565)
             .with_vulkan(VkFormat::VK_FORMAT_R5G6B5_UNORM_PACK16),
+        uncompressed(B5G6R5, UNORM)
+            .with_gl(GlFormat::RGB565)
+            .with_vulkan(VkFormat::VK_FORMAT_B5G6R5_UNORM_PACK16),
         uncompressed(R5G5B5A1, UNORM)
             .with_gl(GlFormat::RGB5_A1)
             .with_vulkan(VkFormat::VK_FORMAT_R5G5B5A1_UNORM_PACK16),
+        uncompressed(B5G5R5A1, UNORM)
+            .with_gl(GlFormat::RGB5_A1)
+            .with_vulkan(VkFormat::VK_FORMAT_B5G5R5A1_UNORM_PACK16),
+        uncompressed(A1R5G5B5, UNORM)
+            .with_gl(GlFormat::RGB5_A1)
+            .with_vulkan(VkFormat::VK_FORMAT_A1R5G5B5_UNORM_PACK16),
         uncompressed(R8, UNORM)
             .with_gl(GlFormat::R8)
             .with_vulkan(VkFormat::VK_FORMAT_R8_UNORM),
@@ -112,7 +121,7 @@ fn gl_supported_formats() -> Vec<PixelFormat> {
             .with_gl(GlFormat::RGB8I)
             .with_vulkan(VkFormat::VK_FORMAT_R8G8B8_SINT),
         uncompressed(R8G8B8, SRGB)
-            .with_gl(GlFormat::RGB8)
+            .with_gl(GlFormat::SRGB8)
             .with_vulkan(VkFormat::VK_FORMAT_R8G8B8_SRGB),
         uncompressed(B8G8R8, UNORM)
             .with_gl(GlFormat::RGB8)
@@ -127,7 +136,7 @@ fn gl_supported_formats() -> Vec<PixelFormat> {
             .with_gl(GlFormat::RGB8I)
             .with_vulkan(VkFormat::VK_FORMAT_B8G8R8_SINT),
         uncompressed(B8G8R8, SRGB)
-            .with_gl(GlFormat::RGB8)
+            .with_gl(GlFormat::SRGB8)
             .with_vulkan(VkFormat::VK_FORMAT_B8G8R8_SRGB),
         uncompressed(R8G8B8A8, UNORM)
             .with_gl(GlFormat::RGBA8)
@@ -142,7 +151,7 @@ fn gl_supported_formats() -> Vec<PixelFormat> {
             .with_gl(GlFormat::RGBA8I)
             .with_vulkan(VkFormat::VK_FORMAT_R8G8B8A8_SINT),
         uncompressed(R8G8B8A8, SRGB)
-            .with_gl(GlFormat::RGBA8)
+            .with_gl(GlFormat::SRGB8_ALPHA8)
             .with_vulkan(VkFormat::VK_FORMAT_R8G8B8A8_SRGB),
         uncompressed(B8G8R8A8, UNORM)
             .with_gl(GlFormat::RGBA8)
@@ -157,7 +166,7 @@ fn gl_supported_formats() -> Vec<PixelFormat> {
             .with_gl(GlFormat::RGBA8I)
             .with_vulkan(VkFormat::VK_FORMAT_B8G8R8A8_SINT),
         uncompressed(B8G8R8A8, SRGB)
-            .with_gl(GlFormat::RGBA8)
+            .with_gl(GlFormat::SRGB8_ALPHA8)
             .with_vulkan(VkFormat::VK_FORMAT_B8G8R8A8_SRGB),
         uncompressed(A8B8G8R8, UNORM)
             .with_gl(GlFormat::RGBA8)
@@ -172,7 +181,7 @@ fn gl_supported_formats() -> Vec<PixelFormat> {
             .with_gl(GlFormat::RGBA8I)
             .with_vulkan(VkFormat::VK_FORMAT_A8B8G8R8_SINT_PACK32),
         uncompressed(A8B8G8R8, SRGB)
-            .with_gl(GlFormat::RGBA8)
+            .with_gl(GlFormat::SRGB8_ALPHA8)
             .with_vulkan(VkFormat::VK_FORMAT_A8B8G8R8_SRGB_PACK32),
         uncompressed(R16, UNORM)
             .with_gl(GlFormat::R16)
@@ -301,7 +310,7 @@ fn gl_supported_formats() -> Vec<PixelFormat> {
             .with_gl(GlFormat::DEPTH24_STENCIL8)
             .with_vulkan(VkFormat::VK_FORMAT_D24_UNORM_S8_UINT),
         uncompressed(D32, SFLOAT)
-            .with_gl(GlFormat::DEPTH32F_STENCIL8)
+            .with_gl(GlFormat::DEPTH_COMPONENT32F)
             .with_vulkan(VkFormat::VK_FORMAT_D32_SFLOAT),
         uncompressed(D32S8, SPECIAL)
             .with_gl(GlFormat::DEPTH32F_STENCIL8)
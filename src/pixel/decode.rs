@@ -0,0 +1,211 @@
+// Reads uncompressed texels into canonical RGBA, whatever their layout and content.
+// Missing colour channels read as zero and missing alpha as one. sRGB texels stay in their
+// encoded form; decoding reads storage, and linearising is a colour-space conversion.
+
+use crate::pixel::float::{f10_to_f32, f11_to_f32, f16_to_f32, rgb9e5_to_f32};
+use crate::pixel::layout::{self, Field, Layout};
+use crate::pixel::CompContent::*;
+use crate::pixel::{CompContent, CompLayout, PixelFormat};
+use crate::RuxResult;
+
+// Normalised formats decode into [0, 1] or [-1, 1], integer formats into their integer
+// values and float formats into their floats.
+pub fn decode(format: &PixelFormat, blob: &[u8]) -> RuxResult<Vec<[f32; 4]>> {
+    Ok(decode_f64(format, blob)?
+        .iter()
+        .map(|t| [t[0] as f32, t[1] as f32, t[2] as f32, t[3] as f32])
+        .collect())
+}
+
+// As decode, but keeping the precision of 32-bit integer and 64-bit float components.
+pub fn decode_f64(format: &PixelFormat, blob: &[u8]) -> RuxResult<Vec<[f64; 4]>> {
+    let layout = texel_layout(format, blob)?;
+    let mut texels = Vec::with_capacity(blob.len() / layout.bytes);
+    for texel in blob.chunks_exact(layout.bytes) {
+        if format.comp_layout == CompLayout::E5B9G9R9 {
+            let rgb = rgb9e5_to_f32(u32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]]));
+            texels.push([f64::from(rgb[0]), f64::from(rgb[1]), f64::from(rgb[2]), 1.0]);
+            continue;
+        }
+        let mut values = [0.0, 0.0, 0.0, 1.0];
+        for field in &layout.fields {
+            let raw = layout::read(texel, field);
+            values[field.channel] = match content(format, field) {
+                UNORM | SRGB => raw as f64 / field.max_unsigned() as f64,
+                SNORM => (field.signed(raw) as f64 / field.max_signed() as f64).max(-1.0),
+                UINT => raw as f64,
+                SINT => field.signed(raw) as f64,
+                SFLOAT => match field.bits {
+                    16 => f64::from(f16_to_f32(raw as u16)),
+                    32 => f64::from(f32::from_bits(raw as u32)),
+                    _ => f64::from_bits(raw),
+                },
+                UFLOAT => match field.bits {
+                    11 => f64::from(f11_to_f32(raw as u32)),
+                    _ => f64::from(f10_to_f32(raw as u32)),
+                },
+                _ => bail!(format!("Can't decode {} yet.", format)),
+            };
+        }
+        texels.push(values);
+    }
+    Ok(texels)
+}
+
+// The exact values of integer formats. Unsigned 64-bit values beyond i64::MAX saturate.
+pub fn decode_i64(format: &PixelFormat, blob: &[u8]) -> RuxResult<Vec<[i64; 4]>> {
+    let layout = texel_layout(format, blob)?;
+    let mut texels = Vec::with_capacity(blob.len() / layout.bytes);
+    for texel in blob.chunks_exact(layout.bytes) {
+        let mut values = [0, 0, 0, 1];
+        for field in &layout.fields {
+            let raw = layout::read(texel, field);
+            values[field.channel] = match content(format, field) {
+                UINT => raw.min(i64::MAX as u64) as i64,
+                SINT => field.signed(raw),
+                _ => bail!(format!(
+                    "Can't decode {} as integers; it isn't an integer format.",
+                    format
+                )),
+            };
+        }
+        texels.push(values);
+    }
+    Ok(texels)
+}
+
+fn texel_layout(format: &PixelFormat, blob: &[u8]) -> RuxResult<Layout> {
+    if format.is_compressed() {
+        bail!(format!(
+            "Can't decode compressed format {} as texels.",
+            format
+        ));
+    }
+    let layout = match layout::describe(format.comp_layout) {
+        Some(layout) => layout,
+        None => bail!(format!("Can't decode {} yet.", format)),
+    };
+    if blob.len() % layout.bytes != 0 {
+        bail!(format!(
+            "Image data of {} bytes isn't a whole number of {}-byte {} texels.",
+            blob.len(),
+            layout.bytes,
+            format
+        ));
+    }
+    Ok(layout)
+}
+
+fn content(format: &PixelFormat, field: &Field) -> CompContent {
+    field.content.unwrap_or(format.comp_content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::format_for_test;
+
+    // each channel of a packed format, alone at its maximum, as the little-endian texel
+    // the Vulkan spec lays out
+    const PACKED: [(&str, u32, [u32; 4]); 12] = [
+        ("R4G4B4A4_UNORM", 2, [0xf000, 0x0f00, 0x00f0, 0x000f]),
+        ("B4G4R4A4_UNORM", 2, [0x00f0, 0x0f00, 0xf000, 0x000f]),
+        ("R5G6B5_UNORM", 2, [0xf800, 0x07e0, 0x001f, 0]),
+        ("B5G6R5_UNORM", 2, [0x001f, 0x07e0, 0xf800, 0]),
+        ("R5G5B5A1_UNORM", 2, [0xf800, 0x07c0, 0x003e, 0x0001]),
+        ("B5G5R5A1_UNORM", 2, [0x003e, 0x07c0, 0xf800, 0x0001]),
+        ("A1R5G5B5_UNORM", 2, [0x7c00, 0x03e0, 0x001f, 0x8000]),
+        (
+            "A2R10G10B10_UNORM",
+            4,
+            [0x3ff0_0000, 0x000f_fc00, 0x0000_03ff, 0xc000_0000],
+        ),
+        (
+            "A2B10G10R10_UNORM",
+            4,
+            [0x0000_03ff, 0x000f_fc00, 0x3ff0_0000, 0xc000_0000],
+        ),
+        (
+            "A8B8G8R8_UNORM",
+            4,
+            [0x0000_00ff, 0x0000_ff00, 0x00ff_0000, 0xff00_0000],
+        ),
+        (
+            "R8G8B8A8_UNORM",
+            4,
+            [0x0000_00ff, 0x0000_ff00, 0x00ff_0000, 0xff00_0000],
+        ),
+        (
+            "B8G8R8A8_UNORM",
+            4,
+            [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000],
+        ),
+    ];
+
+    #[test]
+    fn packed_bit_order() {
+        for (name, bytes, masks) in PACKED.iter() {
+            for (channel, &mask) in masks.iter().enumerate() {
+                if mask == 0 {
+                    continue;
+                }
+                let blob = &mask.to_le_bytes()[..*bytes as usize];
+                let texel = decode_f64(format_for_test(name), blob).unwrap()[0];
+                let mut expected = [0.0; 4];
+                expected[channel] = 1.0;
+                // formats without alpha read it as one
+                if masks[3] == 0 {
+                    expected[3] = 1.0;
+                }
+                assert_eq!(texel, expected, "{} channel {}", name, channel);
+            }
+        }
+    }
+
+    #[test]
+    fn packed_floats() {
+        // 1.0 is 0x3c0 in 11 bits and 0x1e0 in 10, with B10G11R11 packing R lowest
+        let texel = decode_f64(
+            format_for_test("B10G11R11_UFLOAT"),
+            &(0x3c0u32).to_le_bytes(),
+        )
+        .unwrap();
+        assert_eq!(texel[0], [1.0, 0.0, 0.0, 1.0]);
+        let texel = decode_f64(
+            format_for_test("B10G11R11_UFLOAT"),
+            &(0x3c0u32 << 11).to_le_bytes(),
+        );
+        assert_eq!(texel.unwrap()[0], [0.0, 1.0, 0.0, 1.0]);
+        let texel = decode_f64(
+            format_for_test("B10G11R11_UFLOAT"),
+            &(0x1e0u32 << 22).to_le_bytes(),
+        );
+        assert_eq!(texel.unwrap()[0], [0.0, 0.0, 1.0, 1.0]);
+        // the shared exponent sits above B, G and R's mantissas
+        let bits: u32 = 16 << 27 | 64 << 18 | 128 << 9 | 256;
+        let texel = decode_f64(format_for_test("E5B9G9R9_UFLOAT"), &bits.to_le_bytes()).unwrap();
+        assert_eq!(texel[0], [1.0, 0.5, 0.25, 1.0]);
+    }
+
+    #[test]
+    fn signed_and_integer_fields() {
+        // the most negative SNORM value clamps to -1, like the one above it
+        let texel = decode_f64(format_for_test("R8G8_SNORM"), &[0x80, 0x81]).unwrap();
+        assert_eq!(texel[0], [-1.0, -1.0, 0.0, 1.0]);
+        let texel = decode_i64(
+            format_for_test("A2B10G10R10_SINT"),
+            &(0x0000_03ffu32).to_le_bytes(),
+        );
+        assert_eq!(texel.unwrap()[0], [-1, 0, 0, 0]);
+        let texel = decode_i64(
+            format_for_test("A2R10G10B10_UINT"),
+            &(0xc000_0001u32).to_le_bytes(),
+        );
+        assert_eq!(texel.unwrap()[0], [0, 0, 1, 3]);
+    }
+
+    #[test]
+    fn rejects_partial_texels() {
+        assert!(decode_f64(format_for_test("R8G8B8A8_UNORM"), &[0; 6]).is_err());
+    }
+}
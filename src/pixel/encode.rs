@@ -0,0 +1,190 @@
+// Writes canonical RGBA texels into any uncompressed layout and content, the mirror of
+// decode. Normalised values round to nearest, and out-of-range values clamp to what the
+// target can hold.
+
+use crate::pixel::float::{f32_to_f10, f32_to_f11, f32_to_f16, f32_to_rgb9e5};
+use crate::pixel::layout::{self, Layout};
+use crate::pixel::CompContent::*;
+use crate::pixel::{CompLayout, PixelFormat};
+use crate::RuxResult;
+
+pub fn encode(format: &PixelFormat, texels: &[[f32; 4]]) -> RuxResult<Vec<u8>> {
+    let texels: Vec<[f64; 4]> = texels
+        .iter()
+        .map(|t| {
+            [
+                f64::from(t[0]),
+                f64::from(t[1]),
+                f64::from(t[2]),
+                f64::from(t[3]),
+            ]
+        })
+        .collect();
+    encode_f64(format, &texels)
+}
+
+pub fn encode_f64(format: &PixelFormat, texels: &[[f64; 4]]) -> RuxResult<Vec<u8>> {
+    let layout = texel_layout(format)?;
+    let mut blob = vec![0u8; texels.len() * layout.bytes];
+    for (texel, bytes) in texels.iter().zip(blob.chunks_exact_mut(layout.bytes)) {
+        if format.comp_layout == CompLayout::E5B9G9R9 {
+            let rgb = [texel[0] as f32, texel[1] as f32, texel[2] as f32];
+            bytes.copy_from_slice(&f32_to_rgb9e5(rgb).to_le_bytes());
+            continue;
+        }
+        for field in &layout.fields {
+            let value = texel[field.channel];
+            let raw = match field.content.unwrap_or(format.comp_content) {
+                UNORM | SRGB => {
+                    (value.clamp(0.0, 1.0) * field.max_unsigned() as f64).round() as u64
+                }
+                SNORM => {
+                    let scaled = (value.clamp(-1.0, 1.0) * field.max_signed() as f64).round();
+                    scaled as i64 as u64
+                }
+                // float to integer casts saturate, and NaN becomes zero
+                UINT => value.round().min(field.max_unsigned() as f64) as u64,
+                SINT => {
+                    let max = field.max_signed() as f64;
+                    value.round().clamp(-max - 1.0, max) as i64 as u64
+                }
+                SFLOAT => match field.bits {
+                    16 => u64::from(f32_to_f16(value as f32)),
+                    32 => u64::from((value as f32).to_bits()),
+                    _ => value.to_bits(),
+                },
+                UFLOAT => match field.bits {
+                    11 => u64::from(f32_to_f11(value as f32)),
+                    _ => u64::from(f32_to_f10(value as f32)),
+                },
+                _ => bail!(format!("Can't encode {} yet.", format)),
+            };
+            layout::write(bytes, field, raw);
+        }
+    }
+    Ok(blob)
+}
+
+// Integer formats from exact values, saturating at the limits of each component.
+pub fn encode_i64(format: &PixelFormat, texels: &[[i64; 4]]) -> RuxResult<Vec<u8>> {
+    let layout = texel_layout(format)?;
+    let mut blob = vec![0u8; texels.len() * layout.bytes];
+    for (texel, bytes) in texels.iter().zip(blob.chunks_exact_mut(layout.bytes)) {
+        for field in &layout.fields {
+            let value = texel[field.channel];
+            let raw = match field.content.unwrap_or(format.comp_content) {
+                UINT if field.bits == 64 => value.max(0) as u64,
+                UINT => value.clamp(0, field.max_unsigned() as i64) as u64,
+                SINT => {
+                    let max = field.max_signed();
+                    value.clamp(-max - 1, max) as u64
+                }
+                _ => bail!(format!(
+                    "Can't encode integers into {}; it isn't an integer format.",
+                    format
+                )),
+            };
+            layout::write(bytes, field, raw);
+        }
+    }
+    Ok(blob)
+}
+
+fn texel_layout(format: &PixelFormat) -> RuxResult<Layout> {
+    if format.is_compressed() {
+        bail!(format!(
+            "Can't encode texels into compressed format {}; compress instead.",
+            format
+        ));
+    }
+    match layout::describe(format.comp_layout) {
+        Some(layout) => Ok(layout),
+        None => bail!(format!("Can't encode {} yet.", format)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::decode::decode_f64;
+    use crate::pixel::format_for_test;
+
+    fn encode_u32(name: &str, texel: [f64; 4]) -> u32 {
+        let blob = encode_f64(format_for_test(name), &[texel]).unwrap();
+        let mut bytes = [0; 4];
+        bytes[..blob.len()].copy_from_slice(&blob);
+        u32::from_le_bytes(bytes)
+    }
+
+    #[test]
+    fn packed_bit_order() {
+        let red = [1.0, 0.0, 0.0, 0.0];
+        let alpha = [0.0, 0.0, 0.0, 1.0];
+        assert_eq!(encode_u32("B5G6R5_UNORM", red), 0x001f);
+        assert_eq!(encode_u32("R5G6B5_UNORM", red), 0xf800);
+        assert_eq!(encode_u32("R5G5B5A1_UNORM", alpha), 0x0001);
+        assert_eq!(encode_u32("A1R5G5B5_UNORM", alpha), 0x8000);
+        assert_eq!(encode_u32("B4G4R4A4_UNORM", red), 0x00f0);
+        assert_eq!(encode_u32("A2B10G10R10_UNORM", red), 0x0000_03ff);
+        assert_eq!(encode_u32("A2R10G10B10_UNORM", red), 0x3ff0_0000);
+        assert_eq!(encode_u32("A2R10G10B10_UNORM", alpha), 0xc000_0000);
+        assert_eq!(encode_u32("A8B8G8R8_UNORM", alpha), 0xff00_0000);
+        assert_eq!(
+            encode_u32("B10G11R11_UFLOAT", [1.0, 1.0, 1.0, 1.0]),
+            0x781e_03c0
+        );
+        assert_eq!(
+            encode_u32("E5B9G9R9_UFLOAT", [1.0, 0.5, 0.25, 1.0]),
+            0x8101_0100
+        );
+    }
+
+    #[test]
+    fn normalised_rounding_and_clamping() {
+        let texels = [[0.5, 2.0, -1.0, 0.1]];
+        assert_eq!(
+            encode_f64(format_for_test("R8G8B8A8_UNORM"), &texels).unwrap(),
+            [128, 255, 0, 26]
+        );
+        assert_eq!(
+            encode_f64(format_for_test("R8G8B8A8_SNORM"), &texels).unwrap(),
+            [64, 127, 0x81, 13]
+        );
+        assert_eq!(
+            encode_u32("R5G6B5_UNORM", [0.5, 0.5, 0.5, 1.0]),
+            16 << 11 | 32 << 5 | 16
+        );
+    }
+
+    #[test]
+    fn integers_saturate() {
+        let texels = [[300, -5, 70000, -40000]];
+        let blob = encode_i64(format_for_test("R8G8B8A8_UINT"), &texels).unwrap();
+        assert_eq!(blob, [255, 0, 255, 0]);
+        let blob = encode_i64(format_for_test("R16G16B16A16_SINT"), &texels).unwrap();
+        let values: Vec<i16> = blob
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(values, [300, -5, 32767, -32768]);
+        assert!(encode_i64(format_for_test("R8G8B8A8_UNORM"), &texels).is_err());
+    }
+
+    #[test]
+    fn decodes_back() {
+        let texels = [[0.25, -0.5, 1.5, 1.0], [-2.0, 0.0, 65504.0, 0.5]];
+        for name in &[
+            "R16G16B16A16_SFLOAT",
+            "R32G32B32A32_SFLOAT",
+            "R64G64B64A64_SFLOAT",
+        ] {
+            let blob = encode_f64(format_for_test(name), &texels).unwrap();
+            assert_eq!(
+                decode_f64(format_for_test(name), &blob).unwrap(),
+                texels,
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,287 @@
+// Conversions between f32 and the small float encodings used by pixel formats: signed
+// halves, the unsigned 11- and 10-bit floats of B10G11R11, and RGB9E5's shared exponent.
+// They all have five exponent bits with a bias of 15, and all round to nearest, ties to
+// even.
+
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let value = small_to_f32(u32::from(bits & 0x7fff), 10);
+    if bits & 0x8000 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+// Values too large for a half become infinity, and NaNs stay NaN.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let sign = ((value.to_bits() >> 16) & 0x8000) as u16;
+    sign | f32_to_small(value.abs(), 10) as u16
+}
+
+pub fn f11_to_f32(bits: u32) -> f32 {
+    small_to_f32(bits & 0x7ff, 6)
+}
+
+pub fn f10_to_f32(bits: u32) -> f32 {
+    small_to_f32(bits & 0x3ff, 5)
+}
+
+// There is no sign bit, so negative values, negative infinity included, become zero.
+pub fn f32_to_f11(value: f32) -> u32 {
+    f32_to_small(unsigned(value), 6)
+}
+
+pub fn f32_to_f10(value: f32) -> u32 {
+    f32_to_small(unsigned(value), 5)
+}
+
+fn unsigned(value: f32) -> f32 {
+    if value.is_nan() {
+        value.abs()
+    } else if value.is_sign_negative() {
+        0.0
+    } else {
+        value
+    }
+}
+
+// the largest value RGB9E5 can hold: 511/512 * 2^16
+const RGB9E5_MAX: f32 = 65408.0;
+
+pub fn rgb9e5_to_f32(bits: u32) -> [f32; 3] {
+    let exponent = (bits >> 27) as i32;
+    // a 9-bit mantissa without an implicit one, so the scale loses 9 more bits
+    let scale = 2f32.powi(exponent - 15 - 9);
+    [
+        (bits & 0x1ff) as f32 * scale,
+        ((bits >> 9) & 0x1ff) as f32 * scale,
+        ((bits >> 18) & 0x1ff) as f32 * scale,
+    ]
+}
+
+// Follows EXT_texture_shared_exponent: components clamp to [0, RGB9E5_MAX] with NaN as
+// zero, and the shared exponent is the smallest one that still fits the largest component
+// once it's rounded.
+pub fn f32_to_rgb9e5(rgb: [f32; 3]) -> u32 {
+    let clamp = |c: f32| {
+        if c.is_nan() {
+            0.0
+        } else {
+            c.clamp(0.0, RGB9E5_MAX)
+        }
+    };
+    let rgb = [clamp(rgb[0]), clamp(rgb[1]), clamp(rgb[2])];
+    let largest = rgb[0].max(rgb[1]).max(rgb[2]);
+
+    // floor(log2(largest)), no lower than the bias allows, then biased
+    let log2 = ((largest.to_bits() >> 23) as i32 - 127).max(-16);
+    let mut exponent = log2 + 1 + 15;
+    if scale_round(largest, exponent - 15 - 9) == 512 {
+        exponent += 1;
+    }
+    let mantissas: Vec<u32> = rgb
+        .iter()
+        .map(|&c| scale_round(c, exponent - 15 - 9))
+        .collect();
+    mantissas[0] | mantissas[1] << 9 | mantissas[2] << 18 | (exponent as u32) << 27
+}
+
+// a non-negative value divided by 2^exponent, rounded to an integer
+fn scale_round(value: f32, exponent: i32) -> u32 {
+    let bits = value.to_bits();
+    let (biased, mantissa) = match (bits >> 23) as i32 {
+        0 => (1, bits & 0x7f_ffff),
+        biased => (biased, bits & 0x7f_ffff | 0x80_0000),
+    };
+    // value is mantissa * 2^(biased - 150)
+    let shift = exponent + 150 - biased;
+    if shift <= 0 {
+        mantissa << -shift
+    } else if shift > 31 {
+        0
+    } else {
+        round_shift(mantissa, shift as u32)
+    }
+}
+
+// decodes a non-negative float with five exponent bits and the given mantissa bits
+fn small_to_f32(bits: u32, mantissa_bits: u32) -> f32 {
+    let exponent = bits >> mantissa_bits;
+    let mantissa = bits & ((1 << mantissa_bits) - 1);
+    match exponent {
+        // zero or denormal, worth mantissa * 2^(-14 - mantissa_bits)
+        0 => mantissa as f32 * 2f32.powi(-14 - mantissa_bits as i32),
+        0x1f => f32::from_bits(0x7f80_0000 | mantissa << (23 - mantissa_bits)),
+        _ => f32::from_bits((exponent + 112) << 23 | mantissa << (23 - mantissa_bits)),
+    }
+}
+
+// encodes a non-negative (or NaN) f32 with five exponent bits and the given mantissa bits
+fn f32_to_small(value: f32, mantissa_bits: u32) -> u32 {
+    let bits = value.to_bits();
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    let infinity = 0x1f << mantissa_bits;
+
+    if exponent == 0xff {
+        // keep a quiet bit so that a NaN can't truncate into infinity
+        let nan = if mantissa != 0 {
+            1 << (mantissa_bits - 1) | mantissa >> (23 - mantissa_bits)
+        } else {
+            0
+        };
+        return infinity | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return infinity;
+    }
+    if exponent <= 0 {
+        if exponent < -(mantissa_bits as i32) {
+            return 0;
+        }
+        // denormal: shift the mantissa, with its implicit leading one, into place
+        let shift = (24 - mantissa_bits as i32 - exponent) as u32;
+        return round_shift(mantissa | 0x80_0000, shift);
+    }
+    // a carry out of the mantissa correctly bumps the exponent, up to infinity
+    round_shift((exponent as u32) << 23 | mantissa, 23 - mantissa_bits)
+}
+
+fn round_shift(value: u32, shift: u32) -> u32 {
+    let half = 1 << (shift - 1);
+    let remainder = value & ((1 << shift) - 1);
+    let result = value >> shift;
+    if remainder > half || (remainder == half && result & 1 == 1) {
+        result + 1
+    } else {
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f16_values() {
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f16_to_f32(0x3555), 0.333_251_95);
+        // denormals, down to the smallest, and the smallest normal
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(1023.0 * 2f32.powi(-24)), 0x03ff);
+        assert_eq!(f32_to_f16(2f32.powi(-14)), 0x0400);
+        assert_eq!(f16_to_f32(0x8001), -(2f32.powi(-24)));
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+    }
+
+    #[test]
+    fn f16_infinity_and_nan() {
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_f16(1e10), 0x7c00);
+        assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+        // a NaN whose payload lies below the half's mantissa mustn't become infinity
+        let nan = f32_to_f16(f32::from_bits(0x7f80_0001));
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x03ff, 0);
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+    }
+
+    #[test]
+    fn f16_ties_to_even() {
+        let ulp = 2f32.powi(-10);
+        assert_eq!(f32_to_f16(1.0 + ulp / 2.0), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + ulp * 1.5), 0x3c02);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.51), 0x3c01);
+        // halfway between the largest half and the next power of two rounds up, to infinity
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(65519.0), 0x7bff);
+        // and likewise among denormals, where the carry can reach the smallest normal
+        let denormal = 2f32.powi(-24);
+        assert_eq!(f32_to_f16(denormal / 2.0), 0x0000);
+        assert_eq!(f32_to_f16(denormal * 1.5), 0x0002);
+        assert_eq!(f32_to_f16(denormal * 1023.5), 0x0400);
+    }
+
+    #[test]
+    fn f16_round_trips() {
+        for bits in 0..=0xffffu16 {
+            if bits & 0x7c00 == 0x7c00 && bits & 0x03ff != 0 {
+                continue;
+            }
+            assert_eq!(f32_to_f16(f16_to_f32(bits)), bits, "{:#06x}", bits);
+        }
+    }
+
+    #[test]
+    fn f11_and_f10_values() {
+        assert_eq!(f32_to_f11(1.0), 0x3c0);
+        assert_eq!(f32_to_f10(1.0), 0x1e0);
+        assert_eq!(f11_to_f32(0x7bf), 65024.0);
+        assert_eq!(f10_to_f32(0x3df), 64512.0);
+        assert_eq!(f11_to_f32(0x001), 2f32.powi(-20));
+        assert_eq!(f10_to_f32(0x001), 2f32.powi(-19));
+        // no sign bit, so negatives clamp to zero
+        assert_eq!(f32_to_f11(-1.0), 0);
+        assert_eq!(f32_to_f10(f32::NEG_INFINITY), 0);
+        assert_eq!(f32_to_f11(f32::INFINITY), 0x7c0);
+        assert_eq!(f32_to_f10(1e10), 0x3e0);
+        assert!(f11_to_f32(f32_to_f11(f32::NAN)).is_nan());
+        assert!(f10_to_f32(f32_to_f10(-f32::NAN)).is_nan());
+        // ties to even, with six and five mantissa bits
+        assert_eq!(f32_to_f11(1.0 + 2f32.powi(-7)), 0x3c0);
+        assert_eq!(f32_to_f11(1.0 + 3.0 * 2f32.powi(-7)), 0x3c2);
+        assert_eq!(f32_to_f10(1.0 + 2f32.powi(-6)), 0x1e0);
+        assert_eq!(f32_to_f10(1.0 + 3.0 * 2f32.powi(-6)), 0x1e2);
+    }
+
+    #[test]
+    fn f11_and_f10_round_trip() {
+        for bits in 0..=0x7c0 {
+            assert_eq!(f32_to_f11(f11_to_f32(bits)), bits, "{:#05x}", bits);
+        }
+        for bits in 0..=0x3e0 {
+            assert_eq!(f32_to_f10(f10_to_f32(bits)), bits, "{:#05x}", bits);
+        }
+    }
+
+    #[test]
+    fn rgb9e5_values() {
+        assert_eq!(f32_to_rgb9e5([1.0, 0.5, 0.0]), 16 << 27 | 128 << 9 | 256);
+        assert_eq!(rgb9e5_to_f32(16 << 27 | 128 << 9 | 256), [1.0, 0.5, 0.0]);
+        // components clamp to the largest value, and NaN and negatives to zero
+        let clamped = rgb9e5_to_f32(f32_to_rgb9e5([f32::INFINITY, f32::NAN, -1.0]));
+        assert_eq!(clamped, [RGB9E5_MAX, 0.0, 0.0]);
+        assert_eq!(rgb9e5_to_f32(f32_to_rgb9e5([1e10, 0.0, 0.0]))[0], RGB9E5_MAX);
+        // the smallest values have the smallest exponent, without an implicit one
+        let tiny = 2f32.powi(-24);
+        assert_eq!(f32_to_rgb9e5([tiny, 0.0, 0.0]), 1);
+        assert_eq!(rgb9e5_to_f32(1), [tiny, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn rgb9e5_rounding() {
+        // 511.75 / 256 rounds up to 512 / 256, so the exponent grows to fit it
+        let bits = f32_to_rgb9e5([511.75 / 256.0, 0.0, 0.0]);
+        assert_eq!(bits, 17 << 27 | 256);
+        assert_eq!(rgb9e5_to_f32(bits), [2.0, 0.0, 0.0]);
+        // the smaller components share the largest's exponent, ties to even
+        let bits = f32_to_rgb9e5([1.0, 0.5 / 256.0, 1.5 / 256.0]);
+        assert_eq!(bits, 16 << 27 | 2 << 18 | 256);
+        // every encoding decodes and re-encodes to itself once the exponent is minimal
+        for exponent in 0..32 {
+            for mantissa in [1, 255, 256, 511] {
+                let bits = exponent << 27 | mantissa;
+                if mantissa < 256 && exponent > 0 {
+                    continue;
+                }
+                let rgb = rgb9e5_to_f32(bits);
+                assert_eq!(f32_to_rgb9e5(rgb), bits, "{:#010x}", bits);
+            }
+        }
+    }
+}
@@ -28,7 +28,8 @@ pub fn to_gl(layout: &CompLayout, content: &CompContent) -> Option<(GlBaseFormat
       CompContent::SFLOAT => f_type.and_then(|tt| Some((comp, tt))),
       CompContent::UINT => Some((comp_int, u_type)),
       CompContent::SINT => Some((comp_int, s_type)),
-      _ => panic!("Internal error: unexpected comp_content: {:?}", *content),
+      // scaled and other special contents have no plain OpenGL type
+      _ => None,
     }
   };
   match *layout {
@@ -123,6 +124,10 @@ pub enum GlFormat {
   COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT = 0x8C4F,
   COMPRESSED_LUMINANCE_LATC1_EXT = 0x8C70,
   COMPRESSED_LUMINANCE_ALPHA_LATC2_EXT = 0x8C72,
+  COMPRESSED_RED_RGTC1 = 0x8DBB,
+  COMPRESSED_SIGNED_RED_RGTC1 = 0x8DBC,
+  COMPRESSED_RG_RGTC2 = 0x8DBD,
+  COMPRESSED_SIGNED_RG_RGTC2 = 0x8DBE,
   COMPRESSED_RGBA_BPTC_UNORM_ARB = 0x8E8C,
   COMPRESSED_SRGB_ALPHA_BPTC_UNORM_ARB = 0x8E8D,
   COMPRESSED_RGB_BPTC_SIGNED_FLOAT_ARB = 0x8E8E,
@@ -135,17 +140,33 @@ pub enum GlFormat {
   ATC_RGBA_EXPLICIT_ALPHA_AMD = 0x8C93,
   ATC_RGBA_INTERPOLATED_ALPHA_AMD = 0x87EE,
   COMPRESSED_RGBA_ASTC_4x4_KHR = 0x93B0,
+  COMPRESSED_RGBA_ASTC_5x4_KHR = 0x93B1,
   COMPRESSED_RGBA_ASTC_5x5_KHR = 0x93B2,
+  COMPRESSED_RGBA_ASTC_6x5_KHR = 0x93B3,
   COMPRESSED_RGBA_ASTC_6x6_KHR = 0x93B4,
   COMPRESSED_RGBA_ASTC_8x5_KHR = 0x93B5,
   COMPRESSED_RGBA_ASTC_8x6_KHR = 0x93B6,
+  COMPRESSED_RGBA_ASTC_8x8_KHR = 0x93B7,
   COMPRESSED_RGBA_ASTC_10x5_KHR = 0x93B8,
+  COMPRESSED_RGBA_ASTC_10x6_KHR = 0x93B9,
+  COMPRESSED_RGBA_ASTC_10x8_KHR = 0x93BA,
+  COMPRESSED_RGBA_ASTC_10x10_KHR = 0x93BB,
+  COMPRESSED_RGBA_ASTC_12x10_KHR = 0x93BC,
+  COMPRESSED_RGBA_ASTC_12x12_KHR = 0x93BD,
   COMPRESSED_SRGB8_ALPHA8_ASTC_4x4_KHR = 0x93D0,
+  COMPRESSED_SRGB8_ALPHA8_ASTC_5x4_KHR = 0x93D1,
   COMPRESSED_SRGB8_ALPHA8_ASTC_5x5_KHR = 0x93D2,
+  COMPRESSED_SRGB8_ALPHA8_ASTC_6x5_KHR = 0x93D3,
   COMPRESSED_SRGB8_ALPHA8_ASTC_6x6_KHR = 0x93D4,
   COMPRESSED_SRGB8_ALPHA8_ASTC_8x5_KHR = 0x93D5,
   COMPRESSED_SRGB8_ALPHA8_ASTC_8x6_KHR = 0x93D6,
+  COMPRESSED_SRGB8_ALPHA8_ASTC_8x8_KHR = 0x93D7,
   COMPRESSED_SRGB8_ALPHA8_ASTC_10x5_KHR = 0x93D8,
+  COMPRESSED_SRGB8_ALPHA8_ASTC_10x6_KHR = 0x93D9,
+  COMPRESSED_SRGB8_ALPHA8_ASTC_10x8_KHR = 0x93DA,
+  COMPRESSED_SRGB8_ALPHA8_ASTC_10x10_KHR = 0x93DB,
+  COMPRESSED_SRGB8_ALPHA8_ASTC_12x10_KHR = 0x93DC,
+  COMPRESSED_SRGB8_ALPHA8_ASTC_12x12_KHR = 0x93DD,
 
   // OpenGL ES 2.0 had format = internal_format
   ALPHA = 0x1906,
@@ -0,0 +1,257 @@
+use crate::pixel::CompContent::{self, *};
+use crate::pixel::CompLayout::{self, *};
+
+// canonical channels that components decode into
+pub const R: usize = 0;
+pub const G: usize = 1;
+pub const B: usize = 2;
+pub const A: usize = 3;
+
+// Where one component of a texel lives: the channel it decodes into, and its bit offset
+// and width within the texel read as one little-endian integer. Depth and stencil carry
+// their own content, since their combined formats are SPECIAL.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Field {
+    pub channel: usize,
+    pub offset: u32,
+    pub bits: u32,
+    pub content: Option<CompContent>,
+}
+
+impl Field {
+    pub fn max_unsigned(&self) -> u64 {
+        if self.bits == 64 {
+            u64::MAX
+        } else {
+            (1 << self.bits) - 1
+        }
+    }
+
+    pub fn max_signed(&self) -> i64 {
+        (self.max_unsigned() >> 1) as i64
+    }
+
+    // sign-extends the field's two's-complement bits
+    pub fn signed(&self, raw: u64) -> i64 {
+        let shift = 64 - self.bits;
+        ((raw << shift) as i64) >> shift
+    }
+}
+
+pub struct Layout {
+    pub bytes: usize,
+    pub fields: Vec<Field>,
+}
+
+const fn field(channel: usize, offset: u32, bits: u32) -> Field {
+    Field {
+        channel,
+        offset,
+        bits,
+        content: None,
+    }
+}
+
+// depth decodes into R, and stencil into G when there is depth too
+const fn depth(offset: u32, bits: u32, content: CompContent) -> Field {
+    Field {
+        channel: R,
+        offset,
+        bits,
+        content: Some(content),
+    }
+}
+
+const fn stencil(offset: u32) -> Field {
+    Field {
+        channel: G,
+        offset,
+        bits: 8,
+        content: Some(UINT),
+    }
+}
+
+// Component order follows the Vulkan names: separate components sit in memory in name
+// order, while packed layouts name their fields from the most significant bit down.
+pub fn describe(comp_layout: CompLayout) -> Option<Layout> {
+    let (bytes, fields) = match comp_layout {
+        A8 => (1, vec![field(A, 0, 8)]),
+        R8 => (1, vec![field(R, 0, 8)]),
+        R16 => (2, vec![field(R, 0, 16)]),
+        R32 => (4, vec![field(R, 0, 32)]),
+        R64 => (8, vec![field(R, 0, 64)]),
+        R8G8 => (2, vec![field(R, 0, 8), field(G, 8, 8)]),
+        R16G16 => (4, vec![field(R, 0, 16), field(G, 16, 16)]),
+        R32G32 => (8, vec![field(R, 0, 32), field(G, 32, 32)]),
+        R64G64 => (16, vec![field(R, 0, 64), field(G, 64, 64)]),
+        R8G8B8 => (3, vec![field(R, 0, 8), field(G, 8, 8), field(B, 16, 8)]),
+        R16G16B16 => (6, vec![field(R, 0, 16), field(G, 16, 16), field(B, 32, 16)]),
+        R32G32B32 => (
+            12,
+            vec![field(R, 0, 32), field(G, 32, 32), field(B, 64, 32)],
+        ),
+        R64G64B64 => (
+            24,
+            vec![field(R, 0, 64), field(G, 64, 64), field(B, 128, 64)],
+        ),
+        B8G8R8 => (3, vec![field(B, 0, 8), field(G, 8, 8), field(R, 16, 8)]),
+        R8G8B8A8 => (
+            4,
+            vec![
+                field(R, 0, 8),
+                field(G, 8, 8),
+                field(B, 16, 8),
+                field(A, 24, 8),
+            ],
+        ),
+        R16G16B16A16 => (
+            8,
+            vec![
+                field(R, 0, 16),
+                field(G, 16, 16),
+                field(B, 32, 16),
+                field(A, 48, 16),
+            ],
+        ),
+        R32G32B32A32 => (
+            16,
+            vec![
+                field(R, 0, 32),
+                field(G, 32, 32),
+                field(B, 64, 32),
+                field(A, 96, 32),
+            ],
+        ),
+        R64G64B64A64 => (
+            32,
+            vec![
+                field(R, 0, 64),
+                field(G, 64, 64),
+                field(B, 128, 64),
+                field(A, 192, 64),
+            ],
+        ),
+        B8G8R8A8 => (
+            4,
+            vec![
+                field(B, 0, 8),
+                field(G, 8, 8),
+                field(R, 16, 8),
+                field(A, 24, 8),
+            ],
+        ),
+        // packed into 16 bits
+        R5G6B5 => (2, vec![field(R, 11, 5), field(G, 5, 6), field(B, 0, 5)]),
+        B5G6R5 => (2, vec![field(B, 11, 5), field(G, 5, 6), field(R, 0, 5)]),
+        R4G4B4A4 => (
+            2,
+            vec![
+                field(R, 12, 4),
+                field(G, 8, 4),
+                field(B, 4, 4),
+                field(A, 0, 4),
+            ],
+        ),
+        B4G4R4A4 => (
+            2,
+            vec![
+                field(B, 12, 4),
+                field(G, 8, 4),
+                field(R, 4, 4),
+                field(A, 0, 4),
+            ],
+        ),
+        R5G5B5A1 => (
+            2,
+            vec![
+                field(R, 11, 5),
+                field(G, 6, 5),
+                field(B, 1, 5),
+                field(A, 0, 1),
+            ],
+        ),
+        B5G5R5A1 => (
+            2,
+            vec![
+                field(B, 11, 5),
+                field(G, 6, 5),
+                field(R, 1, 5),
+                field(A, 0, 1),
+            ],
+        ),
+        A1R5G5B5 => (
+            2,
+            vec![
+                field(A, 15, 1),
+                field(R, 10, 5),
+                field(G, 5, 5),
+                field(B, 0, 5),
+            ],
+        ),
+        // packed into 32 bits
+        A2R10G10B10 => (
+            4,
+            vec![
+                field(A, 30, 2),
+                field(R, 20, 10),
+                field(G, 10, 10),
+                field(B, 0, 10),
+            ],
+        ),
+        A2B10G10R10 => (
+            4,
+            vec![
+                field(A, 30, 2),
+                field(B, 20, 10),
+                field(G, 10, 10),
+                field(R, 0, 10),
+            ],
+        ),
+        A8B8G8R8 => (
+            4,
+            vec![
+                field(A, 24, 8),
+                field(B, 16, 8),
+                field(G, 8, 8),
+                field(R, 0, 8),
+            ],
+        ),
+        B10G11R11 => (4, vec![field(B, 22, 10), field(G, 11, 11), field(R, 0, 11)]),
+        // depth and stencil, laid out as OpenGL packs them
+        S8 => (1, vec![field(R, 0, 8)]),
+        D16 => (2, vec![field(R, 0, 16)]),
+        D32 => (4, vec![field(R, 0, 32)]),
+        X8D24 => (4, vec![field(R, 0, 24)]),
+        D16S8 => (3, vec![depth(0, 16, UNORM), stencil(16)]),
+        D24S8 => (4, vec![depth(8, 24, UNORM), stencil(0)]),
+        D32S8 => (8, vec![depth(0, 32, SFLOAT), stencil(32)]),
+        // only ever block compressed
+        R11 | R11G11 => return None,
+        // the mantissas, with a shared exponent in the top five bits that decode and encode
+        // handle for the whole texel
+        E5B9G9R9 => (4, vec![field(R, 0, 9), field(G, 9, 9), field(B, 18, 9)]),
+    };
+    Some(Layout { bytes, fields })
+}
+
+// the raw bits of a field, from a texel's bytes
+pub fn read(texel: &[u8], field: &Field) -> u64 {
+    let first = (field.offset / 8) as usize;
+    let last = (field.offset + field.bits).div_ceil(8) as usize;
+    let mut value = 0u128;
+    for (i, &byte) in texel[first..last].iter().enumerate() {
+        value |= u128::from(byte) << (8 * i);
+    }
+    let value = value >> (field.offset % 8);
+    (value & ((1u128 << field.bits) - 1)) as u64
+}
+
+// sets a field's bits in a texel's bytes, which start out zeroed
+pub fn write(texel: &mut [u8], field: &Field, raw: u64) {
+    let first = (field.offset / 8) as usize;
+    let last = (field.offset + field.bits).div_ceil(8) as usize;
+    let value = (u128::from(raw) & ((1u128 << field.bits) - 1)) << (field.offset % 8);
+    for (i, byte) in texel[first..last].iter_mut().enumerate() {
+        *byte |= (value >> (8 * i)) as u8;
+    }
+}
@@ -7,6 +7,12 @@ use dxt10::Dxt10Format;
 pub mod vulkan;
 use vulkan::VkFormat;
 
+pub mod decode;
+pub mod encode;
+pub mod float;
+mod layout;
+pub mod srgb;
+
 mod db;
 
 #[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
@@ -98,10 +104,79 @@ impl PixelFormat {
             .collect()
     }
 
+    // Finds a format by name. Uncompressed formats go by layout and content, as in
+    // "B5G6R5_UNORM"; compressed ones by their Vulkan name without its prefix and suffix,
+    // as in "BC7_SRGB" or "ASTC_8x8_UNORM", or else by tag, layout and content, as in
+    // "ETC1_R8G8B8_UNORM".
+    pub fn for_name(name: &str) -> Option<&'static PixelFormat> {
+        db::PIXEL_FORMATS
+            .iter()
+            .find(|x| x.names().iter().any(|n| n.eq_ignore_ascii_case(name)))
+    }
+
+    fn names(&self) -> Vec<String> {
+        let layout_name = format!("{:?}_{:?}", self.comp_layout, self.comp_content);
+        if !self.is_compressed() {
+            return vec![layout_name];
+        }
+        let mut names = vec![format!("{}_{}", self.tag, layout_name)];
+        if let Some(vk_format) = self.vk_format {
+            let vk_name = format!("{:?}", vk_format);
+            let vk_name = vk_name.trim_start_matches("VK_FORMAT_");
+            names.push(vk_name.trim_end_matches("_BLOCK").to_string());
+        }
+        names
+    }
+
     pub fn is_compressed(&self) -> bool {
         self.block_dim.is_some()
     }
 
+    // bytes per texel of an uncompressed format, as decode and encode lay them out
+    pub fn texel_bytes(&self) -> Option<usize> {
+        if self.is_compressed() {
+            return None;
+        }
+        layout::describe(self.comp_layout).map(|layout| layout.bytes)
+    }
+
+    pub fn is_srgb(&self) -> bool {
+        self.comp_content == CompContent::SRGB
+    }
+
+    // The same format labelled with the other colour space, for flipping UNORM and SRGB
+    // without touching the data.
+    pub fn in_colour_space(&self, srgb: bool) -> Option<&'static PixelFormat> {
+        let (from, to) = if srgb {
+            (CompContent::UNORM, CompContent::SRGB)
+        } else {
+            (CompContent::SRGB, CompContent::UNORM)
+        };
+        if self.comp_content != from && self.comp_content != to {
+            return None;
+        }
+        db::PIXEL_FORMATS.iter().find(|x| {
+            x.tag == self.tag
+                && x.block_dim == self.block_dim
+                && x.comp_layout == self.comp_layout
+                && x.comp_content == to
+        })
+    }
+
+    pub fn has_depth(&self) -> bool {
+        use CompLayout::*;
+        matches!(self.comp_layout, D16 | D32 | X8D24 | D16S8 | D24S8 | D32S8)
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        use CompLayout::*;
+        matches!(self.comp_layout, S8 | D16S8 | D24S8 | D32S8)
+    }
+
+    pub fn block_extent(&self) -> (u32, u32) {
+        self.block_dim.map_or((1, 1), |Dimensions(x, y, _)| (x, y))
+    }
+
     pub fn with_gl(&self, gl_format: GlFormat) -> PixelFormat {
         PixelFormat {
             gl_format: Some(gl_format),
@@ -130,3 +205,12 @@ impl PixelFormat {
         }
     }
 }
+
+// looks a format up by name for tests, naming it when it's missing
+#[cfg(test)]
+pub(crate) fn format_for_test(name: &str) -> &'static PixelFormat {
+    match PixelFormat::for_name(name) {
+        Some(format) => format,
+        None => panic!("No format {}", name),
+    }
+}
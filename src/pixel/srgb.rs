@@ -0,0 +1,33 @@
+// The sRGB transfer function, exactly as IEC 61966-2-1 defines it rather than as a 2.2
+// power. Alpha is always linear, so only colour channels go through it.
+
+pub fn to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn from_linear(value: f64) -> f64 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Moves the colour channels of texels decoded from one format into the colour space of
+// another, if they differ.
+pub fn transfer(texels: &mut [[f64; 4]], from_srgb: bool, to_srgb: bool) {
+    let function: fn(f64) -> f64 = match (from_srgb, to_srgb) {
+        (true, false) => to_linear,
+        (false, true) => |value| from_linear(value.clamp(0.0, 1.0)),
+        _ => return,
+    };
+    for texel in texels {
+        for value in &mut texel[..3] {
+            *value = function(*value);
+        }
+    }
+}
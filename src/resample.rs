@@ -0,0 +1,231 @@
+// Separable resampling of decoded texels. Each output texel is a normalised weighted sum
+// of the source texels around its centre; when shrinking, the kernel stretches with the
+// scale so that every source texel contributes, whatever the ratio of the extents.
+
+use std::f64::consts::PI;
+
+use crate::compress::{compress_texels, decompress_texels, unit_colour, CompressOptions, Texel};
+use crate::pixel::decode::decode_f64;
+use crate::pixel::encode::encode_f64;
+use crate::pixel::CompContent::*;
+use crate::pixel::PixelFormat;
+use crate::{Dimensions, ImageBlob, RuxResult};
+
+clap::arg_enum! {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Filter {
+    Box,
+    Triangle,
+    Kaiser,
+    Lanczos,
+}
+}
+
+clap::arg_enum! {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EdgeMode {
+    Wrap,
+    Clamp,
+}
+}
+
+// the Kaiser window's shape parameter, as common texture tools use with a width of 3
+const KAISER_ALPHA: f64 = 4.0;
+
+impl Filter {
+    // the kernel is zero beyond this distance, in texels of the larger image
+    fn radius(self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Triangle => 1.0,
+            Filter::Kaiser | Filter::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(self, x: f64) -> f64 {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.0;
+        }
+        match self {
+            // a texel exactly on the edge of the box is shared by the two outputs
+            Filter::Box if x < 0.5 => 1.0,
+            Filter::Box => 0.5,
+            Filter::Triangle => 1.0 - x,
+            Filter::Kaiser => {
+                let r = x / self.radius();
+                sinc(x) * bessel_i0(KAISER_ALPHA * (1.0 - r * r).sqrt()) / bessel_i0(KAISER_ALPHA)
+            }
+            Filter::Lanczos => sinc(x) * sinc(x / self.radius()),
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// the zeroth-order modified Bessel function of the first kind, by its power series
+fn bessel_i0(x: f64) -> f64 {
+    let (mut sum, mut term) = (1.0, 1.0);
+    for k in 1..32 {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+// Resamples one image, with depth slices one after another, to new dimensions: along x,
+// then y, then z, skipping axes whose extent doesn't change.
+pub fn resample(
+    texels: &[[f64; 4]],
+    from: Dimensions,
+    to: Dimensions,
+    filter: Filter,
+    edges: [EdgeMode; 3],
+) -> Vec<[f64; 4]> {
+    let mut extent = [from.width(), from.height(), from.depth()];
+    let target = [to.width(), to.height(), to.depth()];
+    let mut texels = texels.to_vec();
+    for axis in 0..3 {
+        if extent[axis] == target[axis] {
+            continue;
+        }
+        let taps = axis_taps(extent[axis], target[axis], filter, edges[axis]);
+        // texels are laid out as outer runs of the axis, each of stride-interleaved rows
+        let stride = extent[..axis].iter().product::<u32>() as usize;
+        let outer = extent[axis + 1..].iter().product::<u32>() as usize;
+        let (from_len, to_len) = (extent[axis] as usize, target[axis] as usize);
+        let mut resampled = vec![[0.0; 4]; stride * to_len * outer];
+        for o in 0..outer {
+            for (i, taps) in taps.iter().enumerate() {
+                for s in 0..stride {
+                    let mut sum = [0.0; 4];
+                    for &(j, weight) in taps {
+                        let texel = texels[(o * from_len + j) * stride + s];
+                        for (sum, value) in sum.iter_mut().zip(texel.iter()) {
+                            *sum += value * weight;
+                        }
+                    }
+                    resampled[(o * to_len + i) * stride + s] = sum;
+                }
+            }
+        }
+        texels = resampled;
+        extent[axis] = target[axis];
+    }
+    texels
+}
+
+// For each output texel along one axis, the source texels and normalised weights that
+// make it up.
+fn axis_taps(from: u32, to: u32, filter: Filter, edge: EdgeMode) -> Vec<Vec<(usize, f64)>> {
+    let scale = f64::from(from) / f64::from(to);
+    let stretch = scale.max(1.0);
+    let support = filter.radius() * stretch;
+    (0..to)
+        .map(|i| {
+            let centre = (f64::from(i) + 0.5) * scale - 0.5;
+            let first = (centre - support).floor() as i64;
+            let last = (centre + support).ceil() as i64;
+            let mut taps = vec![];
+            for j in first..=last {
+                let weight = filter.weight((j as f64 - centre) / stretch);
+                if weight == 0.0 {
+                    continue;
+                }
+                let source = match edge {
+                    EdgeMode::Wrap => j.rem_euclid(i64::from(from)),
+                    EdgeMode::Clamp => j.clamp(0, i64::from(from) - 1),
+                };
+                taps.push((source as usize, weight));
+            }
+            let total: f64 = taps.iter().map(|tap| tap.1).sum();
+            for tap in &mut taps {
+                tap.1 /= total;
+            }
+            taps
+        })
+        .collect()
+}
+
+// Integer, depth and stencil values have no meaningful values in between, so they can't
+// be filtered.
+pub fn check_filterable(format: &PixelFormat) -> RuxResult<()> {
+    if matches!(format.comp_content, UINT | SINT | SPECIAL) {
+        bail!(format!(
+            "Can't filter {}; integer, depth and stencil values have nothing in between.",
+            format
+        ));
+    }
+    Ok(())
+}
+
+// All images of a level, depth slices included, in the format's own range.
+pub fn decode_level(
+    format: &PixelFormat,
+    blob: &[u8],
+    dim: Dimensions,
+    image_count: u32,
+) -> RuxResult<Vec<[f64; 4]>> {
+    let slices = image_count * dim.depth();
+    let texels = if format.is_compressed() {
+        decompress_texels(format, blob, dim, slices)?
+            .iter()
+            .map(|t| {
+                [
+                    f64::from(t[0]),
+                    f64::from(t[1]),
+                    f64::from(t[2]),
+                    f64::from(t[3]),
+                ]
+            })
+            .collect()
+    } else {
+        decode_f64(format, blob)?
+    };
+    if texels.len() != (dim.width() * dim.height() * slices) as usize {
+        bail!(format!(
+            "Level of {} texels doesn't hold {} images of {}x{}x{}.",
+            texels.len(),
+            image_count,
+            dim.width(),
+            dim.height(),
+            dim.depth()
+        ));
+    }
+    Ok(texels)
+}
+
+// Encodes all images of a level from texels in the format's own range, compressing with
+// the given options if the format is compressed.
+pub fn encode_level(
+    format: &PixelFormat,
+    texels: &[[f64; 4]],
+    dim: Dimensions,
+    image_count: u32,
+    options: &CompressOptions,
+) -> RuxResult<ImageBlob> {
+    if !format.is_compressed() {
+        return encode_f64(format, texels);
+    }
+    let snorm_normals = format.comp_content == SNORM && options.normal_map;
+    let texels: Vec<Texel> = texels
+        .iter()
+        .map(|t| {
+            let mut texel = [t[0] as f32, t[1] as f32, t[2] as f32, t[3] as f32];
+            if snorm_normals {
+                unit_colour(&mut texel);
+            }
+            texel
+        })
+        .collect();
+    compress_texels(format, options, &texels, dim, image_count * dim.depth())
+}
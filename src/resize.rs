@@ -0,0 +1,179 @@
+use crate::compress::CompressOptions;
+use crate::mipmap::{full_level_count, generate_mips, MipOptions};
+use crate::pixel::srgb;
+use crate::resample::{check_filterable, decode_level, encode_level, resample, EdgeMode, Filter};
+use crate::{Dimensions, RuxResult, Texture};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ResizeOptions {
+    // filter, edges, linear and compress are as in MipOptions, and carry over to the
+    // mips of a resized texture
+    pub filter: Filter,
+    pub edges: [EdgeMode; 3],
+    pub linear: bool,
+    // each extent goes to the nearest power of two
+    pub power_of_two: bool,
+    // width and height both become the larger of the two, as PVRTC needs
+    pub square: bool,
+    // width and height grow to whole blocks of a compressed format, with the last row and
+    // column repeated rather than the image stretched
+    pub pad_to_blocks: bool,
+    pub compress: CompressOptions,
+}
+
+impl Default for ResizeOptions {
+    fn default() -> ResizeOptions {
+        ResizeOptions {
+            filter: Filter::Lanczos,
+            edges: [EdgeMode::Clamp; 3],
+            linear: true,
+            power_of_two: false,
+            square: false,
+            pad_to_blocks: false,
+            compress: CompressOptions::default(),
+        }
+    }
+}
+
+// Shrinks dimensions to fit within max_size on every axis, keeping their proportions.
+pub fn fit(dim: Dimensions, max_size: u32) -> Dimensions {
+    let largest = dim.width().max(dim.height()).max(dim.depth());
+    if largest <= max_size {
+        return dim;
+    }
+    let factor = f64::from(max_size) / f64::from(largest);
+    let scale = |extent: u32| ((f64::from(extent) * factor).round() as u32).max(1);
+    keep_unused(
+        dim,
+        scale(dim.width()),
+        scale(dim.height()),
+        scale(dim.depth()),
+    )
+}
+
+// The dimensions a resize to dim actually produces, once rounded and padded as asked.
+pub fn target_dimensions(
+    texture: &Texture,
+    dim: Dimensions,
+    options: &ResizeOptions,
+) -> Dimensions {
+    let (mut width, mut height, mut depth) = (dim.width(), dim.height(), dim.depth());
+    if options.square {
+        width = width.max(height);
+        height = width;
+    }
+    if options.power_of_two {
+        width = nearest_power_of_two(width);
+        height = nearest_power_of_two(height);
+        depth = nearest_power_of_two(depth);
+    }
+    if options.pad_to_blocks {
+        let (block_w, block_h) = texture.format.block_extent();
+        width = width.div_ceil(block_w) * block_w;
+        height = height.div_ceil(block_h) * block_h;
+    }
+    keep_unused(texture.pixel_dim, width, height, depth)
+}
+
+// Resamples the base level to new dimensions, rounded and padded as the options ask. A
+// texture with mips gets as many again, filtered down from the new base.
+pub fn resize(texture: &Texture, dim: Dimensions, options: &ResizeOptions) -> RuxResult<Texture> {
+    let format = &texture.format;
+    check_filterable(format)?;
+    let base = match texture.mip_blobs.first() {
+        Some(base) => base,
+        None => bail!("Texture has no base level to resize."),
+    };
+    let padded = target_dimensions(texture, dim, options);
+    let unpadded = target_dimensions(
+        texture,
+        dim,
+        &ResizeOptions {
+            pad_to_blocks: false,
+            ..*options
+        },
+    );
+    let mut edges = options.edges;
+    if texture.face_count == 6 {
+        edges[0] = EdgeMode::Clamp;
+        edges[1] = EdgeMode::Clamp;
+    }
+
+    let from = texture.mip_dim(0);
+    let image_count = texture.image_count();
+    let mut texels = decode_level(format, base, from, image_count)?;
+    let linear = options.linear && format.is_srgb();
+    if linear {
+        srgb::transfer(&mut texels, true, false);
+    }
+    let volume = (from.width() * from.height() * from.depth()) as usize;
+    let mut texels: Vec<[f64; 4]> = texels
+        .chunks(volume)
+        .flat_map(|image| {
+            let resized = resample(image, from, unpadded, options.filter, edges);
+            pad(&resized, unpadded, padded)
+        })
+        .collect();
+    if linear {
+        srgb::transfer(&mut texels, false, true);
+    }
+    let blob = encode_level(format, &texels, padded, image_count, &options.compress)?;
+
+    let resized = Texture {
+        pixel_dim: padded,
+        mip_blobs: vec![blob],
+        ..*texture
+    };
+    if texture.mip_blobs.len() == 1 {
+        return Ok(resized);
+    }
+    let levels = texture.mip_blobs.len().min(full_level_count(padded));
+    let mip_options = MipOptions {
+        filter: options.filter,
+        edges,
+        levels: Some(levels),
+        linear: options.linear,
+        compress: options.compress,
+        ..MipOptions::default()
+    };
+    generate_mips(&resized, &mip_options)
+}
+
+// Grows one image to the padded dimensions by repeating its last column, row and slice.
+fn pad(texels: &[[f64; 4]], dim: Dimensions, padded: Dimensions) -> Vec<[f64; 4]> {
+    if dim == padded {
+        return texels.to_vec();
+    }
+    let (width, height) = (dim.width(), dim.height());
+    let mut out = Vec::with_capacity((padded.width() * padded.height() * padded.depth()) as usize);
+    for z in 0..padded.depth() {
+        let slice = z.min(dim.depth() - 1) * width * height;
+        for y in 0..padded.height() {
+            let row = slice + y.min(height - 1) * width;
+            for x in 0..padded.width() {
+                out.push(texels[(row + x.min(width - 1)) as usize]);
+            }
+        }
+    }
+    out
+}
+
+// ties go to the smaller power, so that rounding never upscales more than it must
+fn nearest_power_of_two(extent: u32) -> u32 {
+    let lower = 1 << (31 - extent.max(1).leading_zeros());
+    if extent - lower <= lower * 2 - extent {
+        lower
+    } else {
+        lower * 2
+    }
+}
+
+// containers use 0 for unused dimensions, which resizing leaves unused
+fn keep_unused(dim: Dimensions, width: u32, height: u32, depth: u32) -> Dimensions {
+    let keep = |unused: bool, extent: u32| if unused && extent == 1 { 0 } else { extent };
+    Dimensions::new(
+        keep(dim.0 == 0, width),
+        keep(dim.1 == 0, height),
+        keep(dim.2 == 0, depth),
+    )
+}
@@ -0,0 +1,169 @@
+use crate::compress::CompressOptions;
+use crate::pixel::CompContent::SNORM;
+use crate::pixel::{srgb, PixelFormat};
+use crate::resample::{decode_level, encode_level};
+use crate::{RuxResult, Texture};
+
+// Where one output channel's values come from.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ChannelSource {
+    // a channel of one of the inputs, by index, optionally inverted
+    Channel {
+        input: usize,
+        channel: usize,
+        invert: bool,
+    },
+    Constant(f64),
+}
+
+// Parses a spec like "R=ao.r,G=1-rough.r,B=metal.r,A=1", with the inputs known by the
+// given names. A channel without an input name is one of the first input's, so "R=b,B=r"
+// swaps red and blue. Colour channels left out are 0, and alpha 1.
+pub fn parse_spec(spec: &str, names: &[&str]) -> RuxResult<[ChannelSource; 4]> {
+    let mut sources = [
+        ChannelSource::Constant(0.0),
+        ChannelSource::Constant(0.0),
+        ChannelSource::Constant(0.0),
+        ChannelSource::Constant(1.0),
+    ];
+    for assignment in spec.split(',') {
+        let (target, source) = match assignment.find('=') {
+            Some(equals) => (&assignment[..equals], &assignment[equals + 1..]),
+            None => bail!(format!("Expected CHANNEL=SOURCE, found {}.", assignment)),
+        };
+        let target = match channel_index(target.trim()) {
+            Some(target) => target,
+            None => bail!(format!("Unknown output channel {}.", target)),
+        };
+        sources[target] = parse_source(source.trim(), names)?;
+    }
+    Ok(sources)
+}
+
+fn parse_source(source: &str, names: &[&str]) -> RuxResult<ChannelSource> {
+    if let Ok(value) = source.parse::<f64>() {
+        return Ok(ChannelSource::Constant(value));
+    }
+    let (invert, source) = if let Some(rest) = source.strip_prefix("1-") {
+        (true, rest.trim())
+    } else {
+        (false, source)
+    };
+    let (input, channel) = match source.rfind('.') {
+        Some(dot) => {
+            let name = &source[..dot];
+            match names.iter().position(|n| *n == name) {
+                Some(input) => (input, &source[dot + 1..]),
+                None => bail!(format!("No input named {}.", name)),
+            }
+        }
+        None => (0, source),
+    };
+    match channel_index(channel) {
+        Some(channel) => Ok(ChannelSource::Channel {
+            input,
+            channel,
+            invert,
+        }),
+        None => bail!(format!("Unknown channel {} in {}.", channel, source)),
+    }
+}
+
+fn channel_index(name: &str) -> Option<usize> {
+    match name.to_ascii_lowercase().as_str() {
+        "r" | "x" => Some(0),
+        "g" | "y" => Some(1),
+        "b" | "z" => Some(2),
+        "a" | "w" => Some(3),
+        _ => None,
+    }
+}
+
+// Builds a texture whose every channel comes from a channel of one of the inputs, or is a
+// constant. The inputs must have the same dimensions, array size and faces; the result
+// has as many mip levels as all of them do. Colour moves between the inputs' and the
+// target's colour spaces. Inverted values are one minus the normalised value, or negated
+// for SNORM inputs, whose values run from -1 to 1.
+pub fn pack(
+    inputs: &[&Texture],
+    sources: &[ChannelSource; 4],
+    target: &PixelFormat,
+    options: &CompressOptions,
+) -> RuxResult<Texture> {
+    let first = match inputs.first() {
+        Some(first) => first,
+        None => bail!("Packing needs at least one input."),
+    };
+    for (i, input) in inputs.iter().enumerate().skip(1) {
+        if input.pixel_dim != first.pixel_dim
+            || input.array_size != first.array_size
+            || input.face_count != first.face_count
+        {
+            bail!(format!(
+                "Input {} is {:?} with {} layers and {} faces, but input 0 is {:?} with {} layers and {} faces.",
+                i,
+                input.pixel_dim,
+                input.array_size,
+                input.face_count,
+                first.pixel_dim,
+                first.array_size,
+                first.face_count
+            ));
+        }
+    }
+    for source in sources {
+        if let ChannelSource::Channel { input, .. } = source {
+            if *input >= inputs.len() {
+                bail!(format!("No input {} to take a channel from.", input));
+            }
+        }
+    }
+
+    let levels = inputs.iter().map(|t| t.mip_blobs.len()).min().unwrap_or(0);
+    let mut mip_blobs = vec![];
+    for level in 0..levels {
+        let dim = first.mip_dim(level);
+        let mut decoded = vec![];
+        for input in inputs {
+            let blob = &input.mip_blobs[level];
+            let mut texels = decode_level(&input.format, blob, dim, input.image_count())?;
+            srgb::transfer(&mut texels, input.format.is_srgb(), target.is_srgb());
+            decoded.push(texels);
+        }
+        let texels: Vec<[f64; 4]> = (0..decoded[0].len())
+            .map(|i| {
+                let mut texel = [0.0; 4];
+                for (value, source) in texel.iter_mut().zip(sources.iter()) {
+                    *value = match *source {
+                        ChannelSource::Constant(value) => value,
+                        ChannelSource::Channel {
+                            input,
+                            channel,
+                            invert,
+                        } => {
+                            let value = decoded[input][i][channel];
+                            match (invert, inputs[input].format.comp_content) {
+                                (false, _) => value,
+                                (true, SNORM) => -value,
+                                (true, _) => 1.0 - value,
+                            }
+                        }
+                    };
+                }
+                texel
+            })
+            .collect();
+        match encode_level(target, &texels, dim, first.image_count(), options) {
+            Ok(blob) => mip_blobs.push(blob),
+            Err(e) => bail!(format!("Mip level {}: {}", level, e)),
+        }
+    }
+
+    Ok(Texture {
+        format: *target,
+        pixel_dim: first.pixel_dim,
+        array_size: first.array_size,
+        face_count: first.face_count,
+        mip_blobs,
+    })
+}